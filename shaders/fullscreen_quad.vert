@@ -0,0 +1,10 @@
+#version 450
+// glslc shaders/fullscreen_quad.vert -o assets/shaders/fullscreen_quad.vert.spv
+// draw 4 vertices as a triangle strip, no vertex buffer. uv (0, 0) is the top left of the screen
+
+layout(location = 0) out vec2 uv;
+
+void main() {
+  uv = vec2(float(gl_VertexIndex & 1), float(gl_VertexIndex >> 1));
+  gl_Position = vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+}
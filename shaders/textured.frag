@@ -0,0 +1,12 @@
+#version 450
+// glslc shaders/textured.frag -o assets/shaders/textured.frag.spv
+
+layout(location = 0) in vec2 uv;
+layout(location = 0) out vec4 color;
+
+layout(set = 0, binding = 0) uniform texture2D image;
+layout(set = 0, binding = 1) uniform sampler image_sampler;
+
+void main() {
+  color = texture(sampler2D(image, image_sampler), uv);
+}
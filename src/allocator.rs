@@ -0,0 +1,406 @@
+use proc_macros::{Getters};
+use crate::{constants::MemoryIntent, error::{GfxError, GfxResult}, memory};
+
+/// what a sub-allocation holds. buffers and linear images can't share a bufferImageGranularity page with optimal images
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResourceKind {
+  Buffer,
+  LinearImage,
+  OptimalImage,
+}
+
+impl ResourceKind {
+  pub fn from_tiling(tiling: ash::vk::ImageTiling) -> Self {
+    match tiling {
+      ash::vk::ImageTiling::LINEAR => ResourceKind::LinearImage,
+      _ => ResourceKind::OptimalImage,
+    }
+  }
+
+  fn conflicts_with(&self, other: &ResourceKind) -> bool {
+    (*self == ResourceKind::OptimalImage) != (*other == ResourceKind::OptimalImage)
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Suballocation {
+  pub offset: u64,
+  pub size: u64,
+  pub kind: ResourceKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// which ranges of one block are taken, sorted by offset. knows nothing about vulkan objects
+pub struct BlockLayout {
+  pub size: u64,
+  pub suballocations: Vec<Suballocation>,
+}
+
+impl BlockLayout {
+  pub fn new(size: u64) -> Self {
+    BlockLayout { size, suballocations: vec![] }
+  }
+
+  /// first fit. alignment and granularity must be powers of two. returns the offset, or None if nothing fits
+  pub fn allocate(&mut self, size: u64, alignment: u64, granularity: u64, kind: ResourceKind) -> Option<u64> {
+    assert!(size > 0, "can't allocate zero bytes");
+    for i in 0..=self.suballocations.len() {
+      let previous = if i == 0 { None } else { self.suballocations.get(i - 1) };
+      let next = self.suballocations.get(i);
+      let gap_start = previous.map(|previous| previous.offset + previous.size).unwrap_or(0);
+      let gap_end = next.map(|next| next.offset).unwrap_or(self.size);
+
+      let mut offset = align_up(gap_start, alignment);
+      // step onto a new page rather than share one with a conflicting neighbour
+      if let Some(previous) = previous && previous.kind.conflicts_with(&kind) && get_if_on_same_page(previous.offset + previous.size - 1, offset, granularity) {
+        offset = align_up(offset, granularity);
+      }
+      if offset + size > gap_end { continue; }
+      if let Some(next) = next && next.kind.conflicts_with(&kind) && get_if_on_same_page(offset + size - 1, next.offset, granularity) { continue; }
+
+      self.suballocations.insert(i, Suballocation { offset, size, kind });
+      return Some(offset);
+    }
+    None
+  }
+
+  /// returns false if nothing starts at offset
+  pub fn free(&mut self, offset: u64) -> bool {
+    match self.suballocations.iter().position(|suballocation| suballocation.offset == offset) {
+      Some(i) => { self.suballocations.remove(i); true },
+      None => false,
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.suballocations.is_empty()
+  }
+
+  /// (offset, size) of every gap, including ones too small to be useful
+  pub fn get_free_regions(&self) -> Vec<(u64, u64)> {
+    let mut regions = vec![];
+    let mut cursor = 0;
+    for suballocation in self.suballocations.iter() {
+      if suballocation.offset > cursor { regions.push((cursor, suballocation.offset - cursor)); }
+      cursor = suballocation.offset + suballocation.size;
+    }
+    if self.size > cursor { regions.push((cursor, self.size - cursor)); }
+    regions
+  }
+}
+
+#[derive(Getters, Clone, Debug, Default, PartialEq)]
+pub struct AllocatorStats {
+  pub block_count: u64,
+  pub allocation_count: u64,
+  /// total size of all blocks, what the driver sees
+  pub reserved_bytes: u64,
+  pub used_bytes: u64,
+  /// includes alignment padding
+  pub free_bytes: u64,
+  pub largest_free_region: u64,
+  /// 0 when all free memory is in one region, approaching 1 as it splinters. 1 - largest_free_region / free_bytes
+  pub fragmentation: f64,
+}
+
+pub fn get_stats_for_layouts<'a>(layouts: impl Iterator<Item = &'a BlockLayout>) -> AllocatorStats {
+  let mut stats = AllocatorStats::default();
+  for layout in layouts {
+    stats.block_count += 1;
+    stats.allocation_count += layout.suballocations.len() as u64;
+    stats.reserved_bytes += layout.size;
+    stats.used_bytes += layout.suballocations.iter().map(|suballocation| suballocation.size).sum::<u64>();
+    for (_, size) in layout.get_free_regions() {
+      stats.free_bytes += size;
+      stats.largest_free_region = stats.largest_free_region.max(size);
+    }
+  }
+  stats.fragmentation = match stats.free_bytes {
+    0 => 0.0,
+    free_bytes => 1.0 - stats.largest_free_region as f64 / free_bytes as f64,
+  };
+  stats
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+  (value + alignment - 1) & !(alignment - 1)
+}
+
+fn align_down(value: u64, alignment: u64) -> u64 {
+  value & !(alignment - 1)
+}
+
+fn get_if_on_same_page(end_byte: u64, start_byte: u64, page_size: u64) -> bool {
+  align_down(end_byte, page_size) == align_down(start_byte, page_size)
+}
+
+/// flush and invalidate ranges must be multiples of nonCoherentAtomSize, or reach the end of the memory
+pub fn get_atom_aligned_range(offset: u64, size: u64, atom_size: u64, memory_size: u64) -> (u64, u64) {
+  let start = align_down(offset, atom_size);
+  let end = align_up(offset + size, atom_size);
+  match end >= memory_size {
+    true => (start, ash::vk::WHOLE_SIZE),
+    false => (start, end - start),
+  }
+}
+
+#[derive(Getters, Clone, Copy, Debug)]
+/// a range of a block. free it through the allocator that made it, or wrap it in a resources::Allocation to have that done on drop
+pub struct RawAllocation {
+  pub memory: ash::vk::DeviceMemory,
+  pub offset: u64,
+  pub size: u64,
+  pub memory_type_index: u32,
+  pub block_id: u64,
+  /// already offset to the start of the allocation. None unless the memory is HOST_VISIBLE
+  pub mapped_ptr: Option<std::ptr::NonNull<u8>>,
+}
+
+impl RawAllocation {
+  pub fn get_mapped_ptr(&self) -> Option<*mut std::ffi::c_void> {
+    self.mapped_ptr.map(|ptr| ptr.as_ptr() as *mut std::ffi::c_void)
+  }
+}
+
+/// one vkAllocateMemory. host visible blocks stay mapped for their whole life, since memory can only be mapped once
+pub struct MemoryBlock {
+  pub id: u64,
+  pub memory: ash::vk::DeviceMemory,
+  pub memory_type_index: u32,
+  pub layout: BlockLayout,
+  pub mapped_ptr: Option<std::ptr::NonNull<u8>>,
+}
+
+/// shared so owning wrappers can give their memory back when dropped. not Send, like the mapped pointers it hands out
+pub type SharedAllocator = std::rc::Rc<std::cell::RefCell<Allocator>>;
+
+#[derive(Getters)]
+/// carves buffers and images out of large blocks, so we stay far away from maxMemoryAllocationCount.
+/// requests bigger than a block get a block of their own
+pub struct Allocator {
+  #[Getters_Skip]
+  pub instance: ash::Instance,
+  #[Getters_Skip]
+  pub device: ash::Device,
+  pub physical_device: ash::vk::PhysicalDevice,
+  pub block_size: u64,
+  pub buffer_image_granularity: u64,
+  pub non_coherent_atom_size: u64,
+  pub blocks: Vec<MemoryBlock>,
+  pub next_block_id: u64,
+}
+
+impl Allocator {
+  pub fn new(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice, device: &ash::Device, block_size: u64) -> Self {
+    let properties = unsafe { instance.get_physical_device_properties(*physical_device) };
+    Allocator {
+      instance: instance.clone(),
+      device: device.clone(),
+      physical_device: *physical_device,
+      block_size,
+      buffer_image_granularity: properties.limits.buffer_image_granularity.max(1),
+      non_coherent_atom_size: properties.limits.non_coherent_atom_size.max(1),
+      blocks: vec![],
+      next_block_id: 0,
+    }
+  }
+
+  /// the memory type comes from the intent, see memory::get_memory_flags_from_intent
+  pub fn allocate(&mut self, requirements: &ash::vk::MemoryRequirements, intent: MemoryIntent, kind: ResourceKind) -> GfxResult<RawAllocation> {
+    let memory_type_index =
+      memory::get_memory_type_index_for_intent(&self.instance, &self.physical_device, intent, requirements.memory_type_bits)
+      .ok_or(GfxError::NoSuitableMemoryType)?;
+    let alignment = requirements.alignment.max(1);
+    let granularity = self.buffer_image_granularity;
+
+    // an existing block, then a new one
+    let existing = self.blocks.iter_mut()
+      .filter(|block| block.memory_type_index == memory_type_index)
+      .find_map(|block| block.layout.allocate(requirements.size, alignment, granularity, kind).map(|offset| (block.id, offset)));
+    let (block_id, offset) = match existing {
+      Some(found) => found,
+      None => {
+        let block = self.create_block(memory_type_index, self.block_size.max(requirements.size))?;
+        let block_id = block.id;
+        self.blocks.push(block);
+        let block = self.blocks.last_mut().expect("block was just pushed");
+        let offset = block.layout.allocate(requirements.size, alignment, granularity, kind).expect("a new block is big enough");
+        (block_id, offset)
+      }
+    };
+
+    let block = self.get_block(block_id);
+    Ok(RawAllocation {
+      memory: block.memory,
+      offset,
+      size: requirements.size,
+      memory_type_index,
+      block_id,
+      mapped_ptr: block.mapped_ptr.map(|ptr| unsafe { ptr.add(offset as usize) }),
+    })
+  }
+
+  /// allocates and binds
+  pub fn allocate_for_image(&mut self, image: &ash::vk::Image, intent: MemoryIntent, tiling: ash::vk::ImageTiling) -> GfxResult<RawAllocation> {
+    let requirements = unsafe { self.device.get_image_memory_requirements(*image) };
+    let allocation = self.allocate(&requirements, intent, ResourceKind::from_tiling(tiling))?;
+    if let Err(error) = unsafe { self.device.bind_image_memory(*image, allocation.memory, allocation.offset) } {
+      self.free(&allocation);
+      return Err(error.into());
+    }
+    Ok(allocation)
+  }
+
+  /// allocates and binds
+  pub fn allocate_for_buffer(&mut self, buffer: &ash::vk::Buffer, intent: MemoryIntent) -> GfxResult<RawAllocation> {
+    let requirements = unsafe { self.device.get_buffer_memory_requirements(*buffer) };
+    let allocation = self.allocate(&requirements, intent, ResourceKind::Buffer)?;
+    if let Err(error) = unsafe { self.device.bind_buffer_memory(*buffer, allocation.memory, allocation.offset) } {
+      self.free(&allocation);
+      return Err(error.into());
+    }
+    Ok(allocation)
+  }
+
+  /// blocks are given back to the driver as soon as they are empty
+  pub fn free(&mut self, allocation: &RawAllocation) -> () {
+    let Some(i) = self.blocks.iter().position(|block| block.id == allocation.block_id) else {
+      panic!("allocation from block {} freed twice, or by the wrong allocator", allocation.block_id);
+    };
+    let freed = self.blocks[i].layout.free(allocation.offset);
+    assert!(freed, "allocation at offset {} of block {} freed twice", allocation.offset, allocation.block_id);
+    if self.blocks[i].layout.is_empty() {
+      let block = self.blocks.remove(i);
+      self.destroy_block(block);
+    }
+  }
+
+  /// makes host writes visible to the device. a no-op cost wise on HOST_COHERENT memory, but still valid
+  pub fn flush(&self, allocation: &RawAllocation) -> GfxResult<()> {
+    let range = self.get_mapped_memory_range(allocation);
+    unsafe { self.device.flush_mapped_memory_ranges(&[range])?; }
+    Ok(())
+  }
+
+  /// makes device writes visible to the host
+  pub fn invalidate(&self, allocation: &RawAllocation) -> GfxResult<()> {
+    let range = self.get_mapped_memory_range(allocation);
+    unsafe { self.device.invalidate_mapped_memory_ranges(&[range])?; }
+    Ok(())
+  }
+
+  pub fn get_stats(&self) -> AllocatorStats {
+    get_stats_for_layouts(self.blocks.iter().map(|block| &block.layout))
+  }
+
+  /// call before destroying the device. empty blocks are already gone, so anything left is an allocation that was
+  /// never given back, e.g. a wrapper that was forgotten. its memory may still be bound, so it's reported and leaked
+  pub fn destroy(&mut self) -> () {
+    for block in std::mem::take(&mut self.blocks) {
+      log::warn!(target: "gfx", "leaking memory block {} with {} allocations still in use", block.id, block.layout.suballocations.len());
+    }
+  }
+
+  fn get_mapped_memory_range(&self, allocation: &RawAllocation) -> ash::vk::MappedMemoryRange<'static> {
+    let block = self.get_block(allocation.block_id);
+    let (offset, size) = get_atom_aligned_range(allocation.offset, allocation.size, self.non_coherent_atom_size, block.layout.size);
+    ash::vk::MappedMemoryRange::default()
+      .memory(allocation.memory)
+      .offset(offset)
+      .size(size)
+  }
+
+  fn get_block(&self, block_id: u64) -> &MemoryBlock {
+    self.blocks.iter().find(|block| block.id == block_id).expect("allocation refers to a block that was freed")
+  }
+
+  fn create_block(&mut self, memory_type_index: u32, size: u64) -> GfxResult<MemoryBlock> {
+    let info = ash::vk::MemoryAllocateInfo::default()
+      .allocation_size(size)
+      .memory_type_index(memory_type_index);
+    let memory = unsafe { self.device.allocate_memory(&info, None)? };
+
+    let flags = memory::get_memory_type_flags_from_index(&self.instance, &self.physical_device, memory_type_index);
+    let mapped_ptr = match flags.contains(ash::vk::MemoryPropertyFlags::HOST_VISIBLE) {
+      false => None,
+      true => {
+        let pointer = unsafe { self.device.map_memory(memory, 0, ash::vk::WHOLE_SIZE, ash::vk::MemoryMapFlags::empty()) };
+        match pointer {
+          Ok(pointer) => std::ptr::NonNull::new(pointer as *mut u8),
+          Err(error) => {
+            unsafe { self.device.free_memory(memory, None); }
+            return Err(error.into());
+          }
+        }
+      }
+    };
+
+    let id = self.next_block_id;
+    self.next_block_id += 1;
+    Ok(MemoryBlock { id, memory, memory_type_index, layout: BlockLayout::new(size), mapped_ptr })
+  }
+
+  fn destroy_block(&self, block: MemoryBlock) -> () {
+    if block.mapped_ptr.is_some() { unsafe { self.device.unmap_memory(block.memory); } }
+    unsafe { self.device.free_memory(block.memory, None); }
+  }
+}
+
+#[test]
+fn test_block_layout_honours_alignment_and_reuses_freed_space() {
+  let mut layout = BlockLayout::new(1024);
+  assert_eq!(layout.allocate(10, 1, 1, ResourceKind::Buffer), Some(0));
+  assert_eq!(layout.allocate(100, 64, 1, ResourceKind::Buffer), Some(64));
+  assert_eq!(layout.allocate(10, 256, 1, ResourceKind::Buffer), Some(256));
+  assert_eq!(layout.get_free_regions(), vec![(10, 54), (164, 92), (266, 758)]);
+
+  // first fit goes back into the hole
+  assert!(layout.free(64));
+  assert!(!layout.free(64));
+  assert_eq!(layout.allocate(200, 16, 1, ResourceKind::Buffer), Some(16));
+  assert_eq!(layout.allocate(2048, 1, 1, ResourceKind::Buffer), None);
+}
+
+#[test]
+fn test_block_layout_honours_buffer_image_granularity() {
+  let granularity = 1024;
+  let mut layout = BlockLayout::new(8192);
+  assert_eq!(layout.allocate(100, 4, granularity, ResourceKind::Buffer), Some(0));
+  // an optimal image can't start on the buffer's page
+  assert_eq!(layout.allocate(100, 4, granularity, ResourceKind::OptimalImage), Some(1024));
+  // a linear image or buffer can share the buffer's pages, as long as it stays off the image's page
+  assert_eq!(layout.allocate(100, 4, granularity, ResourceKind::LinearImage), Some(100));
+  assert_eq!(layout.allocate(100, 4, granularity, ResourceKind::Buffer), Some(200));
+  // too big for the gap before the image, and can't start on the image's last page
+  assert_eq!(layout.allocate(900, 4, granularity, ResourceKind::Buffer), Some(2048));
+
+  // a buffer that would end on the image's page doesn't fit in the gap before it
+  let mut layout = BlockLayout::new(4096);
+  layout.suballocations.push(Suballocation { offset: 1500, size: 100, kind: ResourceKind::OptimalImage });
+  assert_eq!(layout.allocate(1100, 4, granularity, ResourceKind::Buffer), Some(2048));
+  assert_eq!(layout.allocate(1000, 4, granularity, ResourceKind::Buffer), Some(0));
+}
+
+#[test]
+fn test_fragmentation_stats() {
+  let mut layout = BlockLayout::new(1000);
+  let offsets = (0..10).map(|_| layout.allocate(100, 1, 1, ResourceKind::Buffer).unwrap()).collect::<Vec<_>>();
+  let stats = get_stats_for_layouts([&layout].into_iter());
+  assert_eq!(stats.free_bytes, 0);
+  assert_eq!(stats.fragmentation, 0.0);
+
+  // every other allocation freed, 500 bytes free in 100 byte pieces
+  for offset in offsets.iter().step_by(2) { layout.free(*offset); }
+  let stats = get_stats_for_layouts([&layout].into_iter());
+  assert_eq!(stats.allocation_count, 5);
+  assert_eq!(stats.used_bytes, 500);
+  assert_eq!(stats.free_bytes, 500);
+  assert_eq!(stats.largest_free_region, 100);
+  assert!((stats.fragmentation - 0.8).abs() < 1e-9);
+}
+
+#[test]
+fn test_atom_aligned_range() {
+  assert_eq!(get_atom_aligned_range(70, 100, 64, 4096), (64, 128));
+  assert_eq!(get_atom_aligned_range(4000, 96, 64, 4096), (3968, ash::vk::WHOLE_SIZE));
+}
@@ -0,0 +1,261 @@
+use crate::{mipmaps, upload};
+
+/// the device features that gate each family of block compressed formats
+#[derive(Clone, Copy, Debug, PartialEq, strum_macros::EnumIter)]
+pub enum TextureCompression {
+  /// textureCompressionBC. desktop GPUs
+  Bc,
+  /// textureCompressionETC2. most mobile GPUs
+  Etc2,
+  /// textureCompressionASTC_LDR. newer mobile GPUs
+  AstcLdr,
+}
+
+impl TextureCompression {
+  pub fn get_if_supported(&self, features: &ash::vk::PhysicalDeviceFeatures) -> bool {
+    let supported = match self {
+      TextureCompression::Bc => features.texture_compression_bc,
+      TextureCompression::Etc2 => features.texture_compression_etc2,
+      TextureCompression::AstcLdr => features.texture_compression_astc_ldr,
+    };
+    supported == ash::vk::TRUE
+  }
+
+  pub fn enable(&self, features: ash::vk::PhysicalDeviceFeatures) -> ash::vk::PhysicalDeviceFeatures {
+    match self {
+      TextureCompression::Bc => features.texture_compression_bc(true),
+      TextureCompression::Etc2 => features.texture_compression_etc2(true),
+      TextureCompression::AstcLdr => features.texture_compression_astc_ldr(true),
+    }
+  }
+
+  /// as named in VkPhysicalDeviceFeatures
+  pub fn get_feature_name(&self) -> &'static str {
+    match self {
+      TextureCompression::Bc => "textureCompressionBC",
+      TextureCompression::Etc2 => "textureCompressionETC2",
+      TextureCompression::AstcLdr => "textureCompressionASTC_LDR",
+    }
+  }
+}
+
+/// create_device enables every one of these the device has
+pub fn get_supported_texture_compression(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice) -> Vec<TextureCompression> {
+  use strum::IntoEnumIterator;
+  let features = unsafe { instance.get_physical_device_features(*physical_device) };
+  TextureCompression::iter().filter(|compression| compression.get_if_supported(&features)).collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// texels are stored in blocks of width x height, each `bytes` big. uncompressed formats are 1x1 blocks
+pub struct BlockInfo {
+  pub width: u32,
+  pub height: u32,
+  pub bytes: u64,
+}
+
+pub fn get_format_block_info(format: ash::vk::Format) -> Option<BlockInfo> {
+  use ash::vk::Format as F;
+  let block = |width, height, bytes| Some(BlockInfo { width, height, bytes });
+  match format {
+    F::BC1_RGB_UNORM_BLOCK | F::BC1_RGB_SRGB_BLOCK | F::BC1_RGBA_UNORM_BLOCK | F::BC1_RGBA_SRGB_BLOCK
+    | F::BC4_UNORM_BLOCK | F::BC4_SNORM_BLOCK => block(4, 4, 8),
+    F::BC2_UNORM_BLOCK | F::BC2_SRGB_BLOCK | F::BC3_UNORM_BLOCK | F::BC3_SRGB_BLOCK
+    | F::BC5_UNORM_BLOCK | F::BC5_SNORM_BLOCK | F::BC6H_UFLOAT_BLOCK | F::BC6H_SFLOAT_BLOCK
+    | F::BC7_UNORM_BLOCK | F::BC7_SRGB_BLOCK => block(4, 4, 16),
+    F::ETC2_R8G8B8_UNORM_BLOCK | F::ETC2_R8G8B8_SRGB_BLOCK | F::ETC2_R8G8B8A1_UNORM_BLOCK | F::ETC2_R8G8B8A1_SRGB_BLOCK
+    | F::EAC_R11_UNORM_BLOCK | F::EAC_R11_SNORM_BLOCK => block(4, 4, 8),
+    F::ETC2_R8G8B8A8_UNORM_BLOCK | F::ETC2_R8G8B8A8_SRGB_BLOCK | F::EAC_R11G11_UNORM_BLOCK | F::EAC_R11G11_SNORM_BLOCK => block(4, 4, 16),
+    F::ASTC_4X4_UNORM_BLOCK | F::ASTC_4X4_SRGB_BLOCK => block(4, 4, 16),
+    F::ASTC_5X4_UNORM_BLOCK | F::ASTC_5X4_SRGB_BLOCK => block(5, 4, 16),
+    F::ASTC_5X5_UNORM_BLOCK | F::ASTC_5X5_SRGB_BLOCK => block(5, 5, 16),
+    F::ASTC_6X5_UNORM_BLOCK | F::ASTC_6X5_SRGB_BLOCK => block(6, 5, 16),
+    F::ASTC_6X6_UNORM_BLOCK | F::ASTC_6X6_SRGB_BLOCK => block(6, 6, 16),
+    F::ASTC_8X5_UNORM_BLOCK | F::ASTC_8X5_SRGB_BLOCK => block(8, 5, 16),
+    F::ASTC_8X6_UNORM_BLOCK | F::ASTC_8X6_SRGB_BLOCK => block(8, 6, 16),
+    F::ASTC_8X8_UNORM_BLOCK | F::ASTC_8X8_SRGB_BLOCK => block(8, 8, 16),
+    F::ASTC_10X5_UNORM_BLOCK | F::ASTC_10X5_SRGB_BLOCK => block(10, 5, 16),
+    F::ASTC_10X6_UNORM_BLOCK | F::ASTC_10X6_SRGB_BLOCK => block(10, 6, 16),
+    F::ASTC_10X8_UNORM_BLOCK | F::ASTC_10X8_SRGB_BLOCK => block(10, 8, 16),
+    F::ASTC_10X10_UNORM_BLOCK | F::ASTC_10X10_SRGB_BLOCK => block(10, 10, 16),
+    F::ASTC_12X10_UNORM_BLOCK | F::ASTC_12X10_SRGB_BLOCK => block(12, 10, 16),
+    F::ASTC_12X12_UNORM_BLOCK | F::ASTC_12X12_SRGB_BLOCK => block(12, 12, 16),
+    _ => upload::get_format_texel_size(format).map(|bytes| BlockInfo { width: 1, height: 1, bytes }),
+  }
+}
+
+/// None for uncompressed formats
+pub fn get_format_compression(format: ash::vk::Format) -> Option<TextureCompression> {
+  let raw = format.as_raw();
+  let in_range = |first: ash::vk::Format, last: ash::vk::Format| raw >= first.as_raw() && raw <= last.as_raw();
+  if in_range(ash::vk::Format::BC1_RGB_UNORM_BLOCK, ash::vk::Format::BC7_SRGB_BLOCK) { return Some(TextureCompression::Bc); }
+  if in_range(ash::vk::Format::ETC2_R8G8B8_UNORM_BLOCK, ash::vk::Format::EAC_R11G11_SNORM_BLOCK) { return Some(TextureCompression::Etc2); }
+  if in_range(ash::vk::Format::ASTC_4X4_UNORM_BLOCK, ash::vk::Format::ASTC_12X12_SRGB_BLOCK) { return Some(TextureCompression::AstcLdr); }
+  None
+}
+
+/// bytes in one mip level, partial blocks at the edges count as whole ones. None without block info, or past u64
+pub fn get_level_size(extent: &ash::vk::Extent3D, format: ash::vk::Format) -> Option<u64> {
+  let block = get_format_block_info(format)?;
+  let blocks_wide = extent.width.div_ceil(block.width) as u64;
+  let blocks_high = extent.height.div_ceil(block.height) as u64;
+  blocks_wide.checked_mul(blocks_high)?.checked_mul(extent.depth as u64)?.checked_mul(block.bytes)
+}
+
+/// what decompress_mip_chain decodes into, None if it can't
+pub fn get_decompressed_format(format: ash::vk::Format) -> Option<ash::vk::Format> {
+  use ash::vk::Format as F;
+  match format {
+    F::BC1_RGB_UNORM_BLOCK | F::BC1_RGBA_UNORM_BLOCK | F::BC2_UNORM_BLOCK | F::BC3_UNORM_BLOCK | F::BC4_UNORM_BLOCK | F::BC5_UNORM_BLOCK => Some(F::R8G8B8A8_UNORM),
+    F::BC1_RGB_SRGB_BLOCK | F::BC1_RGBA_SRGB_BLOCK | F::BC2_SRGB_BLOCK | F::BC3_SRGB_BLOCK => Some(F::R8G8B8A8_SRGB),
+    _ => None,
+  }
+}
+
+/// the CPU fallback for devices without the compression feature. decodes every level of a tightly packed mip chain to
+/// R8G8B8A8, keeping SRGB. only BC1 to BC5 for now, returns None for everything else.
+/// BC4 comes out as (r, 0, 0, 1) and BC5 as (r, g, 0, 1), what sampling the compressed image would give
+pub fn decompress_mip_chain(bytes: &[u8], extent: &ash::vk::Extent3D, format: ash::vk::Format, mip_levels: u32) -> Option<(Vec<u8>, ash::vk::Format)> {
+  let decompressed_format = get_decompressed_format(format)?;
+  let block = get_format_block_info(format)?;
+  let mut decompressed = vec![];
+  let mut start = 0usize;
+  for level in 0..mip_levels {
+    let mip_extent = mipmaps::get_mip_extent(extent, level);
+    let size = get_level_size(&mip_extent, format)? as usize;
+    let level_bytes = bytes.get(start..start + size)?;
+    start += size;
+
+    let (width, height) = (mip_extent.width as usize, mip_extent.height as usize);
+    let blocks_wide = width.div_ceil(4);
+    let mut pixels = vec![0u8; width * height * 4];
+    for (i, block_bytes) in level_bytes.chunks_exact(block.bytes as usize).enumerate() {
+      let texels = decode_block(block_bytes, format);
+      let (block_x, block_y) = (i % blocks_wide * 4, i / blocks_wide * 4);
+      // blocks hanging over the edge are cut off
+      for (j, texel) in texels.iter().enumerate() {
+        let (x, y) = (block_x + j % 4, block_y + j / 4);
+        if x >= width || y >= height { continue; }
+        pixels[(y * width + x) * 4..(y * width + x) * 4 + 4].copy_from_slice(texel);
+      }
+    }
+    decompressed.extend(pixels);
+  }
+  Some((decompressed, decompressed_format))
+}
+
+/// 16 RGBA8 texels, row by row
+fn decode_block(bytes: &[u8], format: ash::vk::Format) -> [[u8; 4]; 16] {
+  use ash::vk::Format as F;
+  let mut texels = [[0u8, 0, 0, 255]; 16];
+  match format {
+    F::BC1_RGB_UNORM_BLOCK | F::BC1_RGB_SRGB_BLOCK => decode_color_block(&bytes[0..8], true, false, &mut texels),
+    F::BC1_RGBA_UNORM_BLOCK | F::BC1_RGBA_SRGB_BLOCK => decode_color_block(&bytes[0..8], true, true, &mut texels),
+    F::BC2_UNORM_BLOCK | F::BC2_SRGB_BLOCK => {
+      decode_color_block(&bytes[8..16], false, false, &mut texels);
+      let alpha = u64::from_le_bytes(bytes[0..8].try_into().expect("8 bytes"));
+      for (i, texel) in texels.iter_mut().enumerate() { texel[3] = ((alpha >> (4 * i)) & 0xF) as u8 * 17; }
+    },
+    F::BC3_UNORM_BLOCK | F::BC3_SRGB_BLOCK => {
+      decode_color_block(&bytes[8..16], false, false, &mut texels);
+      decode_channel_block(&bytes[0..8], 3, &mut texels);
+    },
+    F::BC4_UNORM_BLOCK => decode_channel_block(&bytes[0..8], 0, &mut texels),
+    F::BC5_UNORM_BLOCK => {
+      decode_channel_block(&bytes[0..8], 0, &mut texels);
+      decode_channel_block(&bytes[8..16], 1, &mut texels);
+    },
+    _ => unreachable!("{:?} has no cpu decoder", format),
+  }
+  texels
+}
+
+/// two RGB565 endpoints and 2 bit indices. BC1 switches to 3 colours plus black (or transparent) when c0 <= c1, BC2 and BC3 never do
+fn decode_color_block(bytes: &[u8], is_bc1: bool, has_alpha: bool, texels: &mut [[u8; 4]; 16]) -> () {
+  let c0 = u16::from_le_bytes([bytes[0], bytes[1]]);
+  let c1 = u16::from_le_bytes([bytes[2], bytes[3]]);
+  let indices = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+  let expand = |c: u16| {
+    let (r, g, b) = ((c >> 11) & 31, (c >> 5) & 63, c & 31);
+    [((r << 3) | (r >> 2)) as u32, ((g << 2) | (g >> 4)) as u32, ((b << 3) | (b >> 2)) as u32]
+  };
+  let (e0, e1) = (expand(c0), expand(c1));
+  let mix = |a: u32, b: u32, wa: u32, wb: u32| ((a * wa + b * wb) / (wa + wb)) as u8;
+  let palette: [[u8; 4]; 4] = match !is_bc1 || c0 > c1 {
+    true => [
+      [e0[0] as u8, e0[1] as u8, e0[2] as u8, 255],
+      [e1[0] as u8, e1[1] as u8, e1[2] as u8, 255],
+      [mix(e0[0], e1[0], 2, 1), mix(e0[1], e1[1], 2, 1), mix(e0[2], e1[2], 2, 1), 255],
+      [mix(e0[0], e1[0], 1, 2), mix(e0[1], e1[1], 1, 2), mix(e0[2], e1[2], 1, 2), 255],
+    ],
+    false => [
+      [e0[0] as u8, e0[1] as u8, e0[2] as u8, 255],
+      [e1[0] as u8, e1[1] as u8, e1[2] as u8, 255],
+      [mix(e0[0], e1[0], 1, 1), mix(e0[1], e1[1], 1, 1), mix(e0[2], e1[2], 1, 1), 255],
+      [0, 0, 0, if has_alpha { 0 } else { 255 }],
+    ],
+  };
+  for (i, texel) in texels.iter_mut().enumerate() {
+    let color = palette[((indices >> (2 * i)) & 3) as usize];
+    texel[0..3].copy_from_slice(&color[0..3]);
+    if is_bc1 { texel[3] = color[3]; }
+  }
+}
+
+/// two 8 bit endpoints and 3 bit indices, as used for BC3 alpha and the BC4 and BC5 channels
+fn decode_channel_block(bytes: &[u8], channel: usize, texels: &mut [[u8; 4]; 16]) -> () {
+  let (a0, a1) = (bytes[0] as u32, bytes[1] as u32);
+  let indices = bytes[2..8].iter().rev().fold(0u64, |bits, byte| (bits << 8) | *byte as u64);
+  let palette: Vec<u8> = match a0 > a1 {
+    true => (0..8).map(|code| match code {
+      0 => a0 as u8,
+      1 => a1 as u8,
+      _ => (((8 - code) * a0 + (code - 1) * a1) / 7) as u8,
+    }).collect(),
+    false => (0..8).map(|code| match code {
+      0 => a0 as u8,
+      1 => a1 as u8,
+      6 => 0,
+      7 => 255,
+      _ => (((6 - code) * a0 + (code - 1) * a1) / 5) as u8,
+    }).collect(),
+  };
+  for (i, texel) in texels.iter_mut().enumerate() {
+    texel[channel] = palette[((indices >> (3 * i)) & 7) as usize];
+  }
+}
+
+#[test]
+fn test_block_sizes() {
+  let extent = |width, height| ash::vk::Extent3D::default().width(width).height(height).depth(1);
+  assert_eq!(get_level_size(&extent(256, 256), ash::vk::Format::BC1_RGB_UNORM_BLOCK), Some(64 * 64 * 8));
+  // partial blocks are padded out
+  assert_eq!(get_level_size(&extent(5, 1), ash::vk::Format::BC7_UNORM_BLOCK), Some(2 * 16));
+  assert_eq!(get_level_size(&extent(13, 12), ash::vk::Format::ASTC_12X12_SRGB_BLOCK), Some(2 * 16));
+  assert_eq!(get_level_size(&extent(3, 3), ash::vk::Format::R8G8B8A8_UNORM), Some(36));
+  assert_eq!(get_format_compression(ash::vk::Format::BC5_SNORM_BLOCK), Some(TextureCompression::Bc));
+  assert_eq!(get_format_compression(ash::vk::Format::EAC_R11_UNORM_BLOCK), Some(TextureCompression::Etc2));
+  assert_eq!(get_format_compression(ash::vk::Format::ASTC_8X8_SRGB_BLOCK), Some(TextureCompression::AstcLdr));
+  assert_eq!(get_format_compression(ash::vk::Format::R8G8B8A8_SRGB), None);
+}
+
+#[test]
+fn test_decompress_bc1_and_bc3() {
+  let extent = ash::vk::Extent3D::default().width(4).height(4).depth(1);
+  // pure red and pure blue endpoints, first row red, second row blue, third row 2/3 red, last row 1/3 red
+  let bc1 = [0x00, 0xF8, 0x1F, 0x00, 0b00000000, 0b01010101, 0b10101010, 0b11111111];
+  let (pixels, format) = decompress_mip_chain(&bc1, &extent, ash::vk::Format::BC1_RGB_UNORM_BLOCK, 1).expect("bc1 has a cpu decoder");
+  assert_eq!(format, ash::vk::Format::R8G8B8A8_UNORM);
+  assert_eq!(&pixels[0..4], &[255, 0, 0, 255]);
+  assert_eq!(&pixels[16..20], &[0, 0, 255, 255]);
+  assert_eq!(&pixels[32..36], &[170, 0, 85, 255]);
+  assert_eq!(&pixels[48..52], &[85, 0, 170, 255]);
+
+  // same colours, with alpha going from 255 to 0 in eight steps along the first row
+  let bc3 = [[255, 0, 0b10001000, 0b11000110, 0b11111010, 0, 0, 0], bc1].concat();
+  let (pixels, format) = decompress_mip_chain(&bc3, &extent, ash::vk::Format::BC3_SRGB_BLOCK, 1).expect("bc3 has a cpu decoder");
+  assert_eq!(format, ash::vk::Format::R8G8B8A8_SRGB);
+  let first_row_alpha = (0..4).map(|x| pixels[x * 4 + 3]).collect::<Vec<_>>();
+  assert_eq!(first_row_alpha, vec![255, 0, 218, 182]);
+  assert_eq!(decompress_mip_chain(&bc3, &extent, ash::vk::Format::BC7_UNORM_BLOCK, 1), None);
+}
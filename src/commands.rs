@@ -0,0 +1,345 @@
+use proc_macros::{Getters};
+use crate::{device_context::SharedDevice, error::GfxResult, image_state::ImageUsage, resources};
+
+/// the stages and accesses an image in this layout is used with. PRESENT_SRC_KHR and UNDEFINED have none,
+/// presentation and acquiring are synchronised by semaphores
+pub fn get_layout_usage(layout: ash::vk::ImageLayout) -> (ash::vk::PipelineStageFlags2, ash::vk::AccessFlags2) {
+  use ash::vk::{AccessFlags2 as A, ImageLayout as L, PipelineStageFlags2 as S};
+  match layout {
+    L::UNDEFINED | L::PREINITIALIZED | L::PRESENT_SRC_KHR => (S::NONE, A::NONE),
+    L::TRANSFER_SRC_OPTIMAL => (S::TRANSFER, A::TRANSFER_READ),
+    L::TRANSFER_DST_OPTIMAL => (S::TRANSFER, A::TRANSFER_WRITE),
+    L::SHADER_READ_ONLY_OPTIMAL => (S::FRAGMENT_SHADER | S::COMPUTE_SHADER, A::SHADER_READ),
+    L::COLOR_ATTACHMENT_OPTIMAL => (S::COLOR_ATTACHMENT_OUTPUT, A::COLOR_ATTACHMENT_READ | A::COLOR_ATTACHMENT_WRITE),
+    L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (S::EARLY_FRAGMENT_TESTS | S::LATE_FRAGMENT_TESTS, A::DEPTH_STENCIL_ATTACHMENT_READ | A::DEPTH_STENCIL_ATTACHMENT_WRITE),
+    L::DEPTH_STENCIL_READ_ONLY_OPTIMAL => (S::EARLY_FRAGMENT_TESTS | S::LATE_FRAGMENT_TESTS, A::DEPTH_STENCIL_ATTACHMENT_READ),
+    // GENERAL and anything we don't know, so be safe
+    _ => (S::ALL_COMMANDS, A::MEMORY_READ | A::MEMORY_WRITE),
+  }
+}
+
+/// only writes have to be made available, a barrier after a read just has to wait for it
+pub fn get_write_accesses(access: ash::vk::AccessFlags2) -> ash::vk::AccessFlags2 {
+  use ash::vk::AccessFlags2 as A;
+  access & (A::TRANSFER_WRITE | A::SHADER_WRITE | A::COLOR_ATTACHMENT_WRITE | A::DEPTH_STENCIL_ATTACHMENT_WRITE | A::HOST_WRITE | A::MEMORY_WRITE)
+}
+
+#[derive(Getters, Clone, Copy, Debug)]
+/// a layout transition, or a queue family transfer. start with ImageBarrier::new and chain the rest.
+/// stages and accesses are derived from the layouts unless set with src_usage or dst_usage
+pub struct ImageBarrier {
+  pub image: ash::vk::Image,
+  pub old_layout: ash::vk::ImageLayout,
+  pub new_layout: ash::vk::ImageLayout,
+  pub src_stage: ash::vk::PipelineStageFlags2,
+  pub src_access: ash::vk::AccessFlags2,
+  pub dst_stage: ash::vk::PipelineStageFlags2,
+  pub dst_access: ash::vk::AccessFlags2,
+  pub subresource_range: ash::vk::ImageSubresourceRange,
+  pub src_queue_family_index: u32,
+  pub dst_queue_family_index: u32,
+}
+
+impl ImageBarrier {
+  /// every mip level and array layer of the colour aspect
+  pub fn new(image: ash::vk::Image, old_layout: ash::vk::ImageLayout, new_layout: ash::vk::ImageLayout) -> Self {
+    let (src_stage, src_access) = get_layout_usage(old_layout);
+    let (dst_stage, dst_access) = get_layout_usage(new_layout);
+    let subresource_range = ash::vk::ImageSubresourceRange::default()
+      .aspect_mask(ash::vk::ImageAspectFlags::COLOR)
+      .base_mip_level(0)
+      .level_count(ash::vk::REMAINING_MIP_LEVELS)
+      .base_array_layer(0)
+      .layer_count(ash::vk::REMAINING_ARRAY_LAYERS);
+    ImageBarrier {
+      image,
+      old_layout,
+      new_layout,
+      src_stage,
+      src_access: get_write_accesses(src_access),
+      dst_stage,
+      dst_access,
+      subresource_range,
+      src_queue_family_index: ash::vk::QUEUE_FAMILY_IGNORED,
+      dst_queue_family_index: ash::vk::QUEUE_FAMILY_IGNORED,
+    }
+  }
+
+  /// what the image was last used for. e.g. a swapchain image has to wait at the stage its acquire semaphore is waited on
+  pub fn src_usage(mut self, stage: ash::vk::PipelineStageFlags2, access: ash::vk::AccessFlags2) -> Self {
+    self.src_stage = stage;
+    self.src_access = access;
+    self
+  }
+
+  /// what the image is used for next
+  pub fn dst_usage(mut self, stage: ash::vk::PipelineStageFlags2, access: ash::vk::AccessFlags2) -> Self {
+    self.dst_stage = stage;
+    self.dst_access = access;
+    self
+  }
+
+  pub fn mip_levels(mut self, base_mip_level: u32, level_count: u32) -> Self {
+    self.subresource_range = self.subresource_range.base_mip_level(base_mip_level).level_count(level_count);
+    self
+  }
+
+  pub fn array_layers(mut self, base_array_layer: u32, layer_count: u32) -> Self {
+    self.subresource_range = self.subresource_range.base_array_layer(base_array_layer).layer_count(layer_count);
+    self
+  }
+
+  pub fn aspect(mut self, aspect_mask: ash::vk::ImageAspectFlags) -> Self {
+    self.subresource_range = self.subresource_range.aspect_mask(aspect_mask);
+    self
+  }
+
+  /// record the same barrier on both queues, the release and then the acquire
+  pub fn queue_family_transfer(mut self, src_queue_family_index: u32, dst_queue_family_index: u32) -> Self {
+    self.src_queue_family_index = src_queue_family_index;
+    self.dst_queue_family_index = dst_queue_family_index;
+    self
+  }
+
+  pub fn get_barrier2(&self) -> ash::vk::ImageMemoryBarrier2<'static> {
+    ash::vk::ImageMemoryBarrier2::default()
+      .src_stage_mask(self.src_stage)
+      .src_access_mask(self.src_access)
+      .dst_stage_mask(self.dst_stage)
+      .dst_access_mask(self.dst_access)
+      .old_layout(self.old_layout)
+      .new_layout(self.new_layout)
+      .src_queue_family_index(self.src_queue_family_index)
+      .dst_queue_family_index(self.dst_queue_family_index)
+      .image(self.image)
+      .subresource_range(self.subresource_range)
+  }
+
+  /// without synchronization2 the stages go on the command instead, see get_legacy_stages
+  pub fn get_barrier(&self) -> ash::vk::ImageMemoryBarrier<'static> {
+    ash::vk::ImageMemoryBarrier::default()
+      .src_access_mask(ash::vk::AccessFlags::from_raw(self.src_access.as_raw() as u32))
+      .dst_access_mask(ash::vk::AccessFlags::from_raw(self.dst_access.as_raw() as u32))
+      .old_layout(self.old_layout)
+      .new_layout(self.new_layout)
+      .src_queue_family_index(self.src_queue_family_index)
+      .dst_queue_family_index(self.dst_queue_family_index)
+      .image(self.image)
+      .subresource_range(self.subresource_range)
+  }
+}
+
+/// the low bits of the synchronization2 flags are the old ones. NONE becomes TOP_OF_PIPE as a source and BOTTOM_OF_PIPE as a destination.
+/// stages that only exist in synchronization2 (COPY, BLIT, ...) can't be expressed, stick to the old ones when it might be missing
+pub fn get_legacy_stages(stage: ash::vk::PipelineStageFlags2, is_src: bool) -> ash::vk::PipelineStageFlags {
+  match (stage == ash::vk::PipelineStageFlags2::NONE, is_src) {
+    (true, true) => ash::vk::PipelineStageFlags::TOP_OF_PIPE,
+    (true, false) => ash::vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+    (false, _) => ash::vk::PipelineStageFlags::from_raw(stage.as_raw() as u32),
+  }
+}
+
+#[derive(Getters, Clone, Copy, Debug)]
+/// makes writes to a whole buffer visible to the next use of it
+pub struct BufferBarrier {
+  pub buffer: ash::vk::Buffer,
+  pub src_stage: ash::vk::PipelineStageFlags2,
+  pub src_access: ash::vk::AccessFlags2,
+  pub dst_stage: ash::vk::PipelineStageFlags2,
+  pub dst_access: ash::vk::AccessFlags2,
+}
+
+impl BufferBarrier {
+  /// only the writes in src_access have to be made available
+  pub fn new(buffer: ash::vk::Buffer, src_stage: ash::vk::PipelineStageFlags2, src_access: ash::vk::AccessFlags2, dst_stage: ash::vk::PipelineStageFlags2, dst_access: ash::vk::AccessFlags2) -> Self {
+    BufferBarrier { buffer, src_stage, src_access: get_write_accesses(src_access), dst_stage, dst_access }
+  }
+
+  pub fn get_barrier2(&self) -> ash::vk::BufferMemoryBarrier2<'static> {
+    ash::vk::BufferMemoryBarrier2::default()
+      .src_stage_mask(self.src_stage)
+      .src_access_mask(self.src_access)
+      .dst_stage_mask(self.dst_stage)
+      .dst_access_mask(self.dst_access)
+      .src_queue_family_index(ash::vk::QUEUE_FAMILY_IGNORED)
+      .dst_queue_family_index(ash::vk::QUEUE_FAMILY_IGNORED)
+      .buffer(self.buffer)
+      .offset(0)
+      .size(ash::vk::WHOLE_SIZE)
+  }
+
+  pub fn get_barrier(&self) -> ash::vk::BufferMemoryBarrier<'static> {
+    ash::vk::BufferMemoryBarrier::default()
+      .src_access_mask(ash::vk::AccessFlags::from_raw(self.src_access.as_raw() as u32))
+      .dst_access_mask(ash::vk::AccessFlags::from_raw(self.dst_access.as_raw() as u32))
+      .src_queue_family_index(ash::vk::QUEUE_FAMILY_IGNORED)
+      .dst_queue_family_index(ash::vk::QUEUE_FAMILY_IGNORED)
+      .buffer(self.buffer)
+      .offset(0)
+      .size(ash::vk::WHOLE_SIZE)
+  }
+}
+
+pub fn record_image_barriers(device: &ash::Device, synchronization2: Option<&ash::khr::synchronization2::Device>, command_buffer: &ash::vk::CommandBuffer, barriers: &[ImageBarrier]) -> () {
+  record_barriers(device, synchronization2, command_buffer, barriers, &[])
+}
+
+/// cmd_pipeline_barrier2 when synchronization2 is enabled. otherwise one cmd_pipeline_barrier, waiting on the stages of every barrier combined
+pub fn record_barriers(device: &ash::Device, synchronization2: Option<&ash::khr::synchronization2::Device>, command_buffer: &ash::vk::CommandBuffer, image_barriers: &[ImageBarrier], buffer_barriers: &[BufferBarrier]) -> () {
+  if image_barriers.is_empty() && buffer_barriers.is_empty() { return; }
+  match synchronization2 {
+    Some(synchronization2) => {
+      let image_memory_barriers = image_barriers.iter().map(|barrier| barrier.get_barrier2()).collect::<Vec<_>>();
+      let buffer_memory_barriers = buffer_barriers.iter().map(|barrier| barrier.get_barrier2()).collect::<Vec<_>>();
+      let dependency_info = ash::vk::DependencyInfo::default()
+        .image_memory_barriers(&image_memory_barriers)
+        .buffer_memory_barriers(&buffer_memory_barriers);
+      unsafe { synchronization2.cmd_pipeline_barrier2(*command_buffer, &dependency_info); }
+    },
+    None => {
+      let stages = image_barriers.iter().map(|barrier| (barrier.src_stage, barrier.dst_stage))
+        .chain(buffer_barriers.iter().map(|barrier| (barrier.src_stage, barrier.dst_stage)));
+      let (src_stages, dst_stages) = stages.fold((ash::vk::PipelineStageFlags2::NONE, ash::vk::PipelineStageFlags2::NONE), |(src, dst), (barrier_src, barrier_dst)| (src | barrier_src, dst | barrier_dst));
+      let image_memory_barriers = image_barriers.iter().map(|barrier| barrier.get_barrier()).collect::<Vec<_>>();
+      let buffer_memory_barriers = buffer_barriers.iter().map(|barrier| barrier.get_barrier()).collect::<Vec<_>>();
+      unsafe {
+        device.cmd_pipeline_barrier(*command_buffer, get_legacy_stages(src_stages, true), get_legacy_stages(dst_stages, false), ash::vk::DependencyFlags::empty(), &[], &buffer_memory_barriers, &image_memory_barriers);
+      }
+    },
+  }
+}
+
+#[derive(Getters)]
+/// records barriers, blits and copies into one command buffer, submitted once. see immediate_submit for one-off work
+pub struct CommandRecorder {
+  pub command_buffer: resources::CommandBuffer,
+  pub synchronization2: Option<ash::khr::synchronization2::Device>,
+  #[Getters_Skip]
+  pub device: SharedDevice,
+}
+
+impl CommandRecorder {
+  /// pass GFXHeadless::synchronization2 to use cmd_pipeline_barrier2 for barriers
+  pub fn begin(device: &SharedDevice, command_pool: &ash::vk::CommandPool, synchronization2: Option<&ash::khr::synchronization2::Device>) -> GfxResult<Self> {
+    let command_buffer = resources::CommandBuffer::new(device, command_pool)?;
+    let begin_info = ash::vk::CommandBufferBeginInfo::default().flags(ash::vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    unsafe { device.begin_command_buffer(*command_buffer, &begin_info)?; }
+    Ok(CommandRecorder { command_buffer, synchronization2: synchronization2.cloned(), device: device.clone() })
+  }
+
+  pub fn image_barriers(&self, barriers: &[ImageBarrier]) -> &Self {
+    record_image_barriers(&self.device, self.synchronization2.as_ref(), &self.command_buffer, barriers);
+    self
+  }
+
+  /// every mip level ready for usage, with whatever barriers its tracked state needs
+  pub fn prepare_image(&self, image: &resources::Image, usage: ImageUsage) -> &Self {
+    self.prepare_image_levels(image, usage, 0, image.mip_levels)
+  }
+
+  pub fn prepare_image_levels(&self, image: &resources::Image, usage: ImageUsage, base_mip_level: u32, level_count: u32) -> &Self {
+    let barriers = image.state.transition(image.image, usage, base_mip_level, level_count, &format!("{:?}", image.image));
+    self.image_barriers(&barriers)
+  }
+
+  /// prepares both tracked images, then blits the first mip level of one onto the other
+  pub fn blit_images(&self, src_image: &resources::Image, dst_image: &resources::Image, filter: ash::vk::Filter) -> &Self {
+    self
+      .prepare_image_levels(src_image, ImageUsage::TransferSrc, 0, 1)
+      .prepare_image_levels(dst_image, ImageUsage::TransferDst, 0, 1)
+      .blit_image(src_image, &src_image.extent, dst_image, &dst_image.extent, filter)
+  }
+
+  /// the first mip level of each, from TRANSFER_SRC_OPTIMAL to TRANSFER_DST_OPTIMAL. scales when the extents differ
+  pub fn blit_image(&self, src_image: &ash::vk::Image, src_extent: &ash::vk::Extent3D, dst_image: &ash::vk::Image, dst_extent: &ash::vk::Extent3D, filter: ash::vk::Filter) -> &Self {
+    let get_far_corner = |extent: &ash::vk::Extent3D| ash::vk::Offset3D::default().x(extent.width as i32).y(extent.height as i32).z(extent.depth as i32);
+    let region = ash::vk::ImageBlit::default()
+      .src_offsets([ash::vk::Offset3D::default(), get_far_corner(src_extent)])
+      .dst_offsets([ash::vk::Offset3D::default(), get_far_corner(dst_extent)])
+      .src_subresource(get_color_layers(0))
+      .dst_subresource(get_color_layers(0));
+    unsafe { self.device.cmd_blit_image(*self.command_buffer, *src_image, ash::vk::ImageLayout::TRANSFER_SRC_OPTIMAL, *dst_image, ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[region], filter); }
+    self
+  }
+
+  /// the first mip level, from TRANSFER_SRC_OPTIMAL to TRANSFER_DST_OPTIMAL. the formats have to be size compatible
+  pub fn copy_image(&self, src_image: &ash::vk::Image, dst_image: &ash::vk::Image, extent: &ash::vk::Extent3D) -> &Self {
+    let region = ash::vk::ImageCopy::default()
+      .src_subresource(get_color_layers(0))
+      .dst_subresource(get_color_layers(0))
+      .extent(*extent);
+    unsafe { self.device.cmd_copy_image(*self.command_buffer, *src_image, ash::vk::ImageLayout::TRANSFER_SRC_OPTIMAL, *dst_image, ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[region]); }
+    self
+  }
+
+  /// the whole buffer into one mip level, tightly packed. the image has to be in TRANSFER_DST_OPTIMAL
+  pub fn copy_buffer_to_image(&self, buffer: &ash::vk::Buffer, image: &ash::vk::Image, extent: &ash::vk::Extent3D, mip_level: u32) -> &Self {
+    let region = ash::vk::BufferImageCopy::default()
+      .image_subresource(get_color_layers(mip_level))
+      .image_extent(*extent);
+    unsafe { self.device.cmd_copy_buffer_to_image(*self.command_buffer, *buffer, *image, ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[region]); }
+    self
+  }
+
+  /// keep the result until it has been waited on, the command buffer can't be freed while the GPU still uses it
+  pub fn submit(self, queue: &ash::vk::Queue) -> GfxResult<SubmittedCommands> {
+    unsafe { self.device.end_command_buffer(*self.command_buffer)?; }
+    let fence = crate::submit(&self.device, queue, &self.command_buffer)?;
+    Ok(SubmittedCommands { command_buffer: self.command_buffer, fence })
+  }
+
+  /// panics if validation recorded an error, see DeviceContext::check_validation
+  pub fn submit_and_wait(self, queue: &ash::vk::Queue) -> GfxResult<()> {
+    let timeout_ns = 9999 * 1000 * 1000;
+    let device = self.device.clone();
+    self.submit(queue)?.wait(timeout_ns)?;
+    device.check_validation();
+    Ok(())
+  }
+}
+
+#[derive(Getters)]
+pub struct SubmittedCommands {
+  pub command_buffer: resources::CommandBuffer,
+  pub fence: resources::Fence,
+}
+
+impl SubmittedCommands {
+  pub fn wait(&self, timeout_ns: u64) -> GfxResult<()> {
+    self.fence.wait(timeout_ns)
+  }
+}
+
+/// for one-off work that the CPU has to wait on anyway, like setting up resources. blocks until the queue is done with it
+pub fn immediate_submit(device: &SharedDevice, command_pool: &ash::vk::CommandPool, queue: &ash::vk::Queue, synchronization2: Option<&ash::khr::synchronization2::Device>, record: impl FnOnce(&CommandRecorder) -> ()) -> GfxResult<()> {
+  let recorder = CommandRecorder::begin(device, command_pool, synchronization2)?;
+  record(&recorder);
+  recorder.submit_and_wait(queue)
+}
+
+fn get_color_layers(mip_level: u32) -> ash::vk::ImageSubresourceLayers {
+  ash::vk::ImageSubresourceLayers::default()
+    .aspect_mask(ash::vk::ImageAspectFlags::COLOR)
+    .mip_level(mip_level)
+    .base_array_layer(0)
+    .layer_count(1)
+}
+
+#[test]
+fn test_barrier_masks_follow_layouts() {
+  let image = ash::vk::Image::null();
+  let barrier = ImageBarrier::new(image, ash::vk::ImageLayout::UNDEFINED, ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+  assert_eq!((barrier.src_stage, barrier.src_access), (ash::vk::PipelineStageFlags2::NONE, ash::vk::AccessFlags2::NONE));
+  assert_eq!((barrier.dst_stage, barrier.dst_access), (ash::vk::PipelineStageFlags2::TRANSFER, ash::vk::AccessFlags2::TRANSFER_WRITE));
+
+  // reads don't need making available, just waiting for
+  let barrier = ImageBarrier::new(image, ash::vk::ImageLayout::TRANSFER_SRC_OPTIMAL, ash::vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL).mip_levels(2, 1);
+  assert_eq!((barrier.src_stage, barrier.src_access), (ash::vk::PipelineStageFlags2::TRANSFER, ash::vk::AccessFlags2::NONE));
+  assert_eq!(barrier.dst_access, ash::vk::AccessFlags2::SHADER_READ);
+  assert_eq!((barrier.subresource_range.base_mip_level, barrier.subresource_range.level_count), (2, 1));
+
+  let legacy = ImageBarrier::new(image, ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL, ash::vk::ImageLayout::PRESENT_SRC_KHR).get_barrier();
+  assert_eq!(legacy.src_access_mask, ash::vk::AccessFlags::TRANSFER_WRITE);
+  assert_eq!(get_legacy_stages(ash::vk::PipelineStageFlags2::NONE, false), ash::vk::PipelineStageFlags::BOTTOM_OF_PIPE);
+  assert_eq!(get_legacy_stages(ash::vk::PipelineStageFlags2::TRANSFER, true), ash::vk::PipelineStageFlags::TRANSFER);
+}
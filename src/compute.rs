@@ -0,0 +1,95 @@
+use crate::{constants::MemoryIntent, device_context::SharedDevice, create_buffer_with_usage, error::{GfxError, GfxResult}, pipeline, resources, shaders};
+
+pub const GRAYSCALE_SHADER_PATH: &str = "./assets/shaders/grayscale.comp.spv";
+
+/// a compute shader with one descriptor set and optional push constants, which the shader sees in order from offset 0
+pub struct ComputePipeline {
+  pub pipeline: resources::Pipeline,
+  pub pipeline_layout: resources::PipelineLayout,
+  pub descriptor_set_layout: resources::DescriptorSetLayout,
+  pub push_constant_size: u32,
+}
+
+impl ComputePipeline {
+  /// bindings are the shader's set 0. push_constant_size is in bytes, 0 for none, and at least 128 is always allowed
+  pub fn new(device: &SharedDevice, shader_path: &str, bindings: &[(u32, ash::vk::DescriptorType)], push_constant_size: u32) -> GfxResult<Self> {
+    let bindings = bindings.iter().map(|(binding, descriptor_type)| (*binding, *descriptor_type, ash::vk::ShaderStageFlags::COMPUTE)).collect::<Vec<_>>();
+    let descriptor_set_layout = resources::DescriptorSetLayout::new(device, &bindings)?;
+    let push_constant_ranges = match push_constant_size {
+      0 => vec![],
+      size => vec![ash::vk::PushConstantRange::default().stage_flags(ash::vk::ShaderStageFlags::COMPUTE).offset(0).size(size)],
+    };
+    let pipeline_layout = resources::PipelineLayout::new(device, &[*descriptor_set_layout], &push_constant_ranges)?;
+    let shader = shaders::create_shader_module(device, shader_path)?;
+    let pipeline = pipeline::create_compute_pipeline(device, &pipeline_layout, &shader)?;
+    Ok(ComputePipeline { pipeline, pipeline_layout, descriptor_set_layout, push_constant_size })
+  }
+
+  /// binds everything and dispatches group_counts workgroups. push_constants has to fill push_constant_size exactly,
+  /// floats go in with to_bits. the resources the descriptor set points at have to be ready for the compute stage already.
+  /// the wrong amount of push constants is a panic in debug builds, in release the validation layer will have to catch it
+  pub fn dispatch(&self, device: &ash::Device, command_buffer: &ash::vk::CommandBuffer, descriptor_set: &ash::vk::DescriptorSet, push_constants: &[u32], group_counts: [u32; 3]) -> () {
+    let push_constant_bytes = get_push_constant_bytes(push_constants);
+    debug_assert!(push_constant_bytes.len() as u32 == self.push_constant_size, "{} bytes of push constants for a pipeline that takes {}", push_constant_bytes.len(), self.push_constant_size);
+    unsafe {
+      device.cmd_bind_pipeline(*command_buffer, ash::vk::PipelineBindPoint::COMPUTE, *self.pipeline);
+      device.cmd_bind_descriptor_sets(*command_buffer, ash::vk::PipelineBindPoint::COMPUTE, *self.pipeline_layout, 0, &[*descriptor_set], &[]);
+      if !push_constant_bytes.is_empty() {
+        device.cmd_push_constants(*command_buffer, *self.pipeline_layout, ash::vk::ShaderStageFlags::COMPUTE, 0, &push_constant_bytes);
+      }
+      device.cmd_dispatch(*command_buffer, group_counts[0], group_counts[1], group_counts[2]);
+    }
+  }
+}
+
+/// in the device's byte order, which is what the shader reads them in
+pub fn get_push_constant_bytes(push_constants: &[u32]) -> Vec<u8> {
+  push_constants.iter().flat_map(|word| word.to_ne_bytes()).collect()
+}
+
+/// enough workgroups of local_size to cover the extent. the shader has to skip invocations past the edge
+pub fn get_group_counts(extent: &ash::vk::Extent3D, local_size: [u32; 3]) -> [u32; 3] {
+  [extent.width.div_ceil(local_size[0]), extent.height.div_ceil(local_size[1]), extent.depth.div_ceil(local_size[2])]
+}
+
+/// for compute shaders to write, then to copy or blit out of. optimal tiling and GPU memory
+pub fn create_storage_image(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice, device: &SharedDevice, extent: &ash::vk::Extent3D, format: &ash::vk::Format) -> GfxResult<resources::Image> {
+  let props = unsafe { instance.get_physical_device_format_properties(*physical_device, *format) };
+  let flags = ash::vk::FormatFeatureFlags::STORAGE_IMAGE | ash::vk::FormatFeatureFlags::BLIT_SRC;
+  if props.optimal_tiling_features & flags != flags { return Err(GfxError::UnsupportedFormat(*format)); }
+
+  let create_info = ash::vk::ImageCreateInfo::default()
+    .image_type(ash::vk::ImageType::TYPE_2D)
+    .initial_layout(ash::vk::ImageLayout::UNDEFINED)
+    .format(*format)
+    .extent(*extent)
+    .tiling(ash::vk::ImageTiling::OPTIMAL)
+    .usage(ash::vk::ImageUsageFlags::STORAGE | ash::vk::ImageUsageFlags::TRANSFER_SRC | ash::vk::ImageUsageFlags::SAMPLED)
+    .sharing_mode(ash::vk::SharingMode::EXCLUSIVE)
+    .samples(ash::vk::SampleCountFlags::TYPE_1)
+    .mip_levels(1)
+    .array_layers(1)
+    ;
+  let image = unsafe { device.create_image(&create_info, None)? };
+  resources::Image::from_handle(device, image, extent, format, ash::vk::ImageTiling::OPTIMAL, MemoryIntent::GpuOnly)
+}
+
+/// mapped and filled with bytes, for compute shaders to read. the CPU's writes are visible to anything submitted after
+pub fn create_storage_buffer_with_bytes(device: &SharedDevice, bytes: &[u8]) -> GfxResult<resources::Buffer> {
+  let size = bytes.len() as u64;
+  let buffer = create_buffer_with_usage(device, size, ash::vk::BufferUsageFlags::STORAGE_BUFFER)?;
+  let buffer = resources::Buffer::from_handle(device, buffer, size, MemoryIntent::CpuToGpu)?;
+  buffer.allocation().write_bytes(bytes)?;
+  Ok(buffer)
+}
+
+#[test]
+fn test_dispatch_sizes() {
+  let extent = ash::vk::Extent3D::default().width(17).height(8).depth(1);
+  assert_eq!(get_group_counts(&extent, [8, 8, 1]), [3, 1, 1]);
+  assert_eq!(get_group_counts(&extent, [1, 1, 1]), [17, 8, 1]);
+
+  let bytes = get_push_constant_bytes(&[17, 1.0f32.to_bits()]);
+  assert_eq!(bytes.len(), 8);
+  assert_eq!(bytes[..4], 17u32.to_ne_bytes());
+}
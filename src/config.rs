@@ -0,0 +1,299 @@
+use proc_macros::{Getters};
+use crate::{block_formats::TextureCompression, constants, debug::{DebugMessage, DebugOutput}, device_selection::DeviceSelector, swapchain::PresentPreference};
+
+#[derive(Getters, Clone, Debug)]
+/// what to ask vulkan for when creating the gfx context. start from GfxConfig::default() and chain the with_ methods.
+/// required layers and extensions fail creation when missing, optional ones are enabled only if supported
+pub struct GfxConfig {
+  pub api_version: u32,
+  pub application_name: String,
+  pub application_version: u32,
+  pub engine_name: String,
+  pub engine_version: u32,
+  pub required_instance_layers: Vec<String>,
+  pub optional_instance_layers: Vec<String>,
+  pub required_instance_extensions: Vec<String>,
+  pub optional_instance_extensions: Vec<String>,
+  /// swapchain is added automatically when there is a window
+  pub required_device_extensions: Vec<String>,
+  pub optional_device_extensions: Vec<String>,
+  /// None means no debug utils messenger is created
+  pub debug_output: Option<DebugOutput>,
+  pub debug_severity: ash::vk::DebugUtilsMessageSeverityFlagsEXT,
+  pub panic_on_validation_error: bool,
+  /// reject devices without dynamic rendering even when headless. a window always requires it
+  pub require_dynamic_rendering: bool,
+  /// which physical device to use. defaults to the highest scoring one
+  pub device_selector: DeviceSelector,
+  /// ask for queues separate from the main one. skipped if the device has nothing to spare
+  pub dedicated_transfer_queue: bool,
+  pub dedicated_compute_queue: bool,
+  /// devices without these are rejected. any other supported compression is enabled anyway,
+  /// textures in a format the device can't sample are decompressed on the CPU where possible
+  pub required_texture_compression: Vec<TextureCompression>,
+  /// only used with a window. the inner size it opens at, in logical pixels
+  pub window_size: (u32, u32),
+  /// only used with a window. falls back to vsync when the surface can't do it
+  pub present_preference: PresentPreference,
+  pub triple_buffering: bool,
+  /// how many frames the CPU can record ahead of the GPU. more hides stalls, fewer keeps input latency down
+  pub frames_in_flight: u32,
+}
+
+impl Default for GfxConfig {
+  fn default() -> Self {
+    let strings = |strs: &[&str]| strs.iter().map(|str| str.to_string()).collect();
+    GfxConfig {
+      api_version: constants::API_VERSION,
+      application_name: "My Application".to_string(),
+      application_version: 1,
+      engine_name: "My Engine".to_string(),
+      engine_version: 1,
+      required_instance_layers: vec![],
+      optional_instance_layers: if get_if_validation_requested_by_env() { strings(&[constants::VALIDATION_LAYER]) } else { vec![] },
+      required_instance_extensions: strings(&constants::REQUIRED_INSTANCE_EXTENSIONS),
+      optional_instance_extensions: vec![],
+      required_device_extensions: strings(&constants::REQUIRED_DEVICE_EXTENSIONS),
+      optional_device_extensions: vec![],
+      debug_output: Some(DebugOutput::Log),
+      debug_severity: ash::vk::DebugUtilsMessageSeverityFlagsEXT::WARNING | ash::vk::DebugUtilsMessageSeverityFlagsEXT::ERROR,
+      panic_on_validation_error: false,
+      require_dynamic_rendering: false,
+      device_selector: DeviceSelector::Best,
+      dedicated_transfer_queue: true,
+      dedicated_compute_queue: true,
+      required_texture_compression: vec![],
+      window_size: (800, 600),
+      present_preference: PresentPreference::LowLatency,
+      triple_buffering: false,
+      frames_in_flight: 2,
+    }
+  }
+}
+
+impl GfxConfig {
+  /// e.g. ash::vk::API_VERSION_1_2, to allow older devices. the default is constants::API_VERSION.
+  /// below 1.3 dynamic rendering needs VK_KHR_dynamic_rendering, and below 1.2 the extensions it depends on
+  pub fn with_api_version(mut self, api_version: u32) -> Self {
+    self.api_version = api_version;
+    self
+  }
+
+  pub fn with_application(mut self, name: &str, version: u32) -> Self {
+    self.application_name = name.to_string();
+    self.application_version = version;
+    self
+  }
+
+  pub fn with_engine(mut self, name: &str, version: u32) -> Self {
+    self.engine_name = name.to_string();
+    self.engine_version = version;
+    self
+  }
+
+  pub fn with_required_instance_layer(mut self, name: &str) -> Self {
+    push_unique(&mut self.required_instance_layers, name);
+    self
+  }
+
+  pub fn with_optional_instance_layer(mut self, name: &str) -> Self {
+    push_unique(&mut self.optional_instance_layers, name);
+    self
+  }
+
+  pub fn with_required_instance_extension(mut self, name: &str) -> Self {
+    push_unique(&mut self.required_instance_extensions, name);
+    self
+  }
+
+  pub fn with_optional_instance_extension(mut self, name: &str) -> Self {
+    push_unique(&mut self.optional_instance_extensions, name);
+    self
+  }
+
+  pub fn with_required_device_extension(mut self, name: &str) -> Self {
+    push_unique(&mut self.required_device_extensions, name);
+    self
+  }
+
+  pub fn with_optional_device_extension(mut self, name: &str) -> Self {
+    push_unique(&mut self.optional_device_extensions, name);
+    self
+  }
+
+  /// validation is opt-in. when enabled it is silently skipped if the layer isn't installed,
+  /// check GFXHeadless::is_validation_enabled to find out if it made it
+  pub fn with_validation(self, enabled: bool) -> Self {
+    match enabled {
+      true => self.with_optional_instance_layer(constants::VALIDATION_LAYER),
+      false => self.without_instance_layer(constants::VALIDATION_LAYER),
+    }
+  }
+
+  /// send debug utils messages to the `log` facade, under the "vulkan" target. this is the default
+  pub fn with_debug_log(mut self) -> Self {
+    self.debug_output = Some(DebugOutput::Log);
+    self
+  }
+
+  /// send debug utils messages to a callback instead of the `log` facade
+  pub fn with_debug_callback(mut self, callback: impl Fn(&DebugMessage) + Send + Sync + 'static) -> Self {
+    self.debug_output = Some(DebugOutput::Callback(std::sync::Arc::new(callback)));
+    self
+  }
+
+  pub fn without_debug_messenger(mut self) -> Self {
+    self.debug_output = None;
+    self
+  }
+
+  /// which severities reach the messenger. defaults to WARNING | ERROR
+  pub fn with_debug_severity(mut self, severity: ash::vk::DebugUtilsMessageSeverityFlagsEXT) -> Self {
+    self.debug_severity = severity;
+    self
+  }
+
+  /// for tests. the first validation error is kept and panicked with by check_validation, which runs after every
+  /// submission that is waited on. creates a messenger logging to the `log` facade if there isn't one
+  pub fn with_panic_on_validation_error(mut self, panic_on_validation_error: bool) -> Self {
+    self.panic_on_validation_error = panic_on_validation_error;
+    self
+  }
+
+  /// for headless rendering with GFXHeadless::dynamic_rendering. it's enabled whenever the device has it regardless
+  pub fn with_dynamic_rendering_required(mut self, required: bool) -> Self {
+    self.require_dynamic_rendering = required;
+    self
+  }
+
+  /// force a physical device by index, name or vendor/device id. creation fails if that device is rejected,
+  /// see create_gfx::list_physical_devices for why
+  pub fn with_device_selector(mut self, device_selector: DeviceSelector) -> Self {
+    self.device_selector = device_selector;
+    self
+  }
+
+  /// uploads go through this queue when there is one, otherwise through the main queue
+  pub fn with_dedicated_transfer_queue(mut self, enabled: bool) -> Self {
+    self.dedicated_transfer_queue = enabled;
+    self
+  }
+
+  /// for async compute. falls back to the main queue when there isn't one
+  pub fn with_dedicated_compute_queue(mut self, enabled: bool) -> Self {
+    self.dedicated_compute_queue = enabled;
+    self
+  }
+
+  pub fn with_required_texture_compression(mut self, compression: TextureCompression) -> Self {
+    if !self.required_texture_compression.contains(&compression) { self.required_texture_compression.push(compression); }
+    self
+  }
+
+  /// the window can still be resized after it opens
+  pub fn with_window_size(mut self, width: u32, height: u32) -> Self {
+    self.window_size = (width, height);
+    self
+  }
+
+  pub fn with_present_preference(mut self, present_preference: PresentPreference) -> Self {
+    self.present_preference = present_preference;
+    self
+  }
+
+  /// shorthand for PresentPreference::Vsync, or back to the LowLatency default
+  pub fn with_vsync(self, enabled: bool) -> Self {
+    self.with_present_preference(if enabled { PresentPreference::Vsync } else { PresentPreference::LowLatency })
+  }
+
+  /// ask for three swapchain images rather than one more than the surface minimum, within what the surface allows
+  pub fn with_triple_buffering(mut self, enabled: bool) -> Self {
+    self.triple_buffering = enabled;
+    self
+  }
+
+  /// at least one
+  pub fn with_frames_in_flight(mut self, frames_in_flight: u32) -> Self {
+    self.frames_in_flight = frames_in_flight.max(1);
+    self
+  }
+
+  /// drops a layer from both the required and optional lists, e.g. to turn off a default
+  pub fn without_instance_layer(mut self, name: &str) -> Self {
+    self.required_instance_layers.retain(|layer| layer != name);
+    self.optional_instance_layers.retain(|layer| layer != name);
+    self
+  }
+
+  /// drops an extension from both the required and optional lists, e.g. to turn off a default
+  pub fn without_instance_extension(mut self, name: &str) -> Self {
+    self.required_instance_extensions.retain(|extension| extension != name);
+    self.optional_instance_extensions.retain(|extension| extension != name);
+    self
+  }
+
+  /// drops an extension from both the required and optional lists, e.g. to turn off a default
+  pub fn without_device_extension(mut self, name: &str) -> Self {
+    self.required_device_extensions.retain(|extension| extension != name);
+    self.optional_device_extensions.retain(|extension| extension != name);
+    self
+  }
+}
+
+fn get_if_validation_requested_by_env() -> bool {
+  match std::env::var(constants::VALIDATION_ENV_VAR) {
+    Ok(value) => value == "1" || value.eq_ignore_ascii_case("true"),
+    Err(_) => false,
+  }
+}
+
+fn push_unique(names: &mut Vec<String>, name: &str) -> () {
+  if !names.iter().any(|existing| existing == name) {
+    names.push(name.to_string());
+  }
+}
+
+/// the required names, plus whichever optional names pass `is_supported`. errors with the first unsupported required name
+pub fn resolve_names(required: &[String], optional: &[String], is_supported: impl Fn(&str) -> bool) -> Result<Vec<String>, String> {
+  let mut enabled = vec![];
+  for name in required.iter() {
+    if !is_supported(name) { return Err(name.clone()); }
+    push_unique(&mut enabled, name);
+  }
+  for name in optional.iter() {
+    if is_supported(name) { push_unique(&mut enabled, name); }
+  }
+  Ok(enabled)
+}
+
+#[test]
+fn test_resolve_names() {
+  let supported = ["a", "b", "c"];
+  let is_supported = |name: &str| supported.contains(&name);
+  let required = vec!["a".to_string()];
+  let optional = vec!["b".to_string(), "x".to_string(), "a".to_string()];
+  assert_eq!(resolve_names(&required, &optional, is_supported), Ok(vec!["a".to_string(), "b".to_string()]));
+
+  let required = vec!["a".to_string(), "y".to_string()];
+  assert_eq!(resolve_names(&required, &optional, is_supported), Err("y".to_string()));
+}
+
+#[test]
+fn test_builder_moves_names_between_lists() {
+  let config = GfxConfig::default()
+    .without_device_extension("VK_EXT_memory_budget")
+    .with_optional_device_extension("VK_EXT_memory_budget")
+    .with_optional_device_extension("VK_EXT_memory_budget");
+  assert!(!config.required_device_extensions().iter().any(|extension| extension == "VK_EXT_memory_budget"));
+  assert_eq!(config.optional_device_extensions(), &vec!["VK_EXT_memory_budget".to_string()]);
+}
+
+#[test]
+fn test_with_validation_is_optional() {
+  let config = GfxConfig::default().with_validation(true);
+  assert!(config.optional_instance_layers().iter().any(|layer| layer == constants::VALIDATION_LAYER));
+  assert!(!config.required_instance_layers().iter().any(|layer| layer == constants::VALIDATION_LAYER));
+
+  let config = config.with_validation(false);
+  assert!(!config.optional_instance_layers().iter().any(|layer| layer == constants::VALIDATION_LAYER));
+}
@@ -1,15 +1,42 @@
-pub static API_VERSION: u32 = ash::vk::API_VERSION_1_1;
+/// dynamic rendering is core from 1.3. configs can ask for less with GfxConfig::with_api_version, windows then need VK_KHR_dynamic_rendering
+pub static API_VERSION: u32 = ash::vk::API_VERSION_1_3;
 
-pub static REQUIRED_INSTANCE_LAYERS: [&str; 1] = ["VK_LAYER_KHRONOS_validation"];
+pub static VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
 
-pub static REQUIRED_DEVICE_LAYERS: [&str; 1] = ["VK_LAYER_KHRONOS_validation"];
+/// set to 1 (or true) to turn validation on without touching the config
+pub static VALIDATION_ENV_VAR: &str = "RAWDOG_VULKAN_VALIDATION";
 
-pub static REQUIRED_DEVICE_EXTENSIONS: [&str; 2] = ["VK_EXT_memory_budget", "VK_KHR_swapchain"];
+/// the allocator asks the driver for memory in blocks this big, bigger requests get a block of their own
+pub static MEMORY_BLOCK_SIZE: u64 = 64 * 1024 * 1024;
 
-pub static REQUIRED_INSTANCE_EXTENSIONS: [&str; 2] = ["VK_KHR_surface", "VK_EXT_debug_utils"];
+pub static REQUIRED_DEVICE_EXTENSIONS: [&str; 1] = ["VK_EXT_memory_budget"];
 
-#[derive(Debug, strum_macros::EnumIter)]
-pub enum MemoryKind {
-  Buffer1,
-  Image1
-}
\ No newline at end of file
+pub static REQUIRED_INSTANCE_EXTENSIONS: [&str; 1] = ["VK_EXT_debug_utils"];
+
+/// enabled whenever the device has it, commands::record_image_barriers falls back to the old barriers without it
+pub static SYNCHRONIZATION_2_EXTENSION: &str = "VK_KHR_synchronization2";
+
+/// enabled below vulkan 1.3, where dynamic rendering isn't core. see dynamic_rendering::get_dynamic_rendering_extensions
+pub static DYNAMIC_RENDERING_EXTENSION: &str = "VK_KHR_dynamic_rendering";
+
+/// what VK_KHR_dynamic_rendering needs below vulkan 1.2, where these aren't core either
+pub static DYNAMIC_RENDERING_EXTENSION_DEPENDENCIES: [&str; 2] = ["VK_KHR_depth_stencil_resolve", "VK_KHR_create_renderpass2"];
+
+/// only requested when presenting to a window
+pub static WINDOW_DEVICE_EXTENSIONS: [&str; 1] = ["VK_KHR_swapchain"];
+
+/// only requested when presenting to a window. ash_window adds the platform specific ones
+pub static WINDOW_INSTANCE_EXTENSIONS: [&str; 1] = ["VK_KHR_surface"];
+
+#[derive(Debug, Clone, Copy, PartialEq, strum_macros::EnumIter)]
+/// what a resource's memory is for. memory::get_memory_flags_from_intent turns this into required and preferred flags
+pub enum MemoryIntent {
+  /// only touched by the GPU. render targets, sampled textures, anything filled by a copy
+  GpuOnly,
+  /// written by the CPU and read by the GPU. staging buffers and per frame uploads
+  CpuToGpu,
+  /// written by the GPU and read back by the CPU. screenshots and readback buffers
+  GpuToCpu,
+  /// attachments that only live inside a render pass, lazily allocated where the driver supports it
+  Transient,
+}
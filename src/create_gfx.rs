@@ -1,4 +1,4 @@
-use crate::{constants, get_supported_surface_formats, get_target_surface_format, gfx_headless::GFXHeadless, gfx_window::GFXWindow, memory, utils};
+use crate::{allocator::Allocator, block_formats, config::{self, GfxConfig}, constants, debug::{self, DebugOutput, DebugState}, device_context::{DeviceContext, DeviceHandle, InstanceContext, SharedInstance}, device_selection::{self, DeviceCandidate}, dynamic_rendering::{self, DynamicRendering, DynamicRenderingSupport}, error::{GfxError, GfxResult}, get_supported_surface_formats, get_target_surface_format, gfx_headless::GFXHeadless, gfx_window::{GFXWindow, Surface}, memory, queues::{self, GfxQueue, QueueAssignment, QueueAssignments}, swapchain, utils};
 use std::{ffi::CString, io::Read, str::FromStr};
 extern crate itertools;
 extern crate strum;
@@ -8,285 +8,380 @@ use crate::utils::{cstr};
 use winit::{dpi::LogicalPosition, event::ElementState};
 use crate::{memory::{print_flags, split_flags, split_flags_u32}, utils::print_endianness};
 
-pub fn create_gfx() -> (GFXHeadless, GFXWindow, winit::event_loop::EventLoop<()>) {
-  let (image_bytes, image_width, image_height) = get_garfield_bytes();
-  let extent = 
-    ash::vk::Extent3D::default()
-    .width(image_width)
-    .height(image_height)
-    .depth(1);
-
+pub fn create_gfx(config: &GfxConfig) -> GfxResult<(GFXHeadless, GFXWindow, winit::event_loop::EventLoop<()>)> {
   // make window
-  let event_loop = winit::event_loop::EventLoop::new().expect("failed to create event loop");
+  let (window_width, window_height) = config.window_size();
+  let event_loop = winit::event_loop::EventLoop::new()?;
   let window = winit::window::WindowBuilder::new()
-    .with_inner_size(winit::dpi::LogicalSize::new(image_width, image_height))
+    .with_inner_size(winit::dpi::LogicalSize::new(*window_width, *window_height))
     .with_active(true)
-    .with_resizable(false)
+    .with_resizable(true)
     .with_decorations(true)
     .with_enabled_buttons(winit::window::WindowButtons::all())
     .with_transparent(false)
     .with_title("some window")
     .with_visible(false)
-    .build(&event_loop).expect("failed to create window");
-  let display_handle = window.display_handle().expect("failed to get display handle");
-  let window_handle = window.window_handle().expect("failed to get window handle");
+    .build(&event_loop)?;
+  let display_handle = window.display_handle()?;
+  let window_handle = window.window_handle()?;
 
-  // make entry, instance, device
-  let entry = create_entry();
-  let instance = create_instance(&entry, display_handle.into());
-  let (physical_device, device, main_queue_family_index) = create_device(&entry, &instance, &display_handle.into(), &window_handle.into());
-  let queue_index = 0; // only one queue for now
+  // every handle below is owned as soon as it's made, so returning early destroys whatever exists so far
 
-  // queue
-  let main_queue = get_queue(&device, main_queue_family_index, queue_index);
+  // make entry, instance
+  let entry = create_entry()?;
+  let CreatedInstance { instance, enabled_layers: enabled_instance_layers, enabled_extensions: enabled_instance_extensions } = create_instance(&entry, config, Some(display_handle.into()))?;
 
-  // command pool
-  let command_pool = create_command_pool(&device, main_queue_family_index);
+  // make a surface. needed before the device so we can check for presentation support
+  let surface = Surface::new(&instance, &display_handle.into(), &window_handle.into())?;
 
-  // make a surface
-  let surface_instance = create_surface_instance(&entry, &instance);
-  let surface = create_surface(&entry, &instance, &display_handle.into(), &window_handle.into());
+  // make device
+  let CreatedDevice { physical_device, mut device, enabled_extensions: enabled_device_extensions, enabled_texture_compression, queue_assignments, dynamic_rendering_support } = create_device(&instance.instance, config, Some((&surface.surface_instance, &surface)))?;
 
-  // make swapchain
-  let surface_format = get_target_surface_format(&physical_device, &surface_instance, &surface);
-  let (swapchain_device, swapchain) = create_swapchain(&instance, &physical_device, &device, &surface, &surface_instance, &extent, &surface_format);
+  // queues, each with its own command pool
+  let main_queue_family_index = queue_assignments.main.family_index;
+  let main_queue = get_queue(&device.device, main_queue_family_index, queue_assignments.main.queue_index);
+  let command_pool = create_command_pool(&mut device, main_queue_family_index)?;
+  let transfer_queue = create_gfx_queue(&mut device, queue_assignments.transfer)?;
+  let compute_queue = create_gfx_queue(&mut device, queue_assignments.compute)?;
+
+  // memory
+  let allocator = std::rc::Rc::new(std::cell::RefCell::new(Allocator::new(&instance.instance, &physical_device, &device.device, constants::MEMORY_BLOCK_SIZE)));
+  let synchronization2 = create_synchronization2_device(&instance.instance, &device.device, &enabled_device_extensions);
+  let dynamic_rendering = dynamic_rendering_support.map(|support| DynamicRendering::new(&instance.instance, &device.device, support));
+  let device = std::rc::Rc::new(DeviceContext { allocator, device, instance });
 
   let gfx_headless = GFXHeadless {
-    entry, 
-    instance, 
     physical_device, 
     device, 
     command_pool, 
     main_queue_family_index, 
     main_queue, 
+    transfer_queue,
+    compute_queue,
+    api_version: config.api_version(),
+    enabled_instance_layers,
+    enabled_instance_extensions,
+    enabled_device_extensions,
+    enabled_texture_compression,
+    synchronization2,
+    dynamic_rendering,
   };
 
-  let gfx_window = GFXWindow {
+  // make swapchain
+  let surface_format = get_target_surface_format(&gfx_headless.physical_device, &surface.surface_instance, &surface)?;
+  let swapchain_device = ash::khr::swapchain::Device::new(gfx_headless.instance(), &gfx_headless.device);
+  let swapchain_preferences = swapchain::SwapchainPreferences { present: *config.present_preference(), triple_buffering: config.triple_buffering() };
+  let (swapchain, swapchain_settings) = swapchain::create_swapchain(&gfx_headless.physical_device, &swapchain_device, &surface, &surface.surface_instance, &window.inner_size(), &surface_format, &swapchain_preferences, ash::vk::SwapchainKHR::null())?;
+
+  let surface_instance = surface.surface_instance.clone();
+  let mut gfx_window = GFXWindow {
     surface, 
     surface_instance, 
     swapchain, 
     swapchain_device, 
+    swapchain_images: vec![],
+    swapchain_image_views: vec![],
+    swapchain_settings,
+    swapchain_preferences,
+    physical_device: gfx_headless.physical_device,
     display_handle: display_handle.into(), 
     window_handle: window_handle.into(),
     window,
-    surface_format
+    surface_format: surface_format.format,
+    surface_color_space: surface_format.color_space,
+    device: gfx_headless.device.clone(),
   };
-  (gfx_headless, gfx_window, event_loop)
+  // filled in once the window owns the swapchain
+  gfx_window.swapchain_images = unsafe { gfx_window.swapchain_device.get_swapchain_images(swapchain)? };
+  gfx_window.swapchain_image_views = swapchain::create_swapchain_image_views(&gfx_headless.device, &gfx_window.swapchain_images, &surface_format.format)?;
+  Ok((gfx_headless, gfx_window, event_loop))
+}
+
+/// no window, no surface, no swapchain. for offscreen rendering and compute
+pub fn create_gfx_headless(config: &GfxConfig) -> GfxResult<GFXHeadless> {
+  // make entry, instance, device. each is owned as soon as it's made, so returning early destroys whatever exists so far
+  let entry = create_entry()?;
+  let CreatedInstance { instance, enabled_layers: enabled_instance_layers, enabled_extensions: enabled_instance_extensions } = create_instance(&entry, config, None)?;
+  let CreatedDevice { physical_device, mut device, enabled_extensions: enabled_device_extensions, enabled_texture_compression, queue_assignments, dynamic_rendering_support } = create_device(&instance.instance, config, None)?;
+
+  // queues, each with its own command pool
+  let main_queue_family_index = queue_assignments.main.family_index;
+  let main_queue = get_queue(&device.device, main_queue_family_index, queue_assignments.main.queue_index);
+  let command_pool = create_command_pool(&mut device, main_queue_family_index)?;
+  let transfer_queue = create_gfx_queue(&mut device, queue_assignments.transfer)?;
+  let compute_queue = create_gfx_queue(&mut device, queue_assignments.compute)?;
+
+  // memory
+  let allocator = std::rc::Rc::new(std::cell::RefCell::new(Allocator::new(&instance.instance, &physical_device, &device.device, constants::MEMORY_BLOCK_SIZE)));
+  let synchronization2 = create_synchronization2_device(&instance.instance, &device.device, &enabled_device_extensions);
+  let dynamic_rendering = dynamic_rendering_support.map(|support| DynamicRendering::new(&instance.instance, &device.device, support));
+  let device = std::rc::Rc::new(DeviceContext { allocator, device, instance });
+
+  Ok(GFXHeadless {
+    physical_device, 
+    device, 
+    command_pool, 
+    main_queue_family_index, 
+    main_queue, 
+    transfer_queue,
+    compute_queue,
+    api_version: config.api_version(),
+    enabled_instance_layers,
+    enabled_instance_extensions,
+    enabled_device_extensions,
+    enabled_texture_compression,
+    synchronization2,
+    dynamic_rendering,
+  })
 }
 
-fn get_garfield_bytes() -> (Vec<u8>, u32, u32) {
-  let img = 
-    image::ImageReader::open("./assets/garfield.png")
-    .expect("failed to read image")
-    .decode()
-    .expect("failed to decode image");
-  let width = img.width();
-  let height = img.height();
-  let bytes = img.into_rgba8().into_raw();
-  (bytes, width, height)
+/// every physical device, scored and with the reasons it would be rejected for headless use.
+/// creates and destroys its own instance, so it can be called before deciding on a config
+pub fn list_physical_devices(config: &GfxConfig) -> GfxResult<Vec<DeviceCandidate>> {
+  let entry = create_entry()?;
+  let config = config.clone().without_debug_messenger().with_panic_on_validation_error(false);
+  let instance = create_instance(&entry, &config, None)?.instance;
+  device_selection::list_device_candidates(&instance.instance, &config, config.required_device_extensions(), None)
 }
 
-fn create_entry() -> ash::Entry {
-  let entry = unsafe { ash::Entry::load().expect("Could not load Vulkan") };
-  entry
+fn create_entry() -> GfxResult<ash::Entry> {
+  let entry = unsafe { ash::Entry::load()? };
+  Ok(entry)
 }
 
-fn create_instance(entry: &ash::Entry, display_handle: raw_window_handle::RawDisplayHandle) -> ash::Instance {
+/// the instance along with its debug messenger if there is one, and the layers and extensions that were actually enabled
+struct CreatedInstance {
+  instance: SharedInstance,
+  enabled_layers: Vec<String>,
+  enabled_extensions: Vec<String>,
+}
+
+/// pass a display handle to enable the surface extensions, or None for headless
+fn create_instance(entry: &ash::Entry, config: &GfxConfig, display_handle: Option<raw_window_handle::RawDisplayHandle>) -> GfxResult<CreatedInstance> {
   // application info
-  let application_name = cstr("My Application");
-  let application_version = 1;
-  let engine_name = cstr("My Engine");
-  let engine_version = 1;
+  let application_name = cstr(config.application_name());
+  let application_version = config.application_version();
+  let engine_name = cstr(config.engine_name());
+  let engine_version = config.engine_version();
   
   let application_info = ash::vk::ApplicationInfo::default()
     .application_name(&application_name)
     .application_version(application_version)
     .engine_name(&engine_name)
     .engine_version(engine_version)
-    .api_version(constants::API_VERSION);
+    .api_version(config.api_version());
   
-  // check that required layers are supported
-  let layers = unsafe { entry.enumerate_instance_layer_properties().expect("failed to enumerate instance layer properties") };
-  let assert_layer_supported = |layer_name: &str| {
-    let has = layers.iter().any(|layer| {
+  // check that required layers are supported, and which optional ones are
+  let layers = unsafe { entry.enumerate_instance_layer_properties()? };
+  let layer_supported = |layer_name: &str| {
+    layers.iter().any(|layer| {
       let name = layer.layer_name_as_c_str().expect("could not get layer name").to_str().expect("could not convert layer name to &str");
       name == layer_name
-    });
-    assert!(has, "instance layer {} is not supported", layer_name);
+    })
   };
-  constants::REQUIRED_INSTANCE_LAYERS.iter().for_each(|layer| assert_layer_supported(layer));
+  let enabled_layers = 
+    config::resolve_names(config.required_instance_layers(), config.optional_instance_layers(), layer_supported)
+    .map_err(GfxError::MissingInstanceLayer)?;
 
-  // check that required extensions are supported
-  let extensions = unsafe { entry.enumerate_instance_extension_properties(None).expect("failed to enumerate instance extension properties") };
+  // check that required extensions are supported, and which optional ones are
+  let extensions = unsafe { entry.enumerate_instance_extension_properties(None)? };
   // extensions.iter().for_each(|extension| {
   //   let name = extension.extension_name_as_c_str().expect("could not get extension name").to_str().expect("could not convert extension name to &str");
   //   println!("instance extension {}", name);
   // });
-  let assert_extension_supported = |extension_name: &str| {
-    let has = extensions.iter().any(|extension| {
+  let extension_supported = |extension_name: &str| {
+    extensions.iter().any(|extension| {
       let name = extension.extension_name_as_c_str().expect("could not get extension name").to_str().expect("could not convert extension name to &str");
       name == extension_name
-    });
-    assert!(has, "instance extension {} is not supported", extension_name);
+    })
   };
-  constants::REQUIRED_INSTANCE_EXTENSIONS.iter().for_each(|extension| assert_extension_supported(extension));
 
-  // ash window instance extensions
-  let ash_window_instance_extensions = ash_window::enumerate_required_extensions(display_handle).expect("failed to enumerate ash window required extensions");
-  ash_window_instance_extensions.iter().for_each(|extension| {
-    let weird_extension_name = extension;
-    let extension_name_as_str = utils::ptr_to_str(weird_extension_name);
-    assert_extension_supported(extension_name_as_str)
-  });
-  let ash_window_instance_extensions_strs = ash_window_instance_extensions.iter().map(|extension| utils::ptr_to_str(extension)).collect_vec();
+  // window instance extensions. skipped entirely when headless
+  let window_instance_extensions = match display_handle {
+    None => vec![],
+    Some(display_handle) => {
+      // ash window instance extensions
+      let ash_window_instance_extensions = ash_window::enumerate_required_extensions(display_handle)?;
+      let ash_window_instance_extensions_strs = ash_window_instance_extensions.iter().map(|extension| utils::ptr_to_str(extension)).collect_vec();
+      constants::WINDOW_INSTANCE_EXTENSIONS.iter().copied()
+      .chain(ash_window_instance_extensions_strs)
+      .map(|str| str.to_string())
+      .collect_vec()
+    }
+  };
+  let required_extensions = config.required_instance_extensions().iter().chain(window_instance_extensions.iter()).cloned().collect_vec();
+  let enabled_extensions = 
+    config::resolve_names(&required_extensions, config.optional_instance_extensions(), extension_supported)
+    .map_err(GfxError::MissingInstanceExtension)?;
+
+  // debug messenger. needs debug utils, which could have been configured away
+  let debug_utils_enabled = enabled_extensions.iter().any(|extension| extension == "VK_EXT_debug_utils");
+  let debug_output = match (config.debug_output(), config.panic_on_validation_error()) {
+    (Some(output), _) => Some(output.clone()),
+    (None, true) => Some(DebugOutput::Log),
+    (None, false) => None,
+  };
+  let debug_state = match debug_output {
+    Some(output) if debug_utils_enabled => Some(Box::new(DebugState::new(output, config.panic_on_validation_error()))),
+    _ => None,
+  };
+  // chained onto the instance create info so messages from instance creation itself are caught
+  let mut debug_create_info = debug_state.as_ref().map(|state| debug::get_debug_messenger_create_info(state, *config.debug_severity()));
 
   // instance create info
   let flags = ash::vk::InstanceCreateFlags::empty();
-  let layer_cstrs = constants::REQUIRED_INSTANCE_LAYERS.iter().map(|str| cstr(str)).collect_vec();
+  let layer_cstrs = enabled_layers.iter().map(|str| cstr(str)).collect_vec();
   let layer_ptrs: Vec<*const i8> = layer_cstrs.iter().map(|s| s.as_ptr()).collect();
-  let extension_strs = 
-    constants::REQUIRED_INSTANCE_EXTENSIONS.iter()
-    .chain(ash_window_instance_extensions_strs.iter())
-    .unique()
-    .collect_vec();
-    ;
-  let extension_cstrs = extension_strs.iter().map(|str| cstr(str)).collect_vec();
+  let extension_cstrs = enabled_extensions.iter().map(|str| cstr(str)).collect_vec();
   let extension_ptrs: Vec<*const i8> = extension_cstrs.iter().map(|s| s.as_ptr()).collect();
-  let instance_create_info = ash::vk::InstanceCreateInfo::default()
+  let mut instance_create_info = ash::vk::InstanceCreateInfo::default()
     .flags(flags)
     .application_info(&application_info)
     .enabled_layer_names(&layer_ptrs)
     .enabled_extension_names(&extension_ptrs);
+  if let Some(debug_create_info) = debug_create_info.as_mut() {
+    instance_create_info = instance_create_info.push_next(debug_create_info);
+  }
 
   // create instance
-  let instance = unsafe { entry.create_instance(&instance_create_info, None).expect("Could not create Vulkan instance") };
-  instance
+  let instance = unsafe { entry.create_instance(&instance_create_info, None)? };
+  let mut instance = InstanceContext { entry: entry.clone(), instance, debug_messenger: None };
+
+  // the long lived messenger, for everything after instance creation
+  if let Some(state) = debug_state {
+    instance.debug_messenger = Some(debug::create_debug_messenger(entry, &instance.instance, state, *config.debug_severity())?);
+  }
+  Ok(CreatedInstance { instance: std::rc::Rc::new(instance), enabled_layers, enabled_extensions })
 }
 
-fn create_device(entry: &ash::Entry, instance: &ash::Instance, display_handle: &raw_window_handle::RawDisplayHandle, window_handle: &raw_window_handle::RawWindowHandle) -> (ash::vk::PhysicalDevice, ash::Device, u32) {
-  // physical device
-  let physical_devices = unsafe { instance.enumerate_physical_devices().expect("failed to enumerate physical devices") };
-  // assert that there is at least one physical device
-  assert!(physical_devices.len() > 0, "no physical devices found");
-  // find the first suitable device
-  let physical_device = *physical_devices.iter().find(|&physical_device| {
-    let properties = unsafe { instance.get_physical_device_properties(*physical_device) };
-
-    // number of memory allocations
-    if properties.limits.max_memory_allocation_count < 1 { return false; }
-
-    // memory flags
-    if !memory::get_if_physical_device_supports_all_memory_requirements(instance, physical_device) { return false; }
-
-    // supported image formats
-    let req_formats = [ash::vk::Format::R8G8B8A8_UNORM];
-    if !req_formats.iter().all(|&req_format|
-      {
-        let props = unsafe {
-          instance.get_physical_device_format_properties(*physical_device, req_format)
-        };
-
-        let flags = ash::vk::FormatFeatureFlags::SAMPLED_IMAGE;
-
-        let pass_linear = props.linear_tiling_features & flags == flags;
-        let pass_optimal = props.optimal_tiling_features & flags == flags;
-  
-        pass_linear && pass_optimal
-      }
-    ) { return false; }
-
-    // check vulkan version
-    let required_vulkan_version = constants::API_VERSION;
-    let supported_version = properties.api_version;
-    if supported_version < required_vulkan_version { return false; }
-
-    let limits = properties.limits;
-    let max_image_size = limits.max_image_dimension2_d;
-    // dbg!(limits.buffer_image_granularity);
-    // dbg!(limits.non_coherent_atom_size);
-
-    let sparse_properties = properties.sparse_properties;
-
-    let features = unsafe { instance.get_physical_device_features(*physical_device) };
-
-    let device_memory_properties = unsafe { instance.get_physical_device_memory_properties(*physical_device) };
-
-    let memory_types = device_memory_properties.memory_types_as_slice();
-
-    // let physical_device_format_properties = unsafe { instance.get_physical_device_format_properties(*physical_device, format) };
-
-    // let physical_device_image_properties = unsafe { instance.get_physical_device_image_format_properties(*physical_device, format) };
-
-    // check for required device layers
-    let layers = unsafe { instance.enumerate_device_layer_properties(*physical_device).expect("failed to enumerate device layer properties") };
-    let layer_supported = |layer_name: &str| {
-      layers.iter().any(|layer| {
-        let name = layer.layer_name_as_c_str().expect("could not get layer name").to_str().expect("could not convert layer name to &str");
-        name == layer_name
-      })
-    };
-    let all_layers_supported = constants::REQUIRED_DEVICE_LAYERS.iter().all(|layer| layer_supported(layer));
-    if !all_layers_supported { return false; }
+/// the device, the extensions and texture compression that were actually enabled, which queues were created
+/// and how dynamic rendering was enabled, if at all
+struct CreatedDevice {
+  physical_device: ash::vk::PhysicalDevice,
+  device: DeviceHandle,
+  enabled_extensions: Vec<String>,
+  enabled_texture_compression: Vec<block_formats::TextureCompression>,
+  queue_assignments: QueueAssignments,
+  dynamic_rendering_support: Option<DynamicRenderingSupport>,
+}
 
-    // check for required device extensions
-    let extensions = unsafe { instance.enumerate_device_extension_properties(*physical_device).expect("failed to enumerate device extension properties") };
-    let extension_supported = |extension_name: &str| {
-      extensions.iter().any(|extension| {
-        let name = extension.extension_name_as_c_str().expect("could not get extension name").to_str().expect("could not convert extension name to &str");
-        name == extension_name
-      })
-    };
-    let all_extensions_supported = constants::REQUIRED_DEVICE_EXTENSIONS.iter().all(|extension| extension_supported(extension));
-    if !all_extensions_supported { return false; }
+/// pass a surface to require presentation support and the swapchain extension, or None for headless
+fn create_device(instance: &ash::Instance, config: &GfxConfig, surface: Option<(&ash::khr::surface::Instance, &ash::vk::SurfaceKHR)>) -> GfxResult<CreatedDevice> {
+  let window_device_extensions = match surface {
+    None => vec![],
+    Some(_) => constants::WINDOW_DEVICE_EXTENSIONS.iter().map(|str| str.to_string()).collect_vec(),
+  };
+  let required_device_extensions = config.required_device_extensions().iter().chain(window_device_extensions.iter()).cloned().collect_vec();
 
-    true // device is adequate
-  }).expect("no physical device satisifies the requirements of this application");
+  // physical device. the best scoring one, unless the config forces a particular device
+  let candidates = device_selection::list_device_candidates(instance, config, &required_device_extensions, surface)?;
+  let physical_device = *device_selection::select_device_candidate(&candidates, config.device_selector())?.physical_device();
 
   let queue_family_properties = unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
   // find index of first suitable queue family
-  assert!(queue_family_properties.len() > 0, "no queue families found");
-  let queue_family_index = queue_family_properties.iter().enumerate().position(|(i, &properties)| {
-    if properties.queue_count < 1 { return false; }
-    let req_flags = [
-      ash::vk::QueueFlags::GRAPHICS, 
-      ash::vk::QueueFlags::COMPUTE, 
-      ash::vk::QueueFlags::TRANSFER,
-    ];
-    for req_flag in req_flags.iter() {
-      if !properties.queue_flags.contains(*req_flag) { return false; }
+  let mut adequate_queue_family_index = None;
+  for (i, properties) in queue_family_properties.iter().enumerate() {
+    if get_if_queue_family_adequate(&physical_device, i as u32, properties, surface)? {
+      adequate_queue_family_index = Some(i);
+      break;
     }
-
-    // check for surface / presentation support
-    let surface_instance = ash::khr::surface::Instance::new(entry, instance);
-    let surface = create_surface(entry, instance, display_handle, window_handle);
-    let surface_support = unsafe { surface_instance.get_physical_device_surface_support(physical_device, i as u32, surface).expect("failed to get physical device surface support") };
-    if !surface_support { return false; }
-    unsafe { surface_instance.destroy_surface(surface, None); }
-
-    true // queue family is adequate
-  }).expect("no queue family satisifies the requirements of this application");
-
-  // queue create info
-  let queue_priorities = [1.0];
-  let main_queue =  ash::vk::DeviceQueueCreateInfo::default()
-    .queue_family_index(queue_family_index as u32)
-    .queue_priorities(&queue_priorities);
-
-  let queue_create_infos = vec![main_queue];
+  }
+  let queue_family_index = adequate_queue_family_index.ok_or(GfxError::NoSuitableQueueFamily)?;
+
+  // the main queue, plus dedicated transfer and compute queues if asked for and available
+  let queue_assignments = queues::get_queue_assignments(&queue_family_properties, queue_family_index as u32, config.dedicated_transfer_queue(), config.dedicated_compute_queue());
+  let queue_counts = queue_assignments.get_queue_counts();
+  let queue_priorities = queue_counts.iter().map(|(_, count)| vec![1.0; *count as usize]).collect_vec();
+  let queue_create_infos = queue_counts.iter().zip(queue_priorities.iter()).map(|((family_index, _), priorities)| {
+    ash::vk::DeviceQueueCreateInfo::default()
+      .queue_family_index(*family_index)
+      .queue_priorities(priorities)
+  }).collect_vec();
+
+  // required extensions are known to be supported by now, this picks up the optional ones
+  let supported_extensions = get_device_extension_names(instance, &physical_device)?;
+  let mut enabled_extensions = 
+    config::resolve_names(&required_device_extensions, config.optional_device_extensions(), |name| supported_extensions.iter().any(|supported| supported == name))
+    .map_err(|_| GfxError::NoSuitablePhysicalDevice)?;
+
+  // synchronization2 whenever the device has it, like texture compression below
+  let synchronization2 = get_if_synchronization2_supported(instance, &physical_device, &supported_extensions);
+  let mut automatic_extensions = if synchronization2 { vec![constants::SYNCHRONIZATION_2_EXTENSION] } else { vec![] };
+  // dynamic rendering too. device selection has already rejected devices without it if it's required. below 1.3 it's an extension
+  let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+  let api_version = properties.api_version.min(config.api_version());
+  let dynamic_rendering_support = dynamic_rendering::get_usable_dynamic_rendering_support(instance, &physical_device, api_version, &supported_extensions);
+  if let Some(support) = dynamic_rendering_support { automatic_extensions.extend(dynamic_rendering::get_dynamic_rendering_extensions(support, api_version)); }
+  for name in automatic_extensions {
+    if !enabled_extensions.iter().any(|enabled| enabled == name) { enabled_extensions.push(name.to_string()); }
+  }
 
   // device create info
-  let extension_strs: Vec<&str> = constants::REQUIRED_DEVICE_EXTENSIONS.into_iter().collect_vec();
-  let extension_cstrs = extension_strs.iter().map(|str| cstr(str)).collect_vec();
+  let extension_cstrs = enabled_extensions.iter().map(|str| cstr(str)).collect_vec();
   let extension_ptrs: Vec<*const i8> = extension_cstrs.iter().map(|s| s.as_ptr()).collect();
-  let device_features = ash::vk::PhysicalDeviceFeatures::default();
-  let device_create_info = ash::vk::DeviceCreateInfo::default()
+  // every texture compression the device has, so compressed textures rarely need decoding on the CPU
+  let enabled_texture_compression = block_formats::get_supported_texture_compression(instance, &physical_device);
+  let device_features = enabled_texture_compression.iter()
+    .fold(ash::vk::PhysicalDeviceFeatures::default(), |features, compression| compression.enable(features));
+  // features from extensions and newer versions go on the pNext chain, each only when it's there to enable
+  let mut synchronization2_features = ash::vk::PhysicalDeviceSynchronization2Features::default().synchronization2(true);
+  let mut dynamic_rendering_features = ash::vk::PhysicalDeviceDynamicRenderingFeatures::default().dynamic_rendering(true);
+  let mut device_create_info = ash::vk::DeviceCreateInfo::default()
     .queue_create_infos(&queue_create_infos)
     .enabled_extension_names(&extension_ptrs)
     .enabled_features(&device_features);
+  if dynamic_rendering_support.is_some() { device_create_info = device_create_info.push_next(&mut dynamic_rendering_features); }
+  if synchronization2 { device_create_info = device_create_info.push_next(&mut synchronization2_features); }
 
   // create device
-  let device = unsafe { instance.create_device(physical_device, &device_create_info, None).expect("Could not create Vulkan device") };
-  (physical_device, device, queue_family_index as u32)
+  let device = unsafe { instance.create_device(physical_device, &device_create_info, None)? };
+  let device = DeviceHandle { device, command_pools: vec![] };
+  Ok(CreatedDevice { physical_device, device, enabled_extensions, enabled_texture_compression, queue_assignments, dynamic_rendering_support })
+}
+
+/// the extension and its feature
+fn get_if_synchronization2_supported(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice, supported_extensions: &[String]) -> bool {
+  if !supported_extensions.iter().any(|name| name == constants::SYNCHRONIZATION_2_EXTENSION) { return false; }
+  let mut synchronization2_features = ash::vk::PhysicalDeviceSynchronization2Features::default();
+  let mut features = ash::vk::PhysicalDeviceFeatures2::default().push_next(&mut synchronization2_features);
+  unsafe { instance.get_physical_device_features2(*physical_device, &mut features); }
+  synchronization2_features.synchronization2 == ash::vk::TRUE
+}
+
+/// a loader for the extension's commands, if create_device enabled it
+fn create_synchronization2_device(instance: &ash::Instance, device: &ash::Device, enabled_device_extensions: &[String]) -> Option<ash::khr::synchronization2::Device> {
+  match enabled_device_extensions.iter().any(|name| name == constants::SYNCHRONIZATION_2_EXTENSION) {
+    true => Some(ash::khr::synchronization2::Device::new(instance, device)),
+    false => None,
+  }
+}
+
+pub fn get_device_extension_names(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice) -> GfxResult<Vec<String>> {
+  let extensions = unsafe { instance.enumerate_device_extension_properties(*physical_device)? };
+  let names = extensions.iter().map(|extension| {
+    extension.extension_name_as_c_str().expect("could not get extension name").to_str().expect("could not convert extension name to &str").to_string()
+  }).collect_vec();
+  Ok(names)
+}
+
+pub fn get_if_queue_family_adequate(physical_device: &ash::vk::PhysicalDevice, queue_family_index: u32, properties: &ash::vk::QueueFamilyProperties, surface: Option<(&ash::khr::surface::Instance, &ash::vk::SurfaceKHR)>) -> GfxResult<bool> {
+  if properties.queue_count < 1 { return Ok(false); }
+  let req_flags = [
+    ash::vk::QueueFlags::GRAPHICS, 
+    ash::vk::QueueFlags::COMPUTE, 
+    ash::vk::QueueFlags::TRANSFER,
+  ];
+  for req_flag in req_flags.iter() {
+    if !properties.queue_flags.contains(*req_flag) { return Ok(false); }
+  }
+
+  // check for surface / presentation support
+  if let Some((surface_instance, surface)) = surface {
+    let surface_support = unsafe { surface_instance.get_physical_device_surface_support(*physical_device, queue_family_index, *surface)? };
+    if !surface_support { return Ok(false); }
+  }
+
+  Ok(true) // queue family is adequate
 }
 
 fn get_queue(device: &ash::Device, queue_family_index: u32, queue_index: u32) -> ash::vk::Queue { 
@@ -294,53 +389,23 @@ fn get_queue(device: &ash::Device, queue_family_index: u32, queue_index: u32) ->
   return queue;
 }
 
-fn create_command_pool(device: &ash::Device, queue_family_index: u32) -> ash::vk::CommandPool {
-  let flags = ash::vk::CommandPoolCreateFlags::empty();
+fn create_gfx_queue(device: &mut DeviceHandle, assignment: Option<QueueAssignment>) -> GfxResult<Option<GfxQueue>> {
+  let Some(assignment) = assignment else { return Ok(None); };
+  let queue = get_queue(&device.device, assignment.family_index, assignment.queue_index);
+  let command_pool = create_command_pool(device, assignment.family_index)?;
+  Ok(Some(GfxQueue { family_index: assignment.family_index, queue_index: assignment.queue_index, queue, command_pool }))
+}
+
+/// the pool is destroyed with the device
+fn create_command_pool(device: &mut DeviceHandle, queue_family_index: u32) -> GfxResult<ash::vk::CommandPool> {
+  // frames::FramesInFlight re-records the same command buffers every frame
+  let flags = ash::vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER;
   let create_info = ash::vk::CommandPoolCreateInfo::default()
     .flags(flags)
     .queue_family_index(queue_family_index)
     .flags(flags);
-  let command_pool = unsafe { device.create_command_pool(&create_info, None).expect("failed to create command pool") };
-  return command_pool;
-}
-
-fn create_surface_instance(entry: &ash::Entry, instance: &ash::Instance) -> ash::khr::surface::Instance {
-  let surface = ash::khr::surface::Instance::new(&entry, &instance);
-  surface
-}
-
-fn create_surface(entry: &ash::Entry, instance: &ash::Instance, display_handle: &raw_window_handle::RawDisplayHandle, window_handle: &raw_window_handle::RawWindowHandle) -> ash::vk::SurfaceKHR {
-  let surface = unsafe { ash_window::create_surface(entry, instance, *display_handle, *window_handle, None).expect("failed to create surface") };
-  surface
+  let command_pool = unsafe { device.device.create_command_pool(&create_info, None)? };
+  device.command_pools.push(command_pool);
+  return Ok(command_pool);
 }
 
-fn create_swapchain(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice, device: &ash::Device, surface: &ash::vk::SurfaceKHR, surface_instance: &ash::khr::surface::Instance, extent: &ash::vk::Extent3D, surface_format: &ash::vk::Format) -> (ash::khr::swapchain::Device, ash::vk::SwapchainKHR) {
-  let swapchain_device = ash::khr::swapchain::Device::new(instance, device);
-  let physical_device_surface_capabilities = unsafe { surface_instance.get_physical_device_surface_capabilities(*physical_device, *surface).expect("failed to get physical device surface capabilities") };
-  let max_images = physical_device_surface_capabilities.min_image_count;
-  let desired_image_count = max_images;
-  let supported_usage_flags = physical_device_surface_capabilities.supported_usage_flags;
-  let usage_flags = ash::vk::ImageUsageFlags::COLOR_ATTACHMENT | ash::vk::ImageUsageFlags::TRANSFER_DST;
-  assert!(usage_flags & supported_usage_flags == usage_flags, "at least one image usage flag is not supported");
-  let color_space = ash::vk::ColorSpaceKHR::SRGB_NONLINEAR;
-  let pre_transform = ash::vk::SurfaceTransformFlagsKHR::IDENTITY;
-  let present_mode = ash::vk::PresentModeKHR::MAILBOX;
-  let extent = ash::vk::Extent2D::default().width(extent.width).height(extent.height);
-  let create_info =  
-    ash::vk::SwapchainCreateInfoKHR::default()
-    .surface(*surface)
-    .min_image_count(desired_image_count)
-    .image_color_space(color_space)
-    .image_format(*surface_format)
-    .image_extent(extent)
-    .image_usage(usage_flags)
-    .image_sharing_mode(ash::vk::SharingMode::EXCLUSIVE)
-    .pre_transform(pre_transform)
-    .composite_alpha(ash::vk::CompositeAlphaFlagsKHR::OPAQUE)
-    .present_mode(present_mode)
-    .clipped(true)
-    .image_array_layers(1);
-    ;
-  let swapchain = unsafe { swapchain_device.create_swapchain(&create_info, None).expect("failed to create swapchain") };
-  (swapchain_device, swapchain)
-}
\ No newline at end of file
@@ -0,0 +1,159 @@
+use proc_macros::{Getters};
+use crate::error::GfxResult;
+
+/// one message from the debug utils messenger, copied out of the callback data
+#[derive(Debug, Clone)]
+pub struct DebugMessage {
+  pub severity: ash::vk::DebugUtilsMessageSeverityFlagsEXT,
+  pub message_type: ash::vk::DebugUtilsMessageTypeFlagsEXT,
+  pub message_id_name: String,
+  pub message_id_number: i32,
+  pub message: String,
+  /// "TYPE 0xhandle name" for each object the message is about. name is empty if it was never set
+  pub object_names: Vec<String>,
+}
+
+pub type DebugCallback = std::sync::Arc<dyn Fn(&DebugMessage) + Send + Sync>;
+
+/// where messages from the messenger end up
+#[derive(Clone)]
+pub enum DebugOutput {
+  /// through the `log` facade. does nothing unless the application installs a logger
+  Log,
+  Callback(DebugCallback),
+}
+
+impl std::fmt::Debug for DebugOutput {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      DebugOutput::Log => write!(f, "Log"),
+      DebugOutput::Callback(_) => write!(f, "Callback"),
+    }
+  }
+}
+
+/// lives on the heap so the pointer handed to vulkan as user data stays put
+pub struct DebugState {
+  pub output: DebugOutput,
+  pub panic_on_validation_error: bool,
+  // the first validation error seen in panic mode, for check_validation to panic with outside the callback
+  pub validation_error: std::sync::Mutex<Option<String>>,
+}
+
+impl DebugState {
+  pub fn new(output: DebugOutput, panic_on_validation_error: bool) -> Self {
+    DebugState { output, panic_on_validation_error, validation_error: std::sync::Mutex::new(None) }
+  }
+
+  pub fn get_validation_error(&self) -> Option<String> {
+    self.validation_error.lock().ok().and_then(|validation_error| validation_error.clone())
+  }
+}
+
+#[derive(Getters)]
+/// the messenger and everything it needs to stay alive. destroy before the instance
+pub struct DebugMessenger {
+  pub debug_utils_instance: ash::ext::debug_utils::Instance,
+  pub messenger: ash::vk::DebugUtilsMessengerEXT,
+  #[Getters_Skip]
+  pub state: Box<DebugState>,
+}
+
+/// the state pointer must outlive anything created from the returned info
+pub fn get_debug_messenger_create_info<'a>(state: &DebugState, severity: ash::vk::DebugUtilsMessageSeverityFlagsEXT) -> ash::vk::DebugUtilsMessengerCreateInfoEXT<'a> {
+  let message_type =
+    ash::vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+    | ash::vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+    | ash::vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE;
+  let create_info = ash::vk::DebugUtilsMessengerCreateInfoEXT::default()
+    .message_severity(severity)
+    .message_type(message_type)
+    .pfn_user_callback(Some(debug_callback))
+    .user_data(state as *const DebugState as *mut std::ffi::c_void);
+  create_info
+}
+
+pub fn create_debug_messenger(entry: &ash::Entry, instance: &ash::Instance, state: Box<DebugState>, severity: ash::vk::DebugUtilsMessageSeverityFlagsEXT) -> GfxResult<DebugMessenger> {
+  let debug_utils_instance = ash::ext::debug_utils::Instance::new(entry, instance);
+  let create_info = get_debug_messenger_create_info(&state, severity);
+  let messenger = unsafe { debug_utils_instance.create_debug_utils_messenger(&create_info, None)? };
+  Ok(DebugMessenger { debug_utils_instance, messenger, state })
+}
+
+pub fn destroy_debug_messenger(debug_messenger: &DebugMessenger) -> () {
+  unsafe { debug_messenger.debug_utils_instance.destroy_debug_utils_messenger(debug_messenger.messenger, None); }
+}
+
+/// panicking can't unwind back through the driver, so in panic mode the first validation error is only recorded.
+/// DeviceContext::check_validation panics with it on the rust side
+unsafe extern "system" fn debug_callback(
+  message_severity: ash::vk::DebugUtilsMessageSeverityFlagsEXT,
+  message_types: ash::vk::DebugUtilsMessageTypeFlagsEXT,
+  p_callback_data: *const ash::vk::DebugUtilsMessengerCallbackDataEXT<'_>,
+  p_user_data: *mut std::ffi::c_void,
+) -> ash::vk::Bool32 {
+  if p_callback_data.is_null() || p_user_data.is_null() { return ash::vk::FALSE; }
+  let state = unsafe { &*(p_user_data as *const DebugState) };
+  let callback_data = unsafe { &*p_callback_data };
+  let message = unsafe { get_debug_message(message_severity, message_types, callback_data) };
+
+  match &state.output {
+    DebugOutput::Log => log_debug_message(&message),
+    DebugOutput::Callback(callback) => callback(&message),
+  }
+
+  let is_validation = message_types.contains(ash::vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION);
+  let is_error = message_severity.contains(ash::vk::DebugUtilsMessageSeverityFlagsEXT::ERROR);
+  if state.panic_on_validation_error && is_validation && is_error {
+    // a poisoned lock means a callback already panicked, there is nothing better to do than drop the message
+    if let Ok(mut validation_error) = state.validation_error.lock() {
+      validation_error.get_or_insert_with(|| format_debug_message(&message));
+    }
+  }
+
+  ash::vk::FALSE // never abort the vulkan call itself
+}
+
+unsafe fn get_debug_message(
+  severity: ash::vk::DebugUtilsMessageSeverityFlagsEXT,
+  message_type: ash::vk::DebugUtilsMessageTypeFlagsEXT,
+  callback_data: &ash::vk::DebugUtilsMessengerCallbackDataEXT<'_>,
+) -> DebugMessage {
+  let to_string = |ptr: *const std::ffi::c_char| {
+    if ptr.is_null() { return String::new(); }
+    unsafe { std::ffi::CStr::from_ptr(ptr) }.to_string_lossy().into_owned()
+  };
+  let objects = match callback_data.p_objects.is_null() {
+    true => &[][..],
+    false => unsafe { std::slice::from_raw_parts(callback_data.p_objects, callback_data.object_count as usize) },
+  };
+  let object_names = objects.iter().map(|object| {
+    format!("{:?} 0x{:x} {}", object.object_type, object.object_handle, to_string(object.p_object_name))
+  }).collect();
+  DebugMessage {
+    severity,
+    message_type,
+    message_id_name: to_string(callback_data.p_message_id_name),
+    message_id_number: callback_data.message_id_number,
+    message: to_string(callback_data.p_message),
+    object_names,
+  }
+}
+
+pub fn format_debug_message(message: &DebugMessage) -> String {
+  let mut formatted = format!("[{:?}] {} ({}): {}", message.message_type, message.message_id_name, message.message_id_number, message.message);
+  for object_name in message.object_names.iter() {
+    formatted.push_str(&format!("\n  object: {}", object_name));
+  }
+  formatted
+}
+
+fn log_debug_message(message: &DebugMessage) -> () {
+  let level = match message.severity {
+    severity if severity.contains(ash::vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) => log::Level::Error,
+    severity if severity.contains(ash::vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) => log::Level::Warn,
+    severity if severity.contains(ash::vk::DebugUtilsMessageSeverityFlagsEXT::INFO) => log::Level::Info,
+    _ => log::Level::Debug,
+  };
+  log::log!(target: "vulkan", level, "{}", format_debug_message(message));
+}
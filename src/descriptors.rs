@@ -0,0 +1,44 @@
+// writes one descriptor at a time. fine for the handful of sets made at startup, batch them if that changes
+
+/// the image has to be in SHADER_READ_ONLY_OPTIMAL when it is sampled
+pub fn write_sampled_image(device: &ash::Device, descriptor_set: &ash::vk::DescriptorSet, binding: u32, image_view: &ash::vk::ImageView) -> () {
+  let image_info = [ash::vk::DescriptorImageInfo::default().image_view(*image_view).image_layout(ash::vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+  let write = ash::vk::WriteDescriptorSet::default()
+    .dst_set(*descriptor_set)
+    .dst_binding(binding)
+    .descriptor_type(ash::vk::DescriptorType::SAMPLED_IMAGE)
+    .image_info(&image_info);
+  unsafe { device.update_descriptor_sets(&[write], &[]); }
+}
+
+pub fn write_sampler(device: &ash::Device, descriptor_set: &ash::vk::DescriptorSet, binding: u32, sampler: &ash::vk::Sampler) -> () {
+  let image_info = [ash::vk::DescriptorImageInfo::default().sampler(*sampler)];
+  let write = ash::vk::WriteDescriptorSet::default()
+    .dst_set(*descriptor_set)
+    .dst_binding(binding)
+    .descriptor_type(ash::vk::DescriptorType::SAMPLER)
+    .image_info(&image_info);
+  unsafe { device.update_descriptor_sets(&[write], &[]); }
+}
+
+/// storage images are read and written in GENERAL, see ImageUsage::StorageImage and StorageImageWrite
+pub fn write_storage_image(device: &ash::Device, descriptor_set: &ash::vk::DescriptorSet, binding: u32, image_view: &ash::vk::ImageView) -> () {
+  let image_info = [ash::vk::DescriptorImageInfo::default().image_view(*image_view).image_layout(ash::vk::ImageLayout::GENERAL)];
+  let write = ash::vk::WriteDescriptorSet::default()
+    .dst_set(*descriptor_set)
+    .dst_binding(binding)
+    .descriptor_type(ash::vk::DescriptorType::STORAGE_IMAGE)
+    .image_info(&image_info);
+  unsafe { device.update_descriptor_sets(&[write], &[]); }
+}
+
+/// the whole buffer
+pub fn write_storage_buffer(device: &ash::Device, descriptor_set: &ash::vk::DescriptorSet, binding: u32, buffer: &ash::vk::Buffer) -> () {
+  let buffer_info = [ash::vk::DescriptorBufferInfo::default().buffer(*buffer).offset(0).range(ash::vk::WHOLE_SIZE)];
+  let write = ash::vk::WriteDescriptorSet::default()
+    .dst_set(*descriptor_set)
+    .dst_binding(binding)
+    .descriptor_type(ash::vk::DescriptorType::STORAGE_BUFFER)
+    .buffer_info(&buffer_info);
+  unsafe { device.update_descriptor_sets(&[write], &[]); }
+}
@@ -0,0 +1,95 @@
+use crate::{allocator::SharedAllocator, debug};
+
+/// shared by GFXHeadless, GFXWindow and every owning wrapper, so the device goes with the last of them
+pub type SharedDevice = std::rc::Rc<DeviceContext>;
+
+/// shared by the DeviceContext and the window's surface, so the instance goes with the last of them
+pub type SharedInstance = std::rc::Rc<InstanceContext>;
+
+/// the instance and its messenger. owns them from the moment they exist, so a failure later in setup still cleans up
+pub struct InstanceContext {
+  pub entry: ash::Entry,
+  pub instance: ash::Instance,
+  // None unless the config asked for one and debug utils is enabled
+  pub debug_messenger: Option<debug::DebugMessenger>,
+}
+
+impl Drop for InstanceContext {
+  fn drop(&mut self) {
+    if let Some(debug_messenger) = self.debug_messenger.as_ref() { debug::destroy_debug_messenger(debug_messenger); }
+    unsafe { self.instance.destroy_instance(None); }
+  }
+}
+
+/// the device and the command pools made for its queues. command pools are pushed as they are created,
+/// so whatever exists when setup fails part way is destroyed with the device
+pub struct DeviceHandle {
+  pub device: ash::Device,
+  // the main queue's, then any dedicated queues'. command buffers are freed into them, so they go after those
+  pub command_pools: Vec<ash::vk::CommandPool>,
+}
+
+impl Drop for DeviceHandle {
+  fn drop(&mut self) {
+    unsafe {
+      // nothing useful to do with an error here, and the teardown has to happen regardless
+      let _ = self.device.device_wait_idle();
+      for command_pool in self.command_pools.iter() { self.device.destroy_command_pool(*command_pool, None); }
+      self.device.destroy_device(None);
+    }
+  }
+}
+
+/// everything that has to outlive the resources made from it. torn down when the last SharedDevice drops,
+/// so nothing can be destroyed after its device whatever order the holders go in. derefs to the ash::Device
+pub struct DeviceContext {
+  // every buffer and image should get its memory from here
+  pub allocator: SharedAllocator,
+  // fields drop in order, so the device goes before the instance
+  pub device: DeviceHandle,
+  pub instance: SharedInstance,
+}
+
+impl std::ops::Deref for DeviceContext {
+  type Target = ash::Device;
+  fn deref(&self) -> &Self::Target {
+    &self.device.device
+  }
+}
+
+impl DeviceContext {
+  pub fn entry(&self) -> &ash::Entry {
+    &self.instance.entry
+  }
+
+  pub fn instance(&self) -> &ash::Instance {
+    &self.instance.instance
+  }
+
+  pub fn allocator(&self) -> &SharedAllocator {
+    &self.allocator
+  }
+
+  pub fn debug_messenger(&self) -> &Option<debug::DebugMessenger> {
+    &self.instance.debug_messenger
+  }
+
+  /// panics with the first validation error the messenger recorded, when the config asked to panic on them.
+  /// the callback can't panic itself, so this runs after every wait on a submission
+  pub fn check_validation(&self) -> () {
+    let validation_error = self.debug_messenger().as_ref().and_then(|debug_messenger| debug_messenger.state.get_validation_error());
+    if let Some(validation_error) = validation_error {
+      panic!("vulkan validation error: {}", validation_error);
+    }
+  }
+}
+
+impl Drop for DeviceContext {
+  /// memory goes back before the fields drop, which destroys the command pools and device, then the instance
+  /// once nothing else holds it
+  fn drop(&mut self) {
+    // nothing useful to do with an error here, and the teardown has to happen regardless
+    let _ = unsafe { self.device.device.device_wait_idle() };
+    if let Ok(mut allocator) = self.allocator.try_borrow_mut() { allocator.destroy(); }
+  }
+}
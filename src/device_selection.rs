@@ -0,0 +1,356 @@
+use proc_macros::{Getters};
+use itertools::Itertools;
+use crate::{block_formats::TextureCompression, config::GfxConfig, create_gfx::{get_device_extension_names, get_if_queue_family_adequate}, dynamic_rendering, error::{GfxError, GfxResult}, memory};
+
+/// which physical device create_device should use
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum DeviceSelector {
+  /// the accepted device with the highest score
+  #[default]
+  Best,
+  /// position in enumerate_physical_devices
+  Index(usize),
+  /// case insensitive substring of the device name, e.g. "radeon"
+  Name(String),
+  /// from VkPhysicalDeviceProperties, e.g. 0x10de for nvidia
+  Ids { vendor_id: u32, device_id: u32 },
+}
+
+/// "best", an index like "1", vendor and device ids in hex like "10de:2684", or anything else as a name
+impl std::str::FromStr for DeviceSelector {
+  type Err = std::convert::Infallible;
+
+  fn from_str(str: &str) -> Result<Self, Self::Err> {
+    if str.eq_ignore_ascii_case("best") { return Ok(DeviceSelector::Best); }
+    if let Ok(index) = str.parse::<usize>() { return Ok(DeviceSelector::Index(index)); }
+    if let Some((vendor_id, device_id)) = str.split_once(':') {
+      if let (Ok(vendor_id), Ok(device_id)) = (u32::from_str_radix(vendor_id, 16), u32::from_str_radix(device_id, 16)) {
+        return Ok(DeviceSelector::Ids { vendor_id, device_id });
+      }
+    }
+    Ok(DeviceSelector::Name(str.to_string()))
+  }
+}
+
+#[derive(Getters, Clone, Debug)]
+/// one physical device, what it is and whether it can run this application
+pub struct DeviceCandidate {
+  pub index: usize,
+  pub physical_device: ash::vk::PhysicalDevice,
+  pub name: String,
+  pub vendor_id: u32,
+  pub device_id: u32,
+  pub device_type: ash::vk::PhysicalDeviceType,
+  pub api_version: u32,
+  /// size of the largest DEVICE_LOCAL heap, in bytes
+  pub device_local_memory: u64,
+  /// optional device extensions and core features this device supports
+  pub feature_count: u32,
+  pub score: u64,
+  /// empty if the device is accepted
+  pub rejection_reasons: Vec<String>,
+}
+
+impl DeviceCandidate {
+  pub fn is_accepted(&self) -> bool {
+    self.rejection_reasons.is_empty()
+  }
+
+  pub fn matches(&self, selector: &DeviceSelector) -> bool {
+    match selector {
+      DeviceSelector::Best => true,
+      DeviceSelector::Index(index) => self.index == *index,
+      DeviceSelector::Name(name) => self.name.to_lowercase().contains(&name.to_lowercase()),
+      DeviceSelector::Ids { vendor_id, device_id } => self.vendor_id == *vendor_id && self.device_id == *device_id,
+    }
+  }
+}
+
+impl std::fmt::Display for DeviceCandidate {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f, "[{}] {} ({:?}, {:04x}:{:04x}, vulkan {}.{}, {} MiB device local, score {})",
+      self.index, self.name, self.device_type, self.vendor_id, self.device_id,
+      ash::vk::api_version_major(self.api_version), ash::vk::api_version_minor(self.api_version),
+      self.device_local_memory / (1024 * 1024), self.score
+    )?;
+    match self.is_accepted() {
+      true => write!(f, ": accepted"),
+      false => write!(f, ": rejected, {}", self.rejection_reasons.join(", ")),
+    }
+  }
+}
+
+/// what a device needs to be accepted, gathered from the config and whether there is a window
+#[derive(Clone, Copy)]
+pub struct DeviceRequirements<'a> {
+  pub api_version: u32,
+  /// the config's, plus the window ones when there is a surface
+  pub device_extensions: &'a [String],
+  pub dynamic_rendering: bool,
+  pub texture_compression: &'a [TextureCompression],
+  /// the queue family has to be able to present to it
+  pub surface: Option<(&'a ash::khr::surface::Instance, &'a ash::vk::SurfaceKHR)>,
+}
+
+impl<'a> DeviceRequirements<'a> {
+  pub fn new(config: &'a GfxConfig, device_extensions: &'a [String], surface: Option<(&'a ash::khr::surface::Instance, &'a ash::vk::SurfaceKHR)>) -> Self {
+    DeviceRequirements {
+      api_version: config.api_version(),
+      device_extensions,
+      dynamic_rendering: dynamic_rendering::get_if_dynamic_rendering_required(config, surface.is_some()),
+      texture_compression: config.required_texture_compression(),
+      surface,
+    }
+  }
+}
+
+/// every physical device with its score and the reasons it can't be used, in enumeration order.
+/// pass the surface when presenting, so devices that can't present to it are rejected
+pub fn list_device_candidates(instance: &ash::Instance, config: &GfxConfig, required_device_extensions: &[String], surface: Option<(&ash::khr::surface::Instance, &ash::vk::SurfaceKHR)>) -> GfxResult<Vec<DeviceCandidate>> {
+  let requirements = DeviceRequirements::new(config, required_device_extensions, surface);
+  let physical_devices = unsafe { instance.enumerate_physical_devices()? };
+  let mut candidates = vec![];
+  for (index, physical_device) in physical_devices.iter().enumerate() {
+    candidates.push(get_device_candidate(instance, config, &requirements, index, physical_device)?);
+  }
+  Ok(candidates)
+}
+
+/// picks the best accepted candidate, or the one the selector forces.
+/// a forced device that was rejected is an error rather than a silent fallback
+pub fn select_device_candidate<'a>(candidates: &'a [DeviceCandidate], selector: &DeviceSelector) -> GfxResult<&'a DeviceCandidate> {
+  if candidates.is_empty() { return Err(GfxError::NoPhysicalDevices); }
+  let matching = candidates.iter().filter(|candidate| candidate.matches(selector)).collect_vec();
+  if matching.is_empty() { return Err(GfxError::NoMatchingPhysicalDevice(format!("{:?}", selector))); }
+  // max_by_key keeps the last of equal scores, so reverse to prefer enumeration order
+  let best = matching.iter().rev().filter(|candidate| candidate.is_accepted()).max_by_key(|candidate| candidate.score);
+  match (best, selector) {
+    (Some(candidate), _) => Ok(candidate),
+    (None, DeviceSelector::Best) => Err(GfxError::NoSuitablePhysicalDevice),
+    (None, _) => {
+      let candidate = matching[0];
+      Err(GfxError::RejectedPhysicalDevice(candidate.name.clone(), candidate.rejection_reasons.clone()))
+    },
+  }
+}
+
+/// discrete > integrated > virtual > other > cpu, always. within a type, more device local memory,
+/// a newer api version and more supported features win
+pub fn get_device_score(device_type: ash::vk::PhysicalDeviceType, device_local_memory: u64, api_version: u32, feature_count: u32) -> u64 {
+  let type_score = match device_type {
+    ash::vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+    ash::vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+    ash::vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+    ash::vk::PhysicalDeviceType::CPU => 0,
+    _ => 1,
+  };
+  // each term is capped below the weight of the one before it, so it can only break ties
+  let memory_score = (device_local_memory / (64 * 1024 * 1024)).min(99_999); // 64 MiB steps
+  let version_score = (ash::vk::api_version_major(api_version) * 10 + ash::vk::api_version_minor(api_version)).min(99) as u64;
+  let feature_score = (feature_count as u64).min(99);
+  type_score * 10_000_000_000 + memory_score * 10_000 + version_score * 100 + feature_score
+}
+
+fn get_device_candidate(instance: &ash::Instance, config: &GfxConfig, requirements: &DeviceRequirements, index: usize, physical_device: &ash::vk::PhysicalDevice) -> GfxResult<DeviceCandidate> {
+  let properties = unsafe { instance.get_physical_device_properties(*physical_device) };
+  let name = properties.device_name_as_c_str().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+
+  let memory_properties = unsafe { instance.get_physical_device_memory_properties(*physical_device) };
+  let device_local_memory = memory_properties.memory_heaps_as_slice().iter()
+    .filter(|heap| heap.flags.contains(ash::vk::MemoryHeapFlags::DEVICE_LOCAL))
+    .map(|heap| heap.size)
+    .max()
+    .unwrap_or(0);
+
+  let extensions = get_device_extension_names(instance, physical_device)?;
+  let features = unsafe { instance.get_physical_device_features(*physical_device) };
+  let optional_extension_count = config.optional_device_extensions().iter().filter(|name| extensions.contains(name)).count() as u32;
+  let core_feature_count = [
+    features.sampler_anisotropy,
+    features.texture_compression_bc,
+    features.texture_compression_etc2,
+    features.texture_compression_astc_ldr,
+    features.shader_int64,
+    features.multi_draw_indirect,
+  ].iter().filter(|&&supported| supported == ash::vk::TRUE).count() as u32;
+  let feature_count = optional_extension_count + core_feature_count;
+
+  let rejection_reasons = get_device_rejection_reasons(instance, physical_device, &properties, &features, &extensions, requirements)?;
+  let score = get_device_score(properties.device_type, device_local_memory, properties.api_version, feature_count);
+
+  Ok(DeviceCandidate {
+    index,
+    physical_device: *physical_device,
+    name,
+    vendor_id: properties.vendor_id,
+    device_id: properties.device_id,
+    device_type: properties.device_type,
+    api_version: properties.api_version,
+    device_local_memory,
+    feature_count,
+    score,
+    rejection_reasons,
+  })
+}
+
+fn get_device_rejection_reasons(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice, properties: &ash::vk::PhysicalDeviceProperties, features: &ash::vk::PhysicalDeviceFeatures, extensions: &[String], requirements: &DeviceRequirements) -> GfxResult<Vec<String>> {
+  let mut reasons = vec![];
+
+  // number of memory allocations
+  if properties.limits.max_memory_allocation_count < 1 { reasons.push("no memory allocations allowed".to_string()); }
+
+  // memory flags
+  if !memory::get_if_physical_device_supports_all_memory_requirements(instance, physical_device) { reasons.push("missing a required memory type".to_string()); }
+
+  // supported image formats
+  let req_formats = [ash::vk::Format::R8G8B8A8_UNORM];
+  for req_format in req_formats.iter() {
+    let props = unsafe { instance.get_physical_device_format_properties(*physical_device, *req_format) };
+    let flags = ash::vk::FormatFeatureFlags::SAMPLED_IMAGE;
+    let pass_linear = props.linear_tiling_features & flags == flags;
+    let pass_optimal = props.optimal_tiling_features & flags == flags;
+    if !(pass_linear && pass_optimal) { reasons.push(format!("{:?} can't be sampled with both tilings", req_format)); }
+  }
+
+  // check vulkan version
+  if properties.api_version < requirements.api_version {
+    reasons.push(format!(
+      "supports vulkan {}.{}, needs {}.{}",
+      ash::vk::api_version_major(properties.api_version), ash::vk::api_version_minor(properties.api_version),
+      ash::vk::api_version_major(requirements.api_version), ash::vk::api_version_minor(requirements.api_version)
+    ));
+  }
+
+  // check for required device extensions
+  for extension in requirements.device_extensions.iter() {
+    if !extensions.contains(extension) { reasons.push(format!("missing extension {}", extension)); }
+  }
+
+  // windows are drawn with dynamic rendering, core or from the extension depending on the version
+  let api_version = properties.api_version.min(requirements.api_version);
+  match dynamic_rendering::get_dynamic_rendering_support(api_version, extensions) {
+    _ if !requirements.dynamic_rendering => {},
+    None => {
+      let missing = dynamic_rendering::get_dynamic_rendering_extensions(dynamic_rendering::DynamicRenderingSupport::Extension, api_version).into_iter().filter(|name| !extensions.iter().any(|extension| extension == name));
+      reasons.extend(missing.map(|name| format!("missing extension {}", name)));
+    },
+    Some(_) if !dynamic_rendering::get_if_dynamic_rendering_feature_supported(instance, physical_device) => reasons.push("missing feature dynamicRendering".to_string()),
+    Some(_) => {},
+  }
+
+  // block compressed formats the assets are shipped in
+  for compression in requirements.texture_compression.iter() {
+    if !compression.get_if_supported(features) { reasons.push(format!("missing feature {}", compression.get_feature_name())); }
+  }
+
+  // needs a queue family that can do everything, and present if there is a surface
+  let queue_family_properties = unsafe { instance.get_physical_device_queue_family_properties(*physical_device) };
+  let mut has_adequate_queue_family = false;
+  for (i, family_properties) in queue_family_properties.iter().enumerate() {
+    if get_if_queue_family_adequate(physical_device, i as u32, family_properties, requirements.surface)? {
+      has_adequate_queue_family = true;
+      break;
+    }
+  }
+  if !has_adequate_queue_family {
+    let reason = match requirements.surface {
+      None => "no queue family supports graphics, compute and transfer",
+      Some(_) => "no queue family supports graphics, compute, transfer and presenting to the surface",
+    };
+    reasons.push(reason.to_string());
+  }
+
+  Ok(reasons)
+}
+
+#[test]
+fn test_device_type_outweighs_memory_version_and_features() {
+  let gib = 1024 * 1024 * 1024;
+  let discrete = get_device_score(ash::vk::PhysicalDeviceType::DISCRETE_GPU, 2 * gib, ash::vk::API_VERSION_1_1, 0);
+  let integrated = get_device_score(ash::vk::PhysicalDeviceType::INTEGRATED_GPU, 64 * gib, ash::vk::API_VERSION_1_3, 50);
+  let virtual_gpu = get_device_score(ash::vk::PhysicalDeviceType::VIRTUAL_GPU, 64 * gib, ash::vk::API_VERSION_1_3, 50);
+  let cpu = get_device_score(ash::vk::PhysicalDeviceType::CPU, 64 * gib, ash::vk::API_VERSION_1_3, 50);
+  assert!(discrete > integrated);
+  assert!(integrated > virtual_gpu);
+  assert!(virtual_gpu > cpu);
+
+  // same type, the tie breakers apply in order
+  let more_memory = get_device_score(ash::vk::PhysicalDeviceType::DISCRETE_GPU, 8 * gib, ash::vk::API_VERSION_1_1, 0);
+  let newer = get_device_score(ash::vk::PhysicalDeviceType::DISCRETE_GPU, 2 * gib, ash::vk::API_VERSION_1_3, 0);
+  let more_features = get_device_score(ash::vk::PhysicalDeviceType::DISCRETE_GPU, 2 * gib, ash::vk::API_VERSION_1_1, 5);
+  assert!(more_memory > newer);
+  assert!(newer > more_features);
+  assert!(more_features > discrete);
+}
+
+#[test]
+fn test_parse_device_selector() {
+  assert_eq!("best".parse::<DeviceSelector>(), Ok(DeviceSelector::Best));
+  assert_eq!("1".parse::<DeviceSelector>(), Ok(DeviceSelector::Index(1)));
+  assert_eq!("10de:2684".parse::<DeviceSelector>(), Ok(DeviceSelector::Ids { vendor_id: 0x10de, device_id: 0x2684 }));
+  assert_eq!("Radeon RX 7600".parse::<DeviceSelector>(), Ok(DeviceSelector::Name("Radeon RX 7600".to_string())));
+}
+
+#[test]
+fn test_select_device_candidate() {
+  let gib = 1024 * 1024 * 1024;
+  let api_version = ash::vk::API_VERSION_1_3;
+  let candidates = vec![
+    DeviceCandidate {
+      index: 0,
+      physical_device: ash::vk::PhysicalDevice::null(),
+      name: "llvmpipe (LLVM 17.0.6, 256 bits)".to_string(),
+      vendor_id: 0x1000,
+      device_id: 0x2000,
+      device_type: ash::vk::PhysicalDeviceType::CPU,
+      api_version,
+      device_local_memory: 16 * gib,
+      feature_count: 0,
+      score: get_device_score(ash::vk::PhysicalDeviceType::CPU, 16 * gib, api_version, 0),
+      rejection_reasons: vec![],
+    },
+    DeviceCandidate {
+      index: 1,
+      physical_device: ash::vk::PhysicalDevice::null(),
+      name: "Intel(R) Graphics".to_string(),
+      vendor_id: 0x1001,
+      device_id: 0x2001,
+      device_type: ash::vk::PhysicalDeviceType::INTEGRATED_GPU,
+      api_version,
+      device_local_memory: 16 * gib,
+      feature_count: 0,
+      score: get_device_score(ash::vk::PhysicalDeviceType::INTEGRATED_GPU, 16 * gib, api_version, 0),
+      rejection_reasons: vec![],
+    },
+    DeviceCandidate {
+      index: 2,
+      physical_device: ash::vk::PhysicalDevice::null(),
+      name: "AMD Radeon RX 7600".to_string(),
+      vendor_id: 0x1002,
+      device_id: 0x2002,
+      device_type: ash::vk::PhysicalDeviceType::DISCRETE_GPU,
+      api_version,
+      device_local_memory: 8 * gib,
+      feature_count: 0,
+      score: get_device_score(ash::vk::PhysicalDeviceType::DISCRETE_GPU, 8 * gib, api_version, 0),
+      rejection_reasons: vec!["missing extension VK_EXT_memory_budget".to_string()],
+    },
+  ];
+
+  // the rejected discrete gpu is skipped
+  assert_eq!(select_device_candidate(&candidates, &DeviceSelector::Best).unwrap().index, 1);
+  assert_eq!(select_device_candidate(&candidates, &DeviceSelector::Index(0)).unwrap().index, 0);
+  assert_eq!(select_device_candidate(&candidates, &DeviceSelector::Name("LLVMPIPE".to_string())).unwrap().index, 0);
+  assert_eq!(select_device_candidate(&candidates, &DeviceSelector::Ids { vendor_id: 0x1001, device_id: 0x2001 }).unwrap().index, 1);
+
+  // forcing a rejected device says why instead of falling back
+  match select_device_candidate(&candidates, &DeviceSelector::Name("radeon".to_string())) {
+    Err(GfxError::RejectedPhysicalDevice(name, reasons)) => {
+      assert_eq!(name, "AMD Radeon RX 7600");
+      assert_eq!(reasons, vec!["missing extension VK_EXT_memory_budget".to_string()]);
+    },
+    other => panic!("expected a rejected device, got {:?}", other.map(|candidate| candidate.index)),
+  }
+  assert!(matches!(select_device_candidate(&candidates, &DeviceSelector::Index(7)), Err(GfxError::NoMatchingPhysicalDevice(_))));
+}
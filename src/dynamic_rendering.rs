@@ -0,0 +1,153 @@
+use crate::{config::GfxConfig, constants};
+
+/// where vkCmdBeginRendering comes from. devices with neither are only accepted when nothing requires it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DynamicRenderingSupport {
+  /// vulkan 1.3 and up
+  Core,
+  /// VK_KHR_dynamic_rendering, and below 1.2 the extensions it depends on
+  Extension,
+}
+
+/// the extensions that have to be enabled for it. none when it's core
+pub fn get_dynamic_rendering_extensions(support: DynamicRenderingSupport, api_version: u32) -> Vec<&'static str> {
+  match (support, api_version >= ash::vk::API_VERSION_1_2) {
+    (DynamicRenderingSupport::Core, _) => vec![],
+    (DynamicRenderingSupport::Extension, true) => vec![constants::DYNAMIC_RENDERING_EXTENSION],
+    (DynamicRenderingSupport::Extension, false) => [constants::DYNAMIC_RENDERING_EXTENSION].into_iter().chain(constants::DYNAMIC_RENDERING_EXTENSION_DEPENDENCIES).collect(),
+  }
+}
+
+/// api_version is the lower of what the config asks for and what the device supports. None when the device has neither
+/// the version nor the extensions. the feature itself still has to be checked, see get_if_dynamic_rendering_feature_supported
+pub fn get_dynamic_rendering_support(api_version: u32, extensions: &[String]) -> Option<DynamicRenderingSupport> {
+  let support = match api_version >= ash::vk::API_VERSION_1_3 {
+    true => DynamicRenderingSupport::Core,
+    false => DynamicRenderingSupport::Extension,
+  };
+  let has_extensions = get_dynamic_rendering_extensions(support, api_version).iter().all(|name| extensions.iter().any(|extension| extension == name));
+  match has_extensions {
+    true => Some(support),
+    false => None,
+  }
+}
+
+/// windows draw their frames with it, headless only needs it when the config asks
+pub fn get_if_dynamic_rendering_required(config: &GfxConfig, has_surface: bool) -> bool {
+  config.require_dynamic_rendering() || has_surface
+}
+
+/// get_dynamic_rendering_support, when the device has the feature as well
+pub fn get_usable_dynamic_rendering_support(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice, api_version: u32, extensions: &[String]) -> Option<DynamicRenderingSupport> {
+  get_dynamic_rendering_support(api_version, extensions).filter(|_| get_if_dynamic_rendering_feature_supported(instance, physical_device))
+}
+
+/// the dynamicRendering feature. only ask once get_dynamic_rendering_support says the version or extension is there,
+/// otherwise the driver doesn't know the struct
+pub fn get_if_dynamic_rendering_feature_supported(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice) -> bool {
+  let mut dynamic_rendering_features = ash::vk::PhysicalDeviceDynamicRenderingFeatures::default();
+  let mut features = ash::vk::PhysicalDeviceFeatures2::default().push_next(&mut dynamic_rendering_features);
+  unsafe { instance.get_physical_device_features2(*physical_device, &mut features); }
+  dynamic_rendering_features.dynamic_rendering == ash::vk::TRUE
+}
+
+/// the commands, from the device when core and from the extension's loader otherwise.
+/// the device's function table is large, so it's boxed to keep the enum small
+#[derive(Clone)]
+pub enum DynamicRendering {
+  Core(Box<ash::Device>),
+  Extension(ash::khr::dynamic_rendering::Device),
+}
+
+impl DynamicRendering {
+  /// support is what create_device enabled it with
+  pub fn new(instance: &ash::Instance, device: &ash::Device, support: DynamicRenderingSupport) -> Self {
+    match support {
+      DynamicRenderingSupport::Extension => DynamicRendering::Extension(ash::khr::dynamic_rendering::Device::new(instance, device)),
+      DynamicRenderingSupport::Core => DynamicRendering::Core(Box::new(device.clone())),
+    }
+  }
+
+  pub fn cmd_begin_rendering(&self, command_buffer: &ash::vk::CommandBuffer, rendering_info: &ash::vk::RenderingInfo) -> () {
+    match self {
+      DynamicRendering::Core(device) => unsafe { device.cmd_begin_rendering(*command_buffer, rendering_info) },
+      DynamicRendering::Extension(dynamic_rendering) => unsafe { dynamic_rendering.cmd_begin_rendering(*command_buffer, rendering_info) },
+    }
+  }
+
+  pub fn cmd_end_rendering(&self, command_buffer: &ash::vk::CommandBuffer) -> () {
+    match self {
+      DynamicRendering::Core(device) => unsafe { device.cmd_end_rendering(*command_buffer) },
+      DynamicRendering::Extension(dynamic_rendering) => unsafe { dynamic_rendering.cmd_end_rendering(*command_buffer) },
+    }
+  }
+}
+
+/// what happens to an attachment's contents when rendering begins
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadOp {
+  Load,
+  Clear([f32; 4]),
+  /// for when every pixel gets drawn over
+  DontCare,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ColorAttachment {
+  pub image_view: ash::vk::ImageView,
+  pub load_op: LoadOp,
+}
+
+#[derive(Clone, Copy, Debug)]
+/// cleared to clear_depth, or loaded when that is None
+pub struct DepthAttachment {
+  pub image_view: ash::vk::ImageView,
+  pub clear_depth: Option<f32>,
+}
+
+/// attachments are stored, and have to be in COLOR_ATTACHMENT_OPTIMAL or DEPTH_STENCIL_ATTACHMENT_OPTIMAL already.
+/// the render graph does that for images a pass writes as ColorAttachment or DepthAttachment
+pub fn begin_rendering(dynamic_rendering: &DynamicRendering, command_buffer: &ash::vk::CommandBuffer, extent: &ash::vk::Extent2D, color_attachments: &[ColorAttachment], depth_attachment: Option<DepthAttachment>) -> () {
+  let color_attachments = color_attachments.iter().map(|attachment| {
+    let (load_op, clear_color) = match attachment.load_op {
+      LoadOp::Load => (ash::vk::AttachmentLoadOp::LOAD, [0.0; 4]),
+      LoadOp::Clear(color) => (ash::vk::AttachmentLoadOp::CLEAR, color),
+      LoadOp::DontCare => (ash::vk::AttachmentLoadOp::DONT_CARE, [0.0; 4]),
+    };
+    ash::vk::RenderingAttachmentInfo::default()
+      .image_view(attachment.image_view)
+      .image_layout(ash::vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+      .load_op(load_op)
+      .store_op(ash::vk::AttachmentStoreOp::STORE)
+      .clear_value(ash::vk::ClearValue { color: ash::vk::ClearColorValue { float32: clear_color } })
+  }).collect::<Vec<_>>();
+  let depth_attachment = depth_attachment.map(|attachment| {
+    ash::vk::RenderingAttachmentInfo::default()
+      .image_view(attachment.image_view)
+      .image_layout(ash::vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+      .load_op(if attachment.clear_depth.is_some() { ash::vk::AttachmentLoadOp::CLEAR } else { ash::vk::AttachmentLoadOp::LOAD })
+      .store_op(ash::vk::AttachmentStoreOp::STORE)
+      .clear_value(ash::vk::ClearValue { depth_stencil: ash::vk::ClearDepthStencilValue { depth: attachment.clear_depth.unwrap_or(1.0), stencil: 0 } })
+  });
+  let mut rendering_info = ash::vk::RenderingInfo::default()
+    .render_area(ash::vk::Rect2D::default().extent(*extent))
+    .layer_count(1)
+    .color_attachments(&color_attachments);
+  if let Some(depth_attachment) = depth_attachment.as_ref() { rendering_info = rendering_info.depth_attachment(depth_attachment); }
+  dynamic_rendering.cmd_begin_rendering(command_buffer, &rendering_info);
+}
+
+#[test]
+fn test_dynamic_rendering_support() {
+  let strings = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+  assert_eq!(get_dynamic_rendering_support(ash::vk::API_VERSION_1_3, &[]), Some(DynamicRenderingSupport::Core));
+  assert_eq!(get_dynamic_rendering_support(ash::vk::API_VERSION_1_2, &[]), None);
+
+  let extension = strings(&[constants::DYNAMIC_RENDERING_EXTENSION]);
+  assert_eq!(get_dynamic_rendering_support(ash::vk::API_VERSION_1_2, &extension), Some(DynamicRenderingSupport::Extension));
+  // 1.1 needs what the extension depends on as well
+  assert_eq!(get_dynamic_rendering_support(ash::vk::API_VERSION_1_1, &extension), None);
+  let with_dependencies = strings(&[constants::DYNAMIC_RENDERING_EXTENSION, "VK_KHR_depth_stencil_resolve", "VK_KHR_create_renderpass2"]);
+  assert_eq!(get_dynamic_rendering_support(ash::vk::API_VERSION_1_1, &with_dependencies), Some(DynamicRenderingSupport::Extension));
+  assert_eq!(get_dynamic_rendering_extensions(DynamicRenderingSupport::Extension, ash::vk::API_VERSION_1_1).len(), 3);
+}
@@ -0,0 +1,111 @@
+/// everything that can go wrong while creating or driving the gfx context
+#[derive(Debug)]
+pub enum GfxError {
+  /// a vulkan call failed. includes device loss and out of memory
+  Vulkan(ash::vk::Result),
+  /// the vulkan loader could not be found or loaded
+  Loading(ash::LoadingError),
+  MissingInstanceLayer(String),
+  MissingInstanceExtension(String),
+  NoPhysicalDevices,
+  NoSuitablePhysicalDevice,
+  /// the config's device selector matched no physical device
+  NoMatchingPhysicalDevice(String),
+  /// the config's device selector matched a device that can't be used. name and the reasons why
+  RejectedPhysicalDevice(String, Vec<String>),
+  NoSuitableQueueFamily,
+  NoSuitableMemoryType,
+  NoSuitableSurfaceFormat,
+  UnsupportedImageUsage(ash::vk::ImageUsageFlags),
+  UnsupportedFormat(ash::vk::Format),
+  /// window, event loop or window handle errors from winit / raw_window_handle
+  Window(String),
+  /// failed to read, decode or write an image file
+  Image(image::ImageError),
+  /// a KTX2 or DDS file that is malformed or uses something we don't support
+  InvalidTextureFile(String),
+  /// an upload whose bytes or mip levels don't add up to the image it describes
+  InvalidUpload(String),
+  /// a SPIR-V file that is missing or isn't SPIR-V. path and why
+  InvalidShader(String, String),
+}
+
+pub type GfxResult<T> = Result<T, GfxError>;
+
+impl std::fmt::Display for GfxError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      GfxError::Vulkan(result) => write!(f, "vulkan call failed: {}", result),
+      GfxError::Loading(error) => write!(f, "could not load vulkan: {}", error),
+      GfxError::MissingInstanceLayer(name) => write!(f, "instance layer {} is not supported", name),
+      GfxError::MissingInstanceExtension(name) => write!(f, "instance extension {} is not supported", name),
+      GfxError::NoPhysicalDevices => write!(f, "no physical devices found"),
+      GfxError::NoSuitablePhysicalDevice => write!(f, "no physical device satisfies the requirements of this application"),
+      GfxError::NoMatchingPhysicalDevice(selector) => write!(f, "no physical device matches {}", selector),
+      GfxError::RejectedPhysicalDevice(name, reasons) => write!(f, "physical device {} can't be used: {}", name, reasons.join(", ")),
+      GfxError::NoSuitableQueueFamily => write!(f, "no queue family satisfies the requirements of this application"),
+      GfxError::NoSuitableMemoryType => write!(f, "no suitable memory type index found"),
+      GfxError::NoSuitableSurfaceFormat => write!(f, "failed to find any good surface formats"),
+      GfxError::UnsupportedImageUsage(usage) => write!(f, "image usage {:?} is not supported", usage),
+      GfxError::UnsupportedFormat(format) => write!(f, "format {:?} is not supported for this use", format),
+      GfxError::Window(message) => write!(f, "window error: {}", message),
+      GfxError::Image(error) => write!(f, "image error: {}", error),
+      GfxError::InvalidTextureFile(reason) => write!(f, "can't load texture file: {}", reason),
+      GfxError::InvalidUpload(reason) => write!(f, "can't upload image: {}", reason),
+      GfxError::InvalidShader(path, reason) => write!(f, "can't load shader {}: {}", path, reason),
+    }
+  }
+}
+
+impl std::error::Error for GfxError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      GfxError::Vulkan(result) => Some(result),
+      GfxError::Loading(error) => Some(error),
+      GfxError::Image(error) => Some(error),
+      _ => None,
+    }
+  }
+}
+
+impl From<ash::vk::Result> for GfxError {
+  fn from(result: ash::vk::Result) -> Self {
+    GfxError::Vulkan(result)
+  }
+}
+
+impl From<ash::LoadingError> for GfxError {
+  fn from(error: ash::LoadingError) -> Self {
+    GfxError::Loading(error)
+  }
+}
+
+impl From<image::ImageError> for GfxError {
+  fn from(error: image::ImageError) -> Self {
+    GfxError::Image(error)
+  }
+}
+
+impl From<winit::error::EventLoopError> for GfxError {
+  fn from(error: winit::error::EventLoopError) -> Self {
+    GfxError::Window(error.to_string())
+  }
+}
+
+impl From<winit::error::OsError> for GfxError {
+  fn from(error: winit::error::OsError) -> Self {
+    GfxError::Window(error.to_string())
+  }
+}
+
+impl From<raw_window_handle::HandleError> for GfxError {
+  fn from(error: raw_window_handle::HandleError) -> Self {
+    GfxError::Window(error.to_string())
+  }
+}
+
+impl From<std::io::Error> for GfxError {
+  fn from(error: std::io::Error) -> Self {
+    GfxError::Image(image::ImageError::IoError(error))
+  }
+}
@@ -0,0 +1,126 @@
+use proc_macros::{Getters};
+use crate::{device_context::SharedDevice, error::GfxResult, gfx_window::GFXWindow, resources};
+
+#[derive(Getters)]
+/// what one frame in flight owns. the fence starts signaled so the first wait on it returns straight away
+pub struct Frame {
+  pub command_buffer: resources::CommandBuffer,
+  pub image_available: resources::Semaphore,
+  pub in_flight: resources::Fence,
+}
+
+impl Frame {
+  pub fn new(device: &SharedDevice, command_pool: &ash::vk::CommandPool) -> GfxResult<Self> {
+    Ok(Frame {
+      command_buffer: resources::CommandBuffer::new(device, command_pool)?,
+      image_available: resources::Semaphore::new(device)?,
+      in_flight: resources::Fence::new(device, true)?,
+    })
+  }
+}
+
+#[derive(Getters)]
+/// lets the CPU record the next frame while the GPU is still busy with the last ones.
+/// the command pool has to allow resetting single command buffers
+pub struct FramesInFlight {
+  pub frames: Vec<Frame>,
+  /// one per swapchain image, not per frame. presenting has no fence, so the only sign a semaphore it waited on
+  /// is free again is its image being acquired again
+  pub render_finished: Vec<resources::Semaphore>,
+  pub current_frame: usize,
+  #[Getters_Skip]
+  pub device: SharedDevice,
+}
+
+impl FramesInFlight {
+  pub fn new(device: &SharedDevice, command_pool: &ash::vk::CommandPool, frames_in_flight: u32, swapchain_image_count: usize) -> GfxResult<Self> {
+    let frames = (0..frames_in_flight.max(1)).map(|_| Frame::new(device, command_pool)).collect::<GfxResult<Vec<_>>>()?;
+    let render_finished = (0..swapchain_image_count).map(|_| resources::Semaphore::new(device)).collect::<GfxResult<Vec<_>>>()?;
+    Ok(FramesInFlight { frames, render_finished, current_frame: 0, device: device.clone() })
+  }
+
+  /// after the swapchain is recreated, which can change how many images it has. the device must be idle
+  pub fn on_swapchain_recreated(&mut self, swapchain_image_count: usize) -> GfxResult<()> {
+    self.render_finished = (0..swapchain_image_count).map(|_| resources::Semaphore::new(&self.device)).collect::<GfxResult<Vec<_>>>()?;
+    Ok(())
+  }
+
+  /// waits for this frame's last submission, acquires a swapchain image and records into the frame's command buffer.
+  /// the submission waits for the image at wait_stage, so record's first use of it (and any layout transition) has to come
+  /// after that stage. record gets the swapchain image and its index, and has to leave it in PRESENT_SRC_KHR.
+  /// returns true when the swapchain is out of date or suboptimal, and should be recreated before the next frame
+  pub fn draw_frame(&mut self, gfx_window: &GFXWindow, queue: &ash::vk::Queue, wait_stage: ash::vk::PipelineStageFlags, record: impl FnOnce(&ash::vk::CommandBuffer, ash::vk::Image, usize) -> GfxResult<()>) -> GfxResult<bool> {
+    unpack!(gfx_window, swapchain_device, swapchain, swapchain_images);
+    let device = &self.device;
+    let frame = &self.frames[self.current_frame];
+    let timeout = 9999 * 1000 * 1000;
+    frame.in_flight.wait(timeout)?;
+    device.check_validation();
+
+    let (image_index, suboptimal) = match unsafe { swapchain_device.acquire_next_image(*swapchain, timeout, *frame.image_available, ash::vk::Fence::null()) } {
+      Ok(acquired) => acquired,
+      Err(ash::vk::Result::ERROR_OUT_OF_DATE_KHR) => return Ok(true),
+      Err(error) => return Err(error.into()),
+    };
+
+    let command_buffer = *frame.command_buffer;
+    let recorded = record_command_buffer(device, command_buffer, || record(&command_buffer, swapchain_images[image_index as usize], image_index as usize));
+    if let Err(error) = recorded {
+      // the acquire signals image_available regardless. a batch that only waits on it unsignals it, so the next acquire can use it
+      let wait_semaphores = [*frame.image_available];
+      let wait_stages = [ash::vk::PipelineStageFlags::ALL_COMMANDS];
+      let submit_info = ash::vk::SubmitInfo::default().wait_semaphores(&wait_semaphores).wait_dst_stage_mask(&wait_stages);
+      unsafe { device.queue_submit(*queue, &[submit_info], ash::vk::Fence::null())?; }
+      return Err(error);
+    }
+
+    let render_finished = *self.render_finished[image_index as usize];
+    let command_buffers = [command_buffer];
+    let wait_semaphores = [*frame.image_available];
+    let wait_stages = [wait_stage];
+    let signal_semaphores = [render_finished];
+    let submit_info = ash::vk::SubmitInfo::default()
+      .command_buffers(&command_buffers)
+      .wait_semaphores(&wait_semaphores)
+      .wait_dst_stage_mask(&wait_stages)
+      .signal_semaphores(&signal_semaphores);
+    // the fence is only reset once nothing can fail before the submit that signals it, or the next wait would never return
+    frame.in_flight.reset()?;
+    unsafe { device.queue_submit(*queue, &[submit_info], *frame.in_flight)?; }
+
+    let swapchains = [*swapchain];
+    let image_indices = [image_index];
+    let present_info = ash::vk::PresentInfoKHR::default()
+      .wait_semaphores(&signal_semaphores)
+      .swapchains(&swapchains)
+      .image_indices(&image_indices);
+    let out_of_date = match unsafe { swapchain_device.queue_present(*queue, &present_info) } {
+      Ok(suboptimal) => suboptimal,
+      Err(ash::vk::Result::ERROR_OUT_OF_DATE_KHR) => true,
+      Err(error) => return Err(error.into()),
+    };
+
+    self.current_frame = (self.current_frame + 1) % self.frames.len();
+    Ok(suboptimal || out_of_date)
+  }
+}
+
+impl Drop for FramesInFlight {
+  /// presenting has no fence, so the frames' fences don't cover everything that might still be in flight
+  fn drop(&mut self) {
+    // nothing useful to do with an error here, and the teardown has to happen regardless
+    let _ = unsafe { self.device.device_wait_idle() };
+  }
+}
+
+/// resets command_buffer and records into it between begin and end
+fn record_command_buffer(device: &ash::Device, command_buffer: ash::vk::CommandBuffer, record: impl FnOnce() -> GfxResult<()>) -> GfxResult<()> {
+  unsafe {
+    device.reset_command_buffer(command_buffer, ash::vk::CommandBufferResetFlags::empty())?;
+    let begin_info = ash::vk::CommandBufferBeginInfo::default().flags(ash::vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    device.begin_command_buffer(command_buffer, &begin_info)?;
+  }
+  record()?;
+  unsafe { device.end_command_buffer(command_buffer)?; }
+  Ok(())
+}
@@ -0,0 +1,55 @@
+use crate::{descriptors, device_context::SharedDevice, dynamic_rendering::{self, DynamicRendering}, error::GfxResult, pipeline, resources, shaders};
+
+pub const VERTEX_SHADER_PATH: &str = "./assets/shaders/fullscreen_quad.vert.spv";
+pub const FRAGMENT_SHADER_PATH: &str = "./assets/shaders/textured.frag.spv";
+
+/// draws an image stretched over the whole of a colour attachment. the image has to be in SHADER_READ_ONLY_OPTIMAL
+/// and outlive this, the view and descriptor set point at it
+pub struct FullscreenQuad {
+  pub pipeline: resources::Pipeline,
+  pub pipeline_layout: resources::PipelineLayout,
+  pub descriptor_set: ash::vk::DescriptorSet,
+  pub descriptor_pool: resources::DescriptorPool,
+  pub descriptor_set_layout: resources::DescriptorSetLayout,
+  pub sampler: resources::Sampler,
+  pub image_view: resources::ImageView,
+}
+
+impl FullscreenQuad {
+  /// target_format is the format of the attachments it will draw to
+  pub fn new(device: &SharedDevice, image: &resources::Image, target_format: &ash::vk::Format) -> GfxResult<Self> {
+    let image_view = resources::ImageView::new(device, image, &image.format, ash::vk::ImageAspectFlags::COLOR, image.mip_levels)?;
+    let sampler = resources::Sampler::new(device)?;
+    let descriptor_set_layout = resources::DescriptorSetLayout::new(device, &[
+      (0, ash::vk::DescriptorType::SAMPLED_IMAGE, ash::vk::ShaderStageFlags::FRAGMENT),
+      (1, ash::vk::DescriptorType::SAMPLER, ash::vk::ShaderStageFlags::FRAGMENT),
+    ])?;
+    let descriptor_pool = resources::DescriptorPool::new(device, 1, &[(ash::vk::DescriptorType::SAMPLED_IMAGE, 1), (ash::vk::DescriptorType::SAMPLER, 1)])?;
+    let descriptor_set = descriptor_pool.allocate(&descriptor_set_layout)?;
+    descriptors::write_sampled_image(device, &descriptor_set, 0, &image_view);
+    descriptors::write_sampler(device, &descriptor_set, 1, &sampler);
+
+    let pipeline_layout = resources::PipelineLayout::new(device, &[*descriptor_set_layout], &[])?;
+    let vertex_shader = shaders::create_shader_module(device, VERTEX_SHADER_PATH)?;
+    let fragment_shader = shaders::create_shader_module(device, FRAGMENT_SHADER_PATH)?;
+    let pipeline = pipeline::GraphicsPipelineBuilder::new(&vertex_shader, &fragment_shader)
+      .topology(ash::vk::PrimitiveTopology::TRIANGLE_STRIP)
+      .color_formats(&[*target_format])
+      .build(device, &pipeline_layout)?;
+    Ok(FullscreenQuad { pipeline, pipeline_layout, descriptor_set, descriptor_pool, descriptor_set_layout, sampler, image_view })
+  }
+
+  /// the target view's image has to be in COLOR_ATTACHMENT_OPTIMAL and have the format given to new
+  pub fn record(&self, device: &ash::Device, dynamic_rendering: &DynamicRendering, command_buffer: &ash::vk::CommandBuffer, target: &ash::vk::ImageView, extent: &ash::vk::Extent2D) -> () {
+    // every pixel is drawn over, so there is nothing to clear or load
+    let color_attachments = [dynamic_rendering::ColorAttachment { image_view: *target, load_op: dynamic_rendering::LoadOp::DontCare }];
+    dynamic_rendering::begin_rendering(dynamic_rendering, command_buffer, extent, &color_attachments, None);
+    unsafe {
+      device.cmd_bind_pipeline(*command_buffer, ash::vk::PipelineBindPoint::GRAPHICS, *self.pipeline);
+      device.cmd_bind_descriptor_sets(*command_buffer, ash::vk::PipelineBindPoint::GRAPHICS, *self.pipeline_layout, 0, &[self.descriptor_set], &[]);
+    }
+    pipeline::set_viewport_and_scissor(device, command_buffer, extent);
+    unsafe { device.cmd_draw(*command_buffer, 4, 1, 0, 0); }
+    dynamic_rendering.cmd_end_rendering(command_buffer);
+  }
+}
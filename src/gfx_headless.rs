@@ -1,14 +1,93 @@
 use proc_macros::{Getters};
+use crate::{allocator::{Allocator, SharedAllocator}, commands, debug, device_context::SharedDevice, error::GfxResult, queues::GfxQueue};
 
 #[derive(Getters)]
-/// collection of vulkan stuff with an effectively 'static' lifetime
+/// collection of vulkan stuff with an effectively 'static' lifetime. the instance, device and memory live in
+/// a shared DeviceContext, which everything made from this holds on to, so they can drop in any order
 pub struct GFXHeadless {
-  // required for all contexts
-  pub entry: ash::Entry,
-  pub instance: ash::Instance,
   pub physical_device: ash::vk::PhysicalDevice,
-  pub device: ash::Device,
+  pub device: SharedDevice,
   pub command_pool: ash::vk::CommandPool,
   pub main_queue_family_index: u32,
   pub main_queue: ash::vk::Queue,
-}
\ No newline at end of file
+  // None when not requested or the device has no spare queue. each has its own command pool
+  pub transfer_queue: Option<GfxQueue>,
+  pub compute_queue: Option<GfxQueue>,
+  // what was actually enabled, including any optional layers and extensions from the config
+  pub api_version: u32,
+  pub enabled_instance_layers: Vec<String>,
+  pub enabled_instance_extensions: Vec<String>,
+  pub enabled_device_extensions: Vec<String>,
+  pub enabled_texture_compression: Vec<crate::block_formats::TextureCompression>,
+  // None when the device doesn't have VK_KHR_synchronization2
+  pub synchronization2: Option<ash::khr::synchronization2::Device>,
+  // core or from VK_KHR_dynamic_rendering, depending on the api version. always there with a window or when the
+  // config requires it, otherwise None when the device doesn't have it
+  pub dynamic_rendering: Option<crate::dynamic_rendering::DynamicRendering>,
+}
+
+impl GFXHeadless {
+  pub fn entry(&self) -> &ash::Entry {
+    self.device.entry()
+  }
+
+  pub fn instance(&self) -> &ash::Instance {
+    self.device.instance()
+  }
+
+  /// every buffer and image should get its memory from here
+  pub fn allocator(&self) -> &SharedAllocator {
+    self.device.allocator()
+  }
+
+  pub fn debug_messenger(&self) -> &Option<debug::DebugMessenger> {
+    self.device.debug_messenger()
+  }
+
+  /// see DeviceContext::check_validation. submissions that are waited on check already
+  pub fn check_validation(&self) -> () {
+    self.device.check_validation()
+  }
+
+  pub fn lock_allocator(&self) -> std::cell::RefMut<'_, Allocator> {
+    self.allocator().borrow_mut()
+  }
+
+  pub fn get_main_queue(&self) -> GfxQueue {
+    GfxQueue { family_index: self.main_queue_family_index, queue_index: 0, queue: self.main_queue, command_pool: self.command_pool }
+  }
+
+  /// on the main queue, with synchronization2 barriers when enabled. submit once everything is recorded
+  pub fn begin_commands(&self) -> GfxResult<commands::CommandRecorder> {
+    commands::CommandRecorder::begin(&self.device, &self.command_pool, self.synchronization2.as_ref())
+  }
+
+  /// records and submits on the main queue, then waits for it. for one-off work
+  pub fn immediate_submit(&self, record: impl FnOnce(&commands::CommandRecorder) -> ()) -> GfxResult<()> {
+    commands::immediate_submit(&self.device, &self.command_pool, &self.main_queue, self.synchronization2.as_ref(), record)
+  }
+
+  pub fn get_transfer_queue_or_main(&self) -> GfxQueue {
+    self.transfer_queue.unwrap_or_else(|| self.get_main_queue())
+  }
+
+  pub fn get_compute_queue_or_main(&self) -> GfxQueue {
+    self.compute_queue.unwrap_or_else(|| self.get_main_queue())
+  }
+
+  pub fn is_validation_enabled(&self) -> bool {
+    self.is_instance_layer_enabled(crate::constants::VALIDATION_LAYER)
+  }
+
+  pub fn is_instance_layer_enabled(&self, name: &str) -> bool {
+    self.enabled_instance_layers.iter().any(|layer| layer == name)
+  }
+
+  pub fn is_instance_extension_enabled(&self, name: &str) -> bool {
+    self.enabled_instance_extensions.iter().any(|extension| extension == name)
+  }
+
+  pub fn is_device_extension_enabled(&self, name: &str) -> bool {
+    self.enabled_device_extensions.iter().any(|extension| extension == name)
+  }
+}
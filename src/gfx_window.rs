@@ -1,15 +1,87 @@
 use proc_macros::{Getters};
+use crate::{device_context::{SharedDevice, SharedInstance}, error::GfxResult, swapchain::{self, SwapchainPreferences, SwapchainSettings}};
+
+/// holds on to the instance it was made from, so it can be destroyed whenever it drops. derefs to the ash::vk::SurfaceKHR
+pub struct Surface {
+  pub surface: ash::vk::SurfaceKHR,
+  pub surface_instance: ash::khr::surface::Instance,
+  pub instance: SharedInstance,
+}
+
+impl Surface {
+  pub fn new(instance: &SharedInstance, display_handle: &raw_window_handle::RawDisplayHandle, window_handle: &raw_window_handle::RawWindowHandle) -> GfxResult<Self> {
+    let surface_instance = ash::khr::surface::Instance::new(&instance.entry, &instance.instance);
+    let surface = unsafe { ash_window::create_surface(&instance.entry, &instance.instance, *display_handle, *window_handle, None)? };
+    Ok(Surface { surface, surface_instance, instance: instance.clone() })
+  }
+}
+
+impl std::ops::Deref for Surface {
+  type Target = ash::vk::SurfaceKHR;
+  fn deref(&self) -> &Self::Target {
+    &self.surface
+  }
+}
+
+impl Drop for Surface {
+  fn drop(&mut self) {
+    unsafe { self.surface_instance.destroy_surface(self.surface, None); }
+  }
+}
 
 #[derive(Getters)]
 /// collection of vulkan stuff with an effectively 'static' lifetime
 pub struct GFXWindow {
-  // required for window context
-  pub surface: ash::vk::SurfaceKHR,
+  // required for window context. the surface goes after the swapchain, which drop destroys before the fields
+  pub surface: Surface,
   pub surface_instance: ash::khr::surface::Instance,
   pub swapchain: ash::vk::SwapchainKHR,
   pub swapchain_device: ash::khr::swapchain::Device,
+  // owned by the swapchain, and replaced along with it
+  pub swapchain_images: Vec<ash::vk::Image>,
+  pub swapchain_image_views: Vec<crate::resources::ImageView>,
+  pub swapchain_settings: SwapchainSettings,
+  pub swapchain_preferences: SwapchainPreferences,
   pub display_handle: raw_window_handle::RawDisplayHandle,
   pub window_handle: raw_window_handle::RawWindowHandle,
   pub window: winit::window::Window,
   pub surface_format: ash::vk::Format,
-}
\ No newline at end of file
+  pub surface_color_space: ash::vk::ColorSpaceKHR,
+  #[Getters_Skip]
+  pub physical_device: ash::vk::PhysicalDevice,
+  #[Getters_Skip]
+  pub device: SharedDevice,
+}
+
+impl GFXWindow {
+  /// after a resize, or when acquiring or presenting says the swapchain is out of date or suboptimal.
+  /// waits for the device to go idle, so nothing still uses the old images. returns false and keeps the old swapchain
+  /// while the window is minimised, try again once it has a size
+  pub fn recreate_swapchain(&mut self) -> GfxResult<bool> {
+    let window_size = self.window.inner_size();
+    let capabilities = unsafe { self.surface_instance.get_physical_device_surface_capabilities(self.physical_device, *self.surface)? };
+    let extent = swapchain::get_swapchain_extent(&capabilities, &window_size);
+    if extent.width == 0 || extent.height == 0 { return Ok(false); }
+    unsafe { self.device.device_wait_idle()?; }
+    let surface_format = ash::vk::SurfaceFormatKHR::default().format(self.surface_format).color_space(self.surface_color_space);
+    let (swapchain, swapchain_settings) = swapchain::create_swapchain(&self.physical_device, &self.swapchain_device, &self.surface, &self.surface_instance, &window_size, &surface_format, &self.swapchain_preferences, self.swapchain)?;
+    self.swapchain_image_views.clear();
+    unsafe { self.swapchain_device.destroy_swapchain(self.swapchain, None); }
+    self.swapchain = swapchain;
+    self.swapchain_settings = swapchain_settings;
+    self.swapchain_images = unsafe { self.swapchain_device.get_swapchain_images(swapchain)? };
+    self.swapchain_image_views = swapchain::create_swapchain_image_views(&self.device, &self.swapchain_images, &self.surface_format)?;
+    Ok(true)
+  }
+}
+
+impl Drop for GFXWindow {
+  /// the swapchain's image views and the swapchain. the surface and then the window itself go after, when their fields drop
+  fn drop(&mut self) {
+    unsafe {
+      let _ = self.device.device_wait_idle();
+      self.swapchain_image_views.clear();
+      self.swapchain_device.destroy_swapchain(self.swapchain, None);
+    }
+  }
+}
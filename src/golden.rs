@@ -0,0 +1,130 @@
+use crate::{error::{GfxError, GfxResult}, constants, create_image, gfx_headless::GFXHeadless, offscreen, record_convert_to_surface_format, resources, upload_image_bytes};
+
+/// where actual and diff images are written when a comparison fails
+pub static GOLDEN_OUTPUT_DIR: &str = "./target/golden";
+
+/// largest allowed absolute difference per channel, in RGBA order
+pub type Tolerance = [u8; 4];
+
+pub static EXACT: Tolerance = [0, 0, 0, 0];
+
+#[derive(Debug)]
+pub struct GoldenComparison {
+  pub mismatched_pixels: usize,
+  /// largest difference seen per channel, in RGBA order
+  pub max_difference: [u8; 4],
+  /// red where a pixel is outside tolerance, a faded copy of the expected image elsewhere
+  pub diff_image: image::RgbaImage,
+}
+
+impl GoldenComparison {
+  pub fn passed(&self) -> bool {
+    self.mismatched_pixels == 0
+  }
+}
+
+/// images must be the same size
+pub fn compare_images(actual: &image::RgbaImage, expected: &image::RgbaImage, tolerance: &Tolerance) -> GoldenComparison {
+  assert!(actual.dimensions() == expected.dimensions(), "image is {:?} but the reference is {:?}", actual.dimensions(), expected.dimensions());
+
+  let mut mismatched_pixels = 0;
+  let mut max_difference = [0u8; 4];
+  let mut diff_image = image::RgbaImage::new(expected.width(), expected.height());
+  for (x, y, expected_pixel) in expected.enumerate_pixels() {
+    let actual_pixel = actual.get_pixel(x, y);
+    let mut within_tolerance = true;
+    for channel in 0..4 {
+      let difference = actual_pixel[channel].abs_diff(expected_pixel[channel]);
+      max_difference[channel] = max_difference[channel].max(difference);
+      if difference > tolerance[channel] { within_tolerance = false; }
+    }
+    let diff_pixel = if within_tolerance {
+      let fade = |c: u8| c / 4 + 192;
+      image::Rgba([fade(expected_pixel[0]), fade(expected_pixel[1]), fade(expected_pixel[2]), 255])
+    } else {
+      mismatched_pixels += 1;
+      image::Rgba([255, 0, 0, 255])
+    };
+    diff_image.put_pixel(x, y, diff_pixel);
+  }
+
+  GoldenComparison { mismatched_pixels, max_difference, diff_image }
+}
+
+/// panics if the image differs from the reference png. on failure the actual and diff images are written to GOLDEN_OUTPUT_DIR
+pub fn assert_matches_golden(actual: &image::RgbaImage, reference_path: &str, tolerance: &Tolerance) -> () {
+  let expected = image::open(reference_path).expect("failed to read reference image").into_rgba8();
+  let comparison = compare_images(actual, &expected, tolerance);
+  if comparison.passed() { return; }
+
+  let name = std::path::Path::new(reference_path).file_stem().expect("reference path has no file name").to_string_lossy();
+  std::fs::create_dir_all(GOLDEN_OUTPUT_DIR).expect("failed to create golden output directory");
+  let actual_path = format!("{}/{}.actual.png", GOLDEN_OUTPUT_DIR, name);
+  let diff_path = format!("{}/{}.diff.png", GOLDEN_OUTPUT_DIR, name);
+  actual.save_with_format(&actual_path, image::ImageFormat::Png).expect("failed to write actual image");
+  comparison.diff_image.save_with_format(&diff_path, image::ImageFormat::Png).expect("failed to write diff image");
+  panic!(
+    "{} pixels differ from {} (max difference per channel {:?}, tolerance {:?}). see {} and {}",
+    comparison.mismatched_pixels, reference_path, comparison.max_difference, tolerance, actual_path, diff_path
+  );
+}
+
+/// uploads RGBA8 pixels, blits them into an image of `intermediate_format` and reads the result back as RGBA8
+pub fn render_through_blit(gfx_headless: &GFXHeadless, bytes: &[u8], extent: &ash::vk::Extent3D, intermediate_format: &ash::vk::Format) -> GfxResult<image::RgbaImage> {
+  unpack!(gfx_headless, instance, physical_device, device, command_pool, main_queue, main_queue_family_index);
+
+  // upload
+  let raw_image = upload_image_bytes(instance, physical_device, device, &gfx_headless.get_transfer_queue_or_main(), &gfx_headless.get_main_queue(), bytes, extent)?;
+
+  // blit into the intermediate format, like we do for the swapchain
+  let (image, image_format) = create_image(device, main_queue_family_index, extent, intermediate_format)?;
+  let image = resources::Image::from_handle(device, image, extent, &image_format, ash::vk::ImageTiling::OPTIMAL, constants::MemoryIntent::GpuOnly)?;
+  gfx_headless.immediate_submit(|recorder| record_convert_to_surface_format(recorder, &raw_image, &image))?;
+
+  // read back
+  let target = offscreen::create_offscreen_target(instance, physical_device, device, main_queue_family_index, extent)?;
+  offscreen::copy_image_to_offscreen_target(device, command_pool, main_queue, gfx_headless.synchronization2().as_ref(), &image, &target)?;
+  let result = offscreen::read_offscreen_target_to_rgba_image(device, &target)?;
+
+  Ok(result)
+}
+
+#[test]
+fn test_compare_images_within_tolerance() {
+  let expected = image::RgbaImage::from_pixel(2, 2, image::Rgba([100, 100, 100, 255]));
+  let actual = image::RgbaImage::from_pixel(2, 2, image::Rgba([102, 99, 100, 255]));
+  let comparison = compare_images(&actual, &expected, &[2, 2, 0, 0]);
+  assert!(comparison.passed());
+  assert_eq!(comparison.max_difference, [2, 1, 0, 0]);
+}
+
+#[test]
+fn test_compare_images_reports_mismatches() {
+  let expected = image::RgbaImage::from_pixel(2, 2, image::Rgba([100, 100, 100, 255]));
+  let mut actual = expected.clone();
+  actual.put_pixel(1, 0, image::Rgba([100, 100, 110, 255]));
+  let comparison = compare_images(&actual, &expected, &[255, 255, 9, 255]);
+  assert_eq!(comparison.mismatched_pixels, 1);
+  assert_eq!(comparison.max_difference, [0, 0, 10, 0]);
+  assert_eq!(*comparison.diff_image.get_pixel(1, 0), image::Rgba([255, 0, 0, 255]));
+}
+
+#[test]
+#[ignore = "needs a vulkan device. run with --include-ignored, lavapipe is fine"]
+fn test_rgbw_survives_blit() {
+  let reference_path = "./assets/RGBW.png";
+  let bytes = image::open(reference_path).expect("failed to read rgbw image").into_rgba8().into_raw();
+  let (width, height) = image::image_dimensions(reference_path).expect("failed to read reference image");
+  let extent = ash::vk::Extent3D::default().width(width).height(height).depth(1);
+
+  let gfx_headless = crate::create_gfx::create_gfx_headless(&crate::config::GfxConfig::default().with_validation(true).with_panic_on_validation_error(true)).expect("failed to create headless gfx");
+  let actual = render_through_blit(&gfx_headless, &bytes, &extent, &ash::vk::Format::B8G8R8A8_UNORM).expect("failed to render rgbw");
+
+  // the four corners should be exactly red, green, blue and white
+  assert_eq!(*actual.get_pixel(0, 0), image::Rgba([255, 0, 0, 255]));
+  assert_eq!(*actual.get_pixel(1, 0), image::Rgba([0, 255, 0, 255]));
+  assert_eq!(*actual.get_pixel(0, 1), image::Rgba([0, 0, 255, 255]));
+  assert_eq!(*actual.get_pixel(1, 1), image::Rgba([255, 255, 255, 255]));
+  assert_matches_golden(&actual, reference_path, &EXACT);
+  gfx_headless.check_validation();
+}
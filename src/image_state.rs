@@ -0,0 +1,155 @@
+use crate::commands::{self, ImageBarrier};
+
+/// what an image is about to be used for. each implies a layout and the stages and accesses that use it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageUsage {
+  TransferSrc,
+  TransferDst,
+  /// sampled in fragment or compute shaders
+  ShaderRead,
+  /// read and written by compute shaders as a storage image
+  StorageImage,
+  /// only written by compute shaders, so whatever was there before doesn't matter
+  StorageImageWrite,
+  ColorAttachment,
+  DepthAttachment,
+  Present,
+  /// read back by the CPU, e.g. an offscreen target
+  HostRead,
+  /// anything, waits for everything. prefer a specific usage
+  General,
+}
+
+impl ImageUsage {
+  pub fn get_layout(&self) -> ash::vk::ImageLayout {
+    use ash::vk::ImageLayout as L;
+    match self {
+      ImageUsage::TransferSrc => L::TRANSFER_SRC_OPTIMAL,
+      ImageUsage::TransferDst => L::TRANSFER_DST_OPTIMAL,
+      ImageUsage::ShaderRead => L::SHADER_READ_ONLY_OPTIMAL,
+      ImageUsage::StorageImage | ImageUsage::StorageImageWrite | ImageUsage::HostRead | ImageUsage::General => L::GENERAL,
+      ImageUsage::ColorAttachment => L::COLOR_ATTACHMENT_OPTIMAL,
+      ImageUsage::DepthAttachment => L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+      ImageUsage::Present => L::PRESENT_SRC_KHR,
+    }
+  }
+
+  /// presenting is synchronised by semaphores, so it has none
+  pub fn get_stage_access(&self) -> (ash::vk::PipelineStageFlags2, ash::vk::AccessFlags2) {
+    use ash::vk::{AccessFlags2 as A, PipelineStageFlags2 as S};
+    match self {
+      ImageUsage::TransferSrc => (S::TRANSFER, A::TRANSFER_READ),
+      ImageUsage::TransferDst => (S::TRANSFER, A::TRANSFER_WRITE),
+      ImageUsage::ShaderRead => (S::FRAGMENT_SHADER | S::COMPUTE_SHADER, A::SHADER_READ),
+      ImageUsage::StorageImage => (S::COMPUTE_SHADER, A::SHADER_READ | A::SHADER_WRITE),
+      ImageUsage::StorageImageWrite => (S::COMPUTE_SHADER, A::SHADER_WRITE),
+      ImageUsage::ColorAttachment => (S::COLOR_ATTACHMENT_OUTPUT, A::COLOR_ATTACHMENT_READ | A::COLOR_ATTACHMENT_WRITE),
+      ImageUsage::DepthAttachment => (S::EARLY_FRAGMENT_TESTS | S::LATE_FRAGMENT_TESTS, A::DEPTH_STENCIL_ATTACHMENT_READ | A::DEPTH_STENCIL_ATTACHMENT_WRITE),
+      ImageUsage::Present => (S::NONE, A::NONE),
+      ImageUsage::HostRead => (S::HOST, A::HOST_READ),
+      ImageUsage::General => (S::ALL_COMMANDS, A::MEMORY_READ | A::MEMORY_WRITE),
+    }
+  }
+
+  /// reading something that was never written is almost always a bug
+  pub fn get_if_reads(&self) -> bool {
+    !matches!(self, ImageUsage::TransferDst | ImageUsage::StorageImageWrite | ImageUsage::ColorAttachment | ImageUsage::DepthAttachment)
+  }
+
+  pub fn get_if_writes(&self) -> bool {
+    matches!(self, ImageUsage::TransferDst | ImageUsage::StorageImage | ImageUsage::StorageImageWrite | ImageUsage::ColorAttachment | ImageUsage::DepthAttachment | ImageUsage::General)
+  }
+}
+
+/// the last usage recorded for each mip level of an image, None while its contents are undefined.
+/// updated when a barrier is recorded, so command buffers have to be submitted in the order they were recorded
+#[derive(Debug)]
+pub struct ImageState {
+  pub levels: std::sync::Mutex<Vec<Option<ImageUsage>>>,
+}
+
+impl ImageState {
+  pub fn new(mip_levels: u32) -> Self {
+    ImageState { levels: std::sync::Mutex::new(vec![None; mip_levels as usize]) }
+  }
+
+  pub fn get_levels(&self) -> Vec<Option<ImageUsage>> {
+    self.levels.lock().expect("image state lock poisoned").clone()
+  }
+
+  /// for work done without going through a CommandRecorder, or None to throw the contents away
+  pub fn set_levels(&self, usage: Option<ImageUsage>, base_mip_level: u32, level_count: u32) -> () {
+    let mut levels = self.levels.lock().expect("image state lock poisoned");
+    check_level_range(levels.len(), base_mip_level, level_count);
+    levels[base_mip_level as usize..(base_mip_level + level_count) as usize].fill(usage);
+  }
+
+  /// the barriers to get the levels ready for usage, and records usage as their new state
+  pub fn transition(&self, image: ash::vk::Image, usage: ImageUsage, base_mip_level: u32, level_count: u32, name: &str) -> Vec<ImageBarrier> {
+    let mut levels = self.levels.lock().expect("image state lock poisoned");
+    check_level_range(levels.len(), base_mip_level, level_count);
+    let range = base_mip_level as usize..(base_mip_level + level_count) as usize;
+    if usage.get_if_reads() && levels[range.clone()].iter().any(|level| level.is_none()) {
+      log::warn!(target: "gfx", "{} is used as {:?} but some of mip levels {:?} were never written", name, usage, range);
+    }
+    let barriers = get_transition_barriers(image, &levels[range.clone()], base_mip_level, usage);
+    levels[range].fill(Some(usage));
+    barriers
+  }
+}
+
+/// misuse in debug builds is a panic, in release the vulkan validation layer will have to catch it
+fn check_level_range(mip_levels: usize, base_mip_level: u32, level_count: u32) -> () {
+  debug_assert!(level_count > 0 && (base_mip_level + level_count) as usize <= mip_levels, "mip levels {}..{} are out of range for an image with {}", base_mip_level, base_mip_level + level_count, mip_levels);
+}
+
+/// one barrier per run of levels in the same state. reads following reads in the same layout need none
+pub fn get_transition_barriers(image: ash::vk::Image, levels: &[Option<ImageUsage>], base_mip_level: u32, usage: ImageUsage) -> Vec<ImageBarrier> {
+  let mut barriers = vec![];
+  let mut start = 0;
+  while start < levels.len() {
+    let previous = levels[start];
+    let run = levels[start..].iter().take_while(|level| **level == previous).count();
+    let needs_barrier = match previous {
+      None => true,
+      Some(previous) => previous.get_layout() != usage.get_layout() || previous.get_if_writes() || usage.get_if_writes(),
+    };
+    if needs_barrier {
+      let old_layout = previous.map(|previous| previous.get_layout()).unwrap_or(ash::vk::ImageLayout::UNDEFINED);
+      let (src_stage, src_access) = previous.map(|previous| previous.get_stage_access()).unwrap_or((ash::vk::PipelineStageFlags2::NONE, ash::vk::AccessFlags2::NONE));
+      let (dst_stage, dst_access) = usage.get_stage_access();
+      let barrier = ImageBarrier::new(image, old_layout, usage.get_layout())
+        .src_usage(src_stage, commands::get_write_accesses(src_access))
+        .dst_usage(dst_stage, dst_access)
+        .mip_levels(base_mip_level + start as u32, run as u32);
+      barriers.push(barrier);
+    }
+    start += run;
+  }
+  barriers
+}
+
+#[test]
+fn test_transition_barriers() {
+  let image = ash::vk::Image::null();
+  let state = ImageState::new(4);
+
+  // undefined to transfer dst, the whole image in one barrier
+  let barriers = state.transition(image, ImageUsage::TransferDst, 0, 4, "test image");
+  assert_eq!(barriers.len(), 1);
+  assert_eq!((barriers[0].old_layout, barriers[0].new_layout), (ash::vk::ImageLayout::UNDEFINED, ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL));
+  assert_eq!(barriers[0].src_stage, ash::vk::PipelineStageFlags2::NONE);
+
+  // one level moves on, then the whole image is made ready for sampling. two runs, two barriers
+  state.transition(image, ImageUsage::TransferSrc, 0, 1, "test image");
+  let barriers = state.transition(image, ImageUsage::ShaderRead, 0, 4, "test image");
+  assert_eq!(barriers.len(), 2);
+  assert_eq!((barriers[0].subresource_range.base_mip_level, barriers[0].subresource_range.level_count), (0, 1));
+  assert_eq!(barriers[0].src_access, ash::vk::AccessFlags2::NONE);
+  assert_eq!((barriers[1].subresource_range.base_mip_level, barriers[1].subresource_range.level_count), (1, 3));
+  assert_eq!(barriers[1].src_access, ash::vk::AccessFlags2::TRANSFER_WRITE);
+
+  // already there
+  assert!(state.transition(image, ImageUsage::ShaderRead, 0, 4, "test image").is_empty());
+  assert_eq!(state.get_levels(), vec![Some(ImageUsage::ShaderRead); 4]);
+}
@@ -2,100 +2,203 @@
 
 use std::{ffi::CString, io::Read, str::FromStr};
 pub mod utils;
+pub mod error;
 pub mod constants;
+pub mod config;
+pub mod debug;
+pub mod device_selection;
+pub mod device_context;
 pub mod gfx_headless;
 pub mod gfx_window;
 pub mod create_gfx;
 #[macro_use]
 pub mod macros;
 pub mod memory;
+pub mod allocator;
+pub mod resources;
+pub mod offscreen;
+pub mod queues;
+pub mod golden;
+pub mod upload;
+pub mod texture;
+pub mod mipmaps;
+pub mod block_formats;
+pub mod texture_containers;
+pub mod swapchain;
+pub mod frames;
+pub mod commands;
+pub mod image_state;
+pub mod render_graph;
+pub mod shaders;
+pub mod pipeline;
+pub mod descriptors;
+pub mod fullscreen_quad;
+pub mod dynamic_rendering;
+pub mod compute;
 extern crate itertools;
 extern crate strum;
 use itertools::Itertools;
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 use utils::{cstr};
 use winit::{dpi::LogicalPosition, event::ElementState};
-use crate::{memory::{print_flags, split_flags, split_flags_u32}, utils::print_endianness};
+use crate::{device_context::SharedDevice, memory::{print_flags, split_flags, split_flags_u32}, utils::print_endianness};
 use gfx_headless::*;
+use error::{GfxError, GfxResult};
 
-fn main() {
-  let (gfx_headless, gfx_window, event_loop) = create_gfx::create_gfx();
+/// prints everything at or above max_level to stderr. the debug messenger and the gfx warnings go through `log`,
+/// and are silent without a logger
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+  fn enabled(&self, metadata: &log::Metadata) -> bool {
+    metadata.level() <= log::max_level()
+  }
+
+  fn log(&self, record: &log::Record) {
+    if self.enabled(record.metadata()) {
+      eprintln!("[{} {}] {}", record.level(), record.target(), record.args());
+    }
+  }
+
+  fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+fn main() -> GfxResult<()> {
+  log::set_logger(&LOGGER).expect("no other logger is installed");
+  log::set_max_level(log::LevelFilter::Info);
+  // `--headless [output.png]` renders offscreen and writes a png instead of opening a window
+  // `--grayscale [output.png]` does the same with a compute shader turning the image grayscale
+  // `--device <index|name|vendor:device>` forces a physical device, `--list-devices` shows what there is to pick from
+  let args = std::env::args().collect_vec();
+  let mut config = config::GfxConfig::default();
+  if let Some(i) = args.iter().position(|arg| arg == "--device") {
+    let device_selector = args.get(i + 1).map(|arg| arg.parse().expect("infallible")).unwrap_or_default();
+    config = config.with_device_selector(device_selector);
+  }
+  if args.iter().any(|arg| arg == "--list-devices") {
+    return list_devices(&config);
+  }
+  if let Some(i) = args.iter().position(|arg| arg == "--grayscale") {
+    let output_path = args.get(i + 1).map(|arg| arg.as_str()).unwrap_or("./grayscale.png");
+    return run_grayscale(&config, output_path);
+  }
+  match args.iter().position(|arg| arg == "--headless") {
+    Some(i) => {
+      let output_path = args.get(i + 1).map(|arg| arg.as_str()).unwrap_or("./output.png");
+      run_headless(&config, output_path)
+    },
+    None => {
+      // start at the size of the image it shows
+      let (image_width, image_height) = image::image_dimensions("./assets/garfield.png")?;
+      run_window(&config.with_window_size(image_width, image_height))
+    },
+  }
+}
+
+fn list_devices(config: &config::GfxConfig) -> GfxResult<()> {
+  let candidates = create_gfx::list_physical_devices(config)?;
+  let selected = device_selection::select_device_candidate(&candidates, config.device_selector()).map(|candidate| *candidate.index());
+  for candidate in candidates.iter() {
+    let marker = if selected.as_ref().ok() == Some(candidate.index()) { "*" } else { " " };
+    println!("{} {}", marker, candidate);
+  }
+  if let Err(error) = selected { println!("no device would be selected: {}", error); }
+  Ok(())
+}
+
+fn run_headless(config: &config::GfxConfig, output_path: &str) -> GfxResult<()> {
+  let gfx_headless = create_gfx::create_gfx_headless(config)?;
   unpack!(gfx_headless, entry, instance, physical_device, device, command_pool, main_queue, main_queue_family_index);
-  unpack!(gfx_window, swapchain_device, swapchain, surface, surface_instance, window, window_handle, display_handle, surface_format);
-
-  let (image_bytes, image_width, image_height) = get_garfield_bytes();
-  let extent = ash::vk::Extent3D::default()
-    .width(image_width)
-    .height(image_height)
-    .depth(1);
-
-  // get swapchain images
-  let swapchain_images = get_swapchain_images(swapchain_device, swapchain);
-
-  // get memory_type_index for the buffer
-  let memory_kind = constants::MemoryKind::Image1;
-  let memory_kind_flags = memory::get_memory_flags_raw(&memory::get_memory_flags_from_kind(memory_kind));
-
-  // allocate the raw image
-  let (raw_image, raw_image_format) = create_image(device, main_queue_family_index, &extent, &ash::vk::Format::R8G8B8A8_UNORM);
-  set_object_name(instance, device, raw_image, "raw image");
-  let requirements = get_image_memory_requirements(device, &raw_image);
-  let memory_type_bits = requirements.memory_type_bits;
-  let memory_type_index = 
-    memory::get_memory_type_index_raw(instance, physical_device, memory_kind_flags, memory_type_bits)
-    .expect("no suitable memory type index found");
-  let memory_allocation = allocate_memory(device, memory_type_index, requirements.size);
-  let offset = 0;
-  bind_image_memory(device, &raw_image, &memory_allocation, offset);
-
-  // map the memory so the CPU can consume it
-  let mapped_memory = map_memory(device, &memory_allocation);
-
-  // populate the host visible image
-  let image_layout = get_image_layout(device, raw_image);
-  write_bytes(mapped_memory, &image_bytes, &image_layout, &extent);
-
-  // transition raw image to TRANSFER_SRC_OPTIMAL
-  transition_image_to_new_layout(device, command_pool, &raw_image, main_queue, &ash::vk::ImageLayout::UNDEFINED, &ash::vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
-
-  // make a 'new' image so we can blit onto it
-  let (image, image_format) = create_image(device, main_queue_family_index, &extent, surface_format);
-  set_object_name(instance, device, image, "blit image");
-  let requirements = get_image_memory_requirements(device, &image);
-  let memory_type_bits = requirements.memory_type_bits;
-  let memory_type_index = 
-    memory::get_memory_type_index_raw(instance, physical_device, memory_kind_flags, memory_type_bits)
-    .expect("no suitable memory type index found");
-  let memory_allocation_2 = allocate_memory(device, memory_type_index, requirements.size);
-  let offset = 0;
-  bind_image_memory(device, &image, &memory_allocation, offset);
-
-  // transition blit image to TRANSFER_DST_OPTIMAL
-  transition_image_to_new_layout(device, command_pool, &image, main_queue, &ash::vk::ImageLayout::UNDEFINED, &ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL);
-
-  // blit
-  copy_image_to_surface_format(device, command_pool, main_queue, &raw_image, &image, &extent);
-
-  // transition blit and swapchain images to formats for copy
-  transition_image_to_new_layout(device, command_pool, &image, main_queue, &ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL, &ash::vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
-
-  let draw = || {
-    let (next_swapchain_image, next_swapchain_image_index) = get_next_swapchain_image(device, swapchain_device, swapchain, &swapchain_images);
-    println!("draw triggered. swapchain image {}", next_swapchain_image_index);
-    set_object_name(instance, device, *next_swapchain_image, "swapchain image");
-
-    transition_image_to_new_layout(device, command_pool, &next_swapchain_image, main_queue, &ash::vk::ImageLayout::UNDEFINED, &ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL);
-
-    // copy the image to the swapchain image
-    copy_image_to_swapchain_image(device, command_pool, &next_swapchain_image, main_queue, &image, &extent);
-  
-    // prepare swapchain image for presentation
-    transition_image_to_new_layout(device, command_pool, &next_swapchain_image, main_queue, &ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL, &ash::vk::ImageLayout::PRESENT_SRC_KHR);
-    
-    // present the image
-    present_image(swapchain_device, main_queue, swapchain, next_swapchain_image_index);
+
+  // upload the raw image, ready to be read by a transfer. UNORM, so the blits pass the bytes through untouched
+  let raw_image = texture::Texture::from_path(&gfx_headless, "./assets/garfield.png", texture::ColorSpace::Linear, false)?;
+  let extent = *raw_image.image().extent();
+  set_object_name(instance, device, *raw_image, "raw image")?;
+
+  // copy it into something the cpu can read, and write it out
+  let offscreen_target = offscreen::create_offscreen_target(instance, physical_device, device, main_queue_family_index, &extent)?;
+  set_object_name(instance, device, **offscreen_target.image(), "offscreen target")?;
+  offscreen::copy_image_to_offscreen_target(device, command_pool, main_queue, gfx_headless.synchronization2().as_ref(), raw_image.image(), &offscreen_target)?;
+  offscreen::save_offscreen_target_png(device, &offscreen_target, output_path)?;
+  println!("wrote {}", output_path);
+  println!("{:?}", gfx_headless.lock_allocator().get_stats());
+
+  println!("Finished");
+  Ok(())
+}
+
+fn run_grayscale(config: &config::GfxConfig, output_path: &str) -> GfxResult<()> {
+  let gfx_headless = create_gfx::create_gfx_headless(config)?;
+  unpack!(gfx_headless, entry, instance, physical_device, device);
+  // the compute queue can't blit, so everything here is a copy between RGBA8 images
+  let compute_queue = gfx_headless.get_compute_queue_or_main();
+
+  // the shader reads the pixels straight out of a storage buffer, no upload to an image needed
+  let pixels = image::open("./assets/garfield.png")?.to_rgba8();
+  let extent = ash::vk::Extent3D::default().width(pixels.width()).height(pixels.height()).depth(1);
+  let format = ash::vk::Format::R8G8B8A8_UNORM;
+  let pixel_buffer = compute::create_storage_buffer_with_bytes(device, pixels.as_raw())?;
+  let grayscale_image = compute::create_storage_image(instance, physical_device, device, &extent, &format)?;
+  set_object_name(instance, device, *grayscale_image, "grayscale image")?;
+  let grayscale_view = resources::ImageView::new(device, &grayscale_image, &format, ash::vk::ImageAspectFlags::COLOR, 1)?;
+
+  // binding 0 is the pixels, binding 1 what they're written to. the push constants are the width and height
+  let grayscale = compute::ComputePipeline::new(device, compute::GRAYSCALE_SHADER_PATH, &[(0, ash::vk::DescriptorType::STORAGE_BUFFER), (1, ash::vk::DescriptorType::STORAGE_IMAGE)], 8)?;
+  let descriptor_pool = resources::DescriptorPool::new(device, 1, &[(ash::vk::DescriptorType::STORAGE_BUFFER, 1), (ash::vk::DescriptorType::STORAGE_IMAGE, 1)])?;
+  let descriptor_set = descriptor_pool.allocate(&grayscale.descriptor_set_layout)?;
+  descriptors::write_storage_buffer(device, &descriptor_set, 0, &pixel_buffer);
+  descriptors::write_storage_image(device, &descriptor_set, 1, &grayscale_view);
+
+  let offscreen_target = offscreen::create_offscreen_target(instance, physical_device, device, compute_queue.family_index, &extent)?;
+  commands::immediate_submit(device, &compute_queue.command_pool, &compute_queue.queue, gfx_headless.synchronization2().as_ref(), |recorder| {
+    recorder.prepare_image(&grayscale_image, image_state::ImageUsage::StorageImageWrite);
+    // matches local_size in the shader
+    let group_counts = compute::get_group_counts(&extent, [8, 8, 1]);
+    grayscale.dispatch(device, recorder.command_buffer(), &descriptor_set, &[extent.width, extent.height], group_counts);
+    recorder
+      .prepare_image(&grayscale_image, image_state::ImageUsage::TransferSrc)
+      .prepare_image(offscreen_target.image(), image_state::ImageUsage::TransferDst)
+      .copy_image(&grayscale_image, offscreen_target.image(), &extent)
+      .prepare_image(offscreen_target.image(), image_state::ImageUsage::HostRead);
+  })?;
+  offscreen::save_offscreen_target_png(device, &offscreen_target, output_path)?;
+  println!("wrote {}", output_path);
+
+  println!("Finished");
+  Ok(())
+}
+
+fn run_window(config: &config::GfxConfig) -> GfxResult<()> {
+  let (gfx_headless, mut gfx_window, event_loop) = create_gfx::create_gfx(config)?;
+  unpack!(gfx_headless, entry, instance, physical_device, device, command_pool, main_queue, main_queue_family_index);
+  let surface_format = *gfx_window.surface_format();
+
+  // upload the raw image with a full mip chain, for when the window is smaller. UNORM, like the surface format, so the bytes pass through untouched
+  let raw_image = texture::Texture::from_path(&gfx_headless, "./assets/garfield.png", texture::ColorSpace::Linear, true)?;
+  set_object_name(instance, device, *raw_image, "raw image")?;
+
+  // samples the raw image over the whole swapchain image, whatever size the window is
+  let quad = fullscreen_quad::FullscreenQuad::new(device, raw_image.image(), &surface_format)?;
+
+  // records into the frame's command buffer, the swapchain image is only waited for once colour is output.
+  // returns true when the swapchain needs recreating before the next frame
+  let mut frames = frames::FramesInFlight::new(device, command_pool, config.frames_in_flight(), gfx_window.swapchain_images().len())?;
+  let draw = |gfx_window: &gfx_window::GFXWindow, frames: &mut frames::FramesInFlight| -> GfxResult<bool> {
+    let swapchain_extent = *gfx_window.swapchain_settings().extent();
+    frames.draw_frame(gfx_window, main_queue, ash::vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, |command_buffer, swapchain_image, image_index| {
+      // no transient images, so nothing the GPU still needs goes when the graph is dropped
+      let swapchain_image_view = &gfx_window.swapchain_image_views()[image_index];
+      let graph = create_frame_graph(&quad, gfx_headless.dynamic_rendering().as_ref().expect("windows always have dynamic rendering"), raw_image.image(), swapchain_image, swapchain_image_view, &swapchain_extent, &surface_format).compile(device)?;
+      log::trace!(target: "gfx", "frame graph:\n{}", graph.to_text());
+      graph.record(gfx_headless.synchronization2().as_ref(), command_buffer)
+    })
   };
 
+  // the event loop can't return errors, so hold on to the first one and stop
+  let mut draw_error = None;
+  let mut swapchain_out_of_date = false;
   {
     use winit::{
       event::{Event, WindowEvent},
@@ -104,7 +207,7 @@ fn main() {
     };
     event_loop.run(|event, window_target| {
       window_target.set_control_flow(ControlFlow::Poll);
-      window.request_redraw();
+      gfx_window.window.request_redraw();
       match event {
         Event::WindowEvent {
             event: WindowEvent::CloseRequested,
@@ -118,50 +221,80 @@ fn main() {
          } => {
           // do keyboard stuff
         }
+        Event::WindowEvent { 
+          event: WindowEvent::Resized(_),
+          ..
+         } => {
+          swapchain_out_of_date = true;
+        }
         Event::Resumed => {
-          window.set_visible(true);
-          window.request_redraw();
+          gfx_window.window.set_visible(true);
+          gfx_window.window.request_redraw();
         },
         Event::WindowEvent { 
           event: WindowEvent::RedrawRequested,
           ..
          } => {
-          draw();
+          // a minimised window can't have a swapchain, so skip drawing until it comes back
+          let result = match swapchain_out_of_date {
+            true => gfx_window.recreate_swapchain().and_then(|recreated| {
+              swapchain_out_of_date = !recreated;
+              if recreated {
+                frames.on_swapchain_recreated(gfx_window.swapchain_images().len())?;
+              }
+              Ok(())
+            }),
+            false => Ok(()),
+          }.and_then(|_| match swapchain_out_of_date {
+            true => Ok(()),
+            false => draw(&gfx_window, &mut frames).map(|out_of_date| swapchain_out_of_date = out_of_date),
+          });
+          if let Err(error) = result {
+            draw_error = Some(error);
+            window_target.exit();
+          }
          }
         _ => {}
       }
-    }).expect("event loop failed");
+    })?;
+  }
+
+  // frames waits for the GPU when it drops, before the image and pipeline its command buffers use
+  match draw_error {
+    Some(error) => Err(error),
+    None => {
+      println!("Finished");
+      Ok(())
+    },
   }
-  
-  unsafe { device.device_wait_idle().expect("Failed to wait for device to become idle"); }
-  unsafe { swapchain_device.destroy_swapchain(*swapchain, None); }
-  unsafe { surface_instance.destroy_surface(*surface, None); }
-  unsafe { device.destroy_command_pool(*command_pool, None); }
-  unsafe { device.free_memory(memory_allocation, None); }
-  unsafe { device.free_memory(memory_allocation_2, None); }
-  unsafe { device.destroy_image(raw_image, None); }
-  unsafe { device.destroy_image(image, None); }
-  unsafe { device.destroy_device(None); }
-  unsafe { instance.destroy_instance(None); }
-  println!("Finished");
 }
 
-fn get_garfield_bytes() -> (Vec<u8>, u32, u32) {
-  let img = 
-    image::ImageReader::open("./assets/garfield.png")
-    .expect("failed to read image")
-    .decode()
-    .expect("failed to decode image");
-  let width = img.width();
-  let height = img.height();
-  let bytes = img.into_rgba8().into_raw();
-  (bytes, width, height)
+/// copies the bytes into a new R8G8B8A8_UNORM image through a staging buffer, on the transfer queue.
+/// the image is handed over to the main queue's family and left in TRANSFER_SRC_OPTIMAL.
+/// for more than one image, add them all to an upload::UploadBatch instead
+fn upload_image_bytes(
+  instance: &ash::Instance,
+  physical_device: &ash::vk::PhysicalDevice,
+  device: &SharedDevice,
+  transfer_queue: &queues::GfxQueue,
+  main_queue: &queues::GfxQueue,
+  bytes: &[u8],
+  extent: &ash::vk::Extent3D,
+) -> GfxResult<resources::Image> {
+  let mut batch = upload::UploadBatch::new();
+  batch.add_image(bytes, extent, &ash::vk::Format::R8G8B8A8_UNORM);
+  let mut images = batch.submit(instance, physical_device, device, transfer_queue, main_queue)?;
+  Ok(images.remove(0))
 }
 
 /// just a handle. not backed with memory
-fn create_buffer(device: &ash::Device, buffer_size: u64) -> ash::vk::Buffer {
+fn create_buffer(device: &ash::Device, buffer_size: u64) -> GfxResult<ash::vk::Buffer> {
+  create_buffer_with_usage(device, buffer_size, ash::vk::BufferUsageFlags::TRANSFER_SRC | ash::vk::BufferUsageFlags::TRANSFER_DST)
+}
+
+/// just a handle. not backed with memory
+fn create_buffer_with_usage(device: &ash::Device, buffer_size: u64, usage: ash::vk::BufferUsageFlags) -> GfxResult<ash::vk::Buffer> {
   let flags = ash::vk::BufferCreateFlags::empty();
-  let usage = ash::vk::BufferUsageFlags::TRANSFER_SRC | ash::vk::BufferUsageFlags::TRANSFER_DST;
   let sharing_mode = ash::vk::SharingMode::EXCLUSIVE; // used in one queue
   let create_info = ash::vk::BufferCreateInfo::default()
     .flags(flags) 
@@ -169,26 +302,40 @@ fn create_buffer(device: &ash::Device, buffer_size: u64) -> ash::vk::Buffer {
     .usage(usage)
     .sharing_mode(sharing_mode);
 
-  let buffer = unsafe { device.create_buffer(&create_info, None).expect("Could not create Vulkan buffer") };
-  buffer
+  let buffer = unsafe { device.create_buffer(&create_info, None)? };
+  Ok(buffer)
 }
 
 /// just a handle. not backed with memory
-fn create_image(device: &ash::Device, queue_family_index: u32, extent: &ash::vk::Extent3D, image_format: &ash::vk::Format) -> (ash::vk::Image, ash::vk::Format) {
-  let flags = ash::vk::ImageCreateFlags::empty();
+fn create_image(device: &ash::Device, queue_family_index: u32, extent: &ash::vk::Extent3D, image_format: &ash::vk::Format) -> GfxResult<(ash::vk::Image, ash::vk::Format)> {
+  let tiling = ash::vk::ImageTiling::OPTIMAL;
+  create_image_with_tiling(device, queue_family_index, extent, image_format, tiling)
+}
+
+/// just a handle. not backed with memory
+fn create_image_with_tiling(device: &ash::Device, queue_family_index: u32, extent: &ash::vk::Extent3D, image_format: &ash::vk::Format, tiling: ash::vk::ImageTiling) -> GfxResult<(ash::vk::Image, ash::vk::Format)> {
+  create_image_with_mip_levels(device, queue_family_index, extent, image_format, tiling, 1)
+}
+
+/// just a handle. not backed with memory. see mipmaps::get_mip_level_count for a full chain
+fn create_image_with_mip_levels(device: &ash::Device, queue_family_index: u32, extent: &ash::vk::Extent3D, image_format: &ash::vk::Format, tiling: ash::vk::ImageTiling, mip_levels: u32) -> GfxResult<(ash::vk::Image, ash::vk::Format)> {
   let usage = 
     ash::vk::ImageUsageFlags::TRANSFER_SRC
     | ash::vk::ImageUsageFlags::TRANSFER_DST
     | ash::vk::ImageUsageFlags::SAMPLED // means that the image can be sampled from in a shader
     | ash::vk::ImageUsageFlags::COLOR_ATTACHMENT
     ;
+  create_image_with_usage(device, queue_family_index, extent, image_format, tiling, mip_levels, usage)
+}
+
+/// just a handle. not backed with memory. the format has to support every usage, compressed ones can't be attachments
+fn create_image_with_usage(device: &ash::Device, queue_family_index: u32, extent: &ash::vk::Extent3D, image_format: &ash::vk::Format, tiling: ash::vk::ImageTiling, mip_levels: u32, usage: ash::vk::ImageUsageFlags) -> GfxResult<(ash::vk::Image, ash::vk::Format)> {
+  let flags = ash::vk::ImageCreateFlags::empty();
   let sharing_mode = ash::vk::SharingMode::EXCLUSIVE; // used in one queue
   let image_type = ash::vk::ImageType::TYPE_2D;
   let initial_layout = ash::vk::ImageLayout::UNDEFINED;
-  let tiling = ash::vk::ImageTiling::LINEAR; // in prod, use OPTIMAL
   let queue_family_indices = [queue_family_index];
   let samples = ash::vk::SampleCountFlags::TYPE_1; // no multi-sampling
-  let mip_levels = 1;
   let array_layers = 1;
   let create_info = ash::vk::ImageCreateInfo::default()
     .flags(flags) 
@@ -205,17 +352,17 @@ fn create_image(device: &ash::Device, queue_family_index: u32, extent: &ash::vk:
     .array_layers(array_layers)
     ;
 
-  let image = unsafe { device.create_image(&create_info, None).expect("Could not create Vulkan image") };
-  (image, *image_format)
+  let image = unsafe { device.create_image(&create_info, None)? };
+  Ok((image, *image_format))
 }
 
-fn allocate_memory(device: &ash::Device, memory_type_index: u32, size: u64) -> ash::vk::DeviceMemory {
+fn allocate_memory(device: &ash::Device, memory_type_index: u32, size: u64) -> GfxResult<ash::vk::DeviceMemory> {
   let info = ash::vk::MemoryAllocateInfo::default()
     .allocation_size(size)
     .memory_type_index(memory_type_index);
 
-  let memory = unsafe { device.allocate_memory(&info, None).expect("Could not allocate Vulkan memory") };
-  memory
+  let memory = unsafe { device.allocate_memory(&info, None)? };
+  Ok(memory)
 }
 
 /// useless, only works with VK_MEMORY_PROPERTY_LAZILY_ALLOCATED_BIT which is basically never supported. (35.06%)
@@ -251,211 +398,99 @@ fn get_image_memory_requirements(device: &ash::Device, image: &ash::vk::Image) -
   return reqs;
 }
 
-fn bind_buffer_memory(device: &ash::Device, buffer: &ash::vk::Buffer, memory_allocation: &ash::vk::DeviceMemory, offset: u64) -> () {
-  unsafe { device.bind_buffer_memory(*buffer, *memory_allocation, offset).expect("failed to bind buffer memory") }
+fn bind_buffer_memory(device: &ash::Device, buffer: &ash::vk::Buffer, memory_allocation: &ash::vk::DeviceMemory, offset: u64) -> GfxResult<()> {
+  unsafe { device.bind_buffer_memory(*buffer, *memory_allocation, offset)?; }
+  Ok(())
 }
 
-fn bind_image_memory(device: &ash::Device, image: &ash::vk::Image, memory_allocation: &ash::vk::DeviceMemory, offset: u64) -> () {
-  unsafe { device.bind_image_memory(*image, *memory_allocation, offset).expect("failed to bind image memory") }
+fn bind_image_memory(device: &ash::Device, image: &ash::vk::Image, memory_allocation: &ash::vk::DeviceMemory, offset: u64) -> GfxResult<()> {
+  unsafe { device.bind_image_memory(*image, *memory_allocation, offset)?; }
+  Ok(())
 }
 
-fn create_command_buffer(device: &ash::Device, command_pool: &ash::vk::CommandPool) -> ash::vk::CommandBuffer {
-  let command_buffer_allocate_info = ash::vk::CommandBufferAllocateInfo::default()
-    .command_buffer_count(1)
-    .command_pool(*command_pool)
-    .level(ash::vk::CommandBufferLevel::PRIMARY);
-  let command_buffers = unsafe { device.allocate_command_buffers(&command_buffer_allocate_info).expect("failed to allocate command buffer") };
-  return *command_buffers.get(0).expect("no command buffers created?");
+/// freed back to the pool when dropped
+fn create_command_buffer(device: &SharedDevice, command_pool: &ash::vk::CommandPool) -> GfxResult<resources::CommandBuffer> {
+  resources::CommandBuffer::new(device, command_pool)
 }
 
-fn record_command_buffer_buffer(device: &ash::Device, command_buffer: &ash::vk::CommandBuffer, buffer: &ash::vk::Buffer, buffer_size: u64) -> () {
+fn record_command_buffer_buffer(device: &ash::Device, command_buffer: &ash::vk::CommandBuffer, buffer: &ash::vk::Buffer, buffer_size: u64) -> GfxResult<()> {
   let begin_flags = ash::vk::CommandBufferUsageFlags::default();
   let begin_create_info = ash::vk::CommandBufferBeginInfo::default()
     .flags(begin_flags);
   unsafe { 
     device
-    .begin_command_buffer(*command_buffer, &begin_create_info)
-    .expect("failed to begin command buffer");
+    .begin_command_buffer(*command_buffer, &begin_create_info)?;
 
     let offset = 0;
     let data = 257;
     device.cmd_fill_buffer(*command_buffer, *buffer, offset, ash::vk::WHOLE_SIZE, data);
 
     device
-    .end_command_buffer(*command_buffer)
-    .expect("failed to end command buffer");
+    .end_command_buffer(*command_buffer)?;
   };
+  Ok(())
 }
 
-fn transition_image_to_new_layout(device: &ash::Device, command_pool: &ash::vk::CommandPool, image: &ash::vk::Image, queue: &ash::vk::Queue, old_layout: &ash::vk::ImageLayout, new_layout: &ash::vk::ImageLayout) -> () {
-  let command_buffer = create_command_buffer(&device, &command_pool);
-  let begin_flags = ash::vk::CommandBufferUsageFlags::default();
-  let begin_create_info = ash::vk::CommandBufferBeginInfo::default()
-    .flags(begin_flags);
-  unsafe { 
-    device
-    .begin_command_buffer(command_buffer, &begin_create_info)
-    .expect("failed to begin command buffer");
-
-    let image_memory_barrier = ash::vk::ImageMemoryBarrier::default()
-      .old_layout(*old_layout)
-      .new_layout(*new_layout)
-      .src_queue_family_index(ash::vk::QUEUE_FAMILY_IGNORED)
-      .dst_queue_family_index(ash::vk::QUEUE_FAMILY_IGNORED)
-      .image(*image)
-      .subresource_range(ash::vk::ImageSubresourceRange::default()
-        .aspect_mask(ash::vk::ImageAspectFlags::COLOR)
-        .base_mip_level(0)
-        .level_count(1)
-        .base_array_layer(0)
-        .layer_count(1)
-    );
-
-    device.cmd_pipeline_barrier(
-      command_buffer, 
-      ash::vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, 
-      ash::vk::PipelineStageFlags::BOTTOM_OF_PIPE, 
-      ash::vk::DependencyFlags::empty(), 
-      &[], 
-      &[], 
-      &[image_memory_barrier]
-    );
-
-    device
-    .end_command_buffer(command_buffer)
-    .expect("failed to end command buffer");
-  };
-
-  // submit
-  let fence = submit(&device, &queue, &command_buffer);
-
-  // await for fence
-  let timeout_ms = 9999;
-  let timeout_ns = timeout_ms * 1000 * 1000;
-  unsafe { device.wait_for_fences(&[fence], true, timeout_ns).expect("failed to wait for fence"); }
-  unsafe { device.destroy_fence(fence, None); }
-  unsafe { device.free_command_buffers(*command_pool, &[command_buffer]); }
+/// records the blit from the raw image into an image in the surface format.
+/// image ends up in TRANSFER_SRC_OPTIMAL, ready to be blitted to the swapchain
+fn record_convert_to_surface_format(recorder: &commands::CommandRecorder, raw_image: &resources::Image, image: &resources::Image) -> () {
+  recorder
+    .blit_images(raw_image, image, ash::vk::Filter::LINEAR)
+    .prepare_image(image, image_state::ImageUsage::TransferSrc);
 }
 
-fn copy_image_to_surface_format(device: &ash::Device, command_pool: &ash::vk::CommandPool, queue: &ash::vk::Queue, src_image: &ash::vk::Image, dst_image: &ash::vk::Image, extent: &ash::vk::Extent3D) -> () {
-  let command_buffer = create_command_buffer(&device, &command_pool);
-  let begin_flags = ash::vk::CommandBufferUsageFlags::default();
-  let begin_create_info = ash::vk::CommandBufferBeginInfo::default()
-    .flags(begin_flags);
-  unsafe { 
-    device
-    .begin_command_buffer(command_buffer, &begin_create_info)
-    .expect("failed to begin command buffer");
-
-    let src_image_layout = ash::vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
-    let dst_image_layout = ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL;
-    let src_subresource = ash::vk::ImageSubresourceLayers::default()
-      .aspect_mask(ash::vk::ImageAspectFlags::COLOR)
-      .mip_level(0)
-      .base_array_layer(0)
-      .layer_count(1)
-    ;
-    let dst_subresource = ash::vk::ImageSubresourceLayers::default()
-      .aspect_mask(ash::vk::ImageAspectFlags::COLOR)
-      .mip_level(0)
-      .base_array_layer(0)
-      .layer_count(1)
-    ;
-    let region = 
-      ash::vk::ImageBlit::default()
-      .src_offsets([ash::vk::Offset3D::default().x(0).y(0).z(0), ash::vk::Offset3D::default().x(extent.width as i32).y(extent.height as i32).z(extent.depth as i32)])
-      .dst_offsets([ash::vk::Offset3D::default().x(0).y(0).z(0), ash::vk::Offset3D::default().x(extent.width as i32).y(extent.height as i32).z(extent.depth as i32)])
-      .src_subresource(src_subresource)
-      .dst_subresource(dst_subresource)    
-    ;
-    let regions = [region];
-    let filter = ash::vk::Filter::LINEAR;
-    device.cmd_blit_image(command_buffer, *src_image, src_image_layout, *dst_image, dst_image_layout, &regions, filter);
-
-    device
-    .end_command_buffer(command_buffer)
-    .expect("failed to end command buffer");
-  };
-
-  // submit
-  let fence = submit(&device, &queue, &command_buffer);
-
-  // await for fence
-  let timeout_ms = 9999;
-  let timeout_ns = timeout_ms * 1000 * 1000;
-  unsafe { device.wait_for_fences(&[fence], true, timeout_ns).expect("failed to wait for fence"); }
-  unsafe { device.destroy_fence(fence, None); }
-  unsafe { device.free_command_buffers(*command_pool, &[command_buffer]); }
+/// draws the image over the whole swapchain image and leaves it ready to present.
+/// the swapchain image is first used at the colour attachment output stage, where draw_frame waits for it
+fn create_frame_graph<'a>(quad: &'a fullscreen_quad::FullscreenQuad, dynamic_rendering: &'a dynamic_rendering::DynamicRendering, image: &'a resources::Image, swapchain_image: ash::vk::Image, swapchain_image_view: &'a resources::ImageView, swapchain_extent: &ash::vk::Extent2D, swapchain_format: &ash::vk::Format) -> render_graph::RenderGraph<'a> {
+  let mut graph = render_graph::RenderGraph::new();
+  let source = graph.import_image("raw image", image);
+  let render_extent = *swapchain_extent;
+  let swapchain_extent = ash::vk::Extent3D::default().width(swapchain_extent.width).height(swapchain_extent.height).depth(1);
+  let target = graph.import_external_image("swapchain image", swapchain_image, &swapchain_extent, swapchain_format, None, Some(image_state::ImageUsage::Present));
+  graph.add_pass("fullscreen quad", move |context| {
+    quad.record(context.device, dynamic_rendering, &context.command_buffer, swapchain_image_view, &render_extent);
+    Ok(())
+  })
+    .read_image(source, image_state::ImageUsage::ShaderRead)
+    .write_image(target, image_state::ImageUsage::ColorAttachment);
+  graph
 }
 
-fn copy_image_to_swapchain_image(device: &ash::Device, command_pool: &ash::vk::CommandPool, swapchain_image: &ash::vk::Image, queue: &ash::vk::Queue, image: &ash::vk::Image, extent: &ash::vk::Extent3D) -> () {
-  let command_buffer = create_command_buffer(&device, &command_pool);
-  let begin_flags = ash::vk::CommandBufferUsageFlags::default();
-  let begin_create_info = ash::vk::CommandBufferBeginInfo::default()
-    .flags(begin_flags);
-  unsafe { 
-    device
-    .begin_command_buffer(command_buffer, &begin_create_info)
-    .expect("failed to begin command buffer");
-
-    let src_image = image;
-    let dst_image = swapchain_image;
-    let src_image_layout = ash::vk::ImageLayout::TRANSFER_SRC_OPTIMAL;
-    let dst_image_layout = ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL;
-    let region = ash::vk::ImageCopy::default()
-      .src_subresource(ash::vk::ImageSubresourceLayers::default()
-        .aspect_mask(ash::vk::ImageAspectFlags::COLOR)
-        .mip_level(0)
-        .base_array_layer(0)
-        .layer_count(1)
-      )
-      .dst_subresource(ash::vk::ImageSubresourceLayers::default()
-        .aspect_mask(ash::vk::ImageAspectFlags::COLOR)
-        .mip_level(0)
-        .base_array_layer(0)
-        .layer_count(1)
-      )
-      .extent(ash::vk::Extent3D::default()
-        .width(extent.width)
-        .height(extent.height)
-        .depth(1)
-      );
-    let regions = [region];
-
-    device.cmd_copy_image(command_buffer, *src_image, src_image_layout, *dst_image, dst_image_layout, &regions);
+/// the returned fence is destroyed when dropped, so wait on it first
+fn submit(device: &SharedDevice, queue: &ash::vk::Queue, command_buffer: &ash::vk::CommandBuffer) -> GfxResult<resources::Fence> {
+  let command_buffers = [*command_buffer];
+  let submit_info = ash::vk::SubmitInfo::default()
+    .command_buffers(&command_buffers);
 
-    device
-    .end_command_buffer(command_buffer)
-    .expect("failed to end command buffer");
-  };
+  let fence = resources::Fence::new(device, false)?;
 
-  // submit
-  let fence = submit(&device, &queue, &command_buffer);
+  unsafe { device.queue_submit(*queue, &[submit_info], *fence)?; }
 
-  // await for fence
-  let timeout_ms = 9999;
-  let timeout_ns = timeout_ms * 1000 * 1000;
-  unsafe { device.wait_for_fences(&[fence], true, timeout_ns).expect("failed to wait for fence"); }
-  unsafe { device.destroy_fence(fence, None); }
-  unsafe { device.free_command_buffers(*command_pool, &[command_buffer]); }
+  Ok(fence)
 }
 
-fn submit(device: &ash::Device, queue: &ash::vk::Queue, command_buffer: &ash::vk::CommandBuffer) -> ash::vk::Fence {
+/// like submit, but waits on and signals semaphores. for handing work between queues
+fn submit_with_semaphores(device: &SharedDevice, queue: &ash::vk::Queue, command_buffer: &ash::vk::CommandBuffer, wait_semaphores: &[(ash::vk::Semaphore, ash::vk::PipelineStageFlags)], signal_semaphores: &[ash::vk::Semaphore]) -> GfxResult<resources::Fence> {
   let command_buffers = [*command_buffer];
+  let wait_semaphore_handles = wait_semaphores.iter().map(|(semaphore, _)| *semaphore).collect_vec();
+  let wait_stages = wait_semaphores.iter().map(|(_, stage)| *stage).collect_vec();
   let submit_info = ash::vk::SubmitInfo::default()
-    .command_buffers(&command_buffers);
+    .command_buffers(&command_buffers)
+    .wait_semaphores(&wait_semaphore_handles)
+    .wait_dst_stage_mask(&wait_stages)
+    .signal_semaphores(signal_semaphores);
 
-  let fence = unsafe { device.create_fence(&ash::vk::FenceCreateInfo::default(), None).expect("failed to create fence") };
+  let fence = resources::Fence::new(device, false)?;
 
-  unsafe { device.queue_submit(*queue, &[submit_info], fence).expect("failed to submit to queue"); }
+  unsafe { device.queue_submit(*queue, &[submit_info], *fence)?; }
 
-  fence
+  Ok(fence)
 }
 
-fn map_memory(device: &ash::Device, memory_allocation: &ash::vk::DeviceMemory) -> *mut std::ffi::c_void {
+fn map_memory(device: &ash::Device, memory_allocation: &ash::vk::DeviceMemory) -> GfxResult<*mut std::ffi::c_void> {
   let flags = ash::vk::MemoryMapFlags::default();
-  let pointer = unsafe { device.map_memory(*memory_allocation, 0, ash::vk::WHOLE_SIZE, flags).expect("failed to map memory") };
-  pointer
+  let pointer = unsafe { device.map_memory(*memory_allocation, 0, ash::vk::WHOLE_SIZE, flags)? };
+  Ok(pointer)
 }
 
 fn print_buffer(mapped_memory: *mut std::ffi::c_void, buffer_size: u64) -> () {
@@ -513,6 +548,42 @@ fn print_image(
   }
 }
 
+/// the inverse of write_bytes. strips the row padding so the result is tightly packed
+fn read_image(
+  mapped_memory: *mut std::ffi::c_void,
+  layout: &ash::vk::SubresourceLayout, 
+  extent: &ash::vk::Extent3D,
+) -> Vec<u8> {
+  unsafe {
+    let byte_ptr = (mapped_memory as *const u8).add(layout.offset as usize);
+
+    let row_pitch = layout.row_pitch as u32;
+    let scanline_width = extent.width * 4;
+    let image_size = row_pitch * (extent.height - 1) + scanline_width; // last row has no padding
+    let slice = std::slice::from_raw_parts(byte_ptr, image_size as usize);
+
+    let mut bytes = Vec::with_capacity((scanline_width * extent.height) as usize);
+    for row in 0..extent.height {
+      let x = row * row_pitch; // base index into source
+      let row_slice = &slice[x as usize..(x + scanline_width) as usize];
+      bytes.extend_from_slice(row_slice);
+    }
+    bytes
+  }
+}
+
+#[test]
+fn test_read_image_strips_row_padding() {
+  // 2x2 RGBA8 image with 4 bytes of padding per row, starting 8 bytes in
+  let mut memory = vec![0u8; 8 + 12 + 8];
+  memory[8..16].copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+  memory[20..28].copy_from_slice(&[9, 10, 11, 12, 13, 14, 15, 16]);
+  let layout = ash::vk::SubresourceLayout::default().offset(8).row_pitch(12).size(20);
+  let extent = ash::vk::Extent3D::default().width(2).height(2).depth(1);
+  let bytes = read_image(memory.as_mut_ptr() as *mut std::ffi::c_void, &layout, &extent);
+  assert_eq!(bytes, (1..=16).collect_vec());
+}
+
 fn get_image_layout(device: &ash::Device, image: ash::vk::Image) -> ash::vk::SubresourceLayout {
   let subresource = ash::vk::ImageSubresource::default()
     .aspect_mask(ash::vk::ImageAspectFlags::COLOR)
@@ -546,55 +617,12 @@ fn read_buffer_to_cpu(mapped_memory: *mut std::ffi::c_void, buffer_size: u64) ->
   }
 }
 
-fn get_rgbw_bytes() -> Vec<u8> {
-  let img = 
-    image::ImageReader::open("./assets/RGBW.png")
-    .expect("failed to read image")
-    .decode()
-    .expect("failed to decode image");
-  let bytes = img.into_rgba8().into_raw();
-  bytes
-}
-
-fn get_swapchain_images(swapchain_device: &ash::khr::swapchain::Device, swapchain: &ash::vk::SwapchainKHR) -> Vec<ash::vk::Image> {
-  let swapchain_images = unsafe { swapchain_device.get_swapchain_images(*swapchain).expect("failed to get swapchain images") };
-  swapchain_images
-}
-
-fn get_next_swapchain_image<'a>(device: &ash::Device, swapchain_device: &ash::khr::swapchain::Device, swapchain: &ash::vk::SwapchainKHR, swapchain_images: &'a Vec<ash::vk::Image>) -> (&'a ash::vk::Image, u32) {
-  let timeout = 9999 * 1000 * 1000;
-  let semaphore = ash::vk::Semaphore::null();
-  let fence = unsafe { device.create_fence(&ash::vk::FenceCreateInfo::default(), None).expect("failed to create fence") };
-  let (image_index, suboptimal) = unsafe { swapchain_device.acquire_next_image(*swapchain, timeout, semaphore, fence).expect("failed to acquire next image") };
-  unsafe { device.wait_for_fences(&[fence], true, timeout).expect("failed to wait for swapchain image fence"); }
-  unsafe { device.destroy_fence(fence, None); }
-  let image = swapchain_images.get(image_index as usize).expect("failed to get swapchain image from index");
-  (image, image_index)
-}
-
-fn present_image(
-  swapchain_device: &ash::khr::swapchain::Device,
-  main_queue: &ash::vk::Queue,
-  swapchain: &ash::vk::SwapchainKHR,
-  image_index: u32,
-) -> () {
-  let swapchains = [*swapchain];
-  let image_indices = [image_index];
-  let present_info = ash::vk::PresentInfoKHR::default()
-    .swapchains(&swapchains)
-    .image_indices(&image_indices);
-
-  unsafe {
-    swapchain_device.queue_present(*main_queue, &present_info).expect("Failed to present swapchain image");
-  }
-}
-
 fn set_object_name<H: ash::vk::Handle>(
   instance: &ash::Instance,
   device: &ash::Device,
   object_handle: H,
   name: &str,
-) -> () {
+) -> GfxResult<()> {
   use ash::vk;
   use std::ffi::CString;
 
@@ -606,23 +634,23 @@ fn set_object_name<H: ash::vk::Handle>(
     ;
   unsafe {
     debug_utils_loader
-      .set_debug_utils_object_name(&name_info)
-      .expect("Failed to set Vulkan object name");
+      .set_debug_utils_object_name(&name_info)?;
   }
+  Ok(())
 }
 
-fn get_supported_surface_formats(physical_device: &ash::vk::PhysicalDevice, surface_instance: &ash::khr::surface::Instance, surface: &ash::vk::SurfaceKHR) -> Vec<ash::vk::SurfaceFormatKHR> {
-  let formats = unsafe { surface_instance.get_physical_device_surface_formats(*physical_device, *surface).expect("failed to get supported surface formats") };
-  formats
+fn get_supported_surface_formats(physical_device: &ash::vk::PhysicalDevice, surface_instance: &ash::khr::surface::Instance, surface: &ash::vk::SurfaceKHR) -> GfxResult<Vec<ash::vk::SurfaceFormatKHR>> {
+  let formats = unsafe { surface_instance.get_physical_device_surface_formats(*physical_device, *surface)? };
+  Ok(formats)
 }
 
-fn get_target_surface_format(physical_device: &ash::vk::PhysicalDevice, surface_instance: &ash::khr::surface::Instance, surface: &ash::vk::SurfaceKHR) -> ash::vk::Format {
-  let formats = get_supported_surface_formats(physical_device, surface_instance, surface);
+/// an 8 bit UNORM format, written to as is. SRGB_NONLINEAR when there is a choice of colour space, it is the one every surface has
+fn get_target_surface_format(physical_device: &ash::vk::PhysicalDevice, surface_instance: &ash::khr::surface::Instance, surface: &ash::vk::SurfaceKHR) -> GfxResult<ash::vk::SurfaceFormatKHR> {
+  let formats = get_supported_surface_formats(physical_device, surface_instance, surface)?;
   let preferences = [ash::vk::Format::R8G8B8A8_UNORM, ash::vk::Format::B8G8R8A8_UNORM];
-  let first_preference = preferences.iter().find(|preference| {
-    formats.iter().any(|format| {
-      format.format == **preference
-    })
-  }).expect("failed to find any good surface formats");
-  return *first_preference;
-}
\ No newline at end of file
+  let first_preference = preferences.iter().find_map(|preference| {
+    let matching = formats.iter().filter(|format| format.format == *preference).collect_vec();
+    matching.iter().find(|format| format.color_space == ash::vk::ColorSpaceKHR::SRGB_NONLINEAR).or(matching.first()).map(|format| **format)
+  }).ok_or(GfxError::NoSuitableSurfaceFormat)?;
+  return Ok(first_preference);
+}
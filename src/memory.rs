@@ -2,7 +2,7 @@ use std::u32;
 
 use itertools::Itertools;
 use strum::IntoEnumIterator;
-use crate::{constants::{self, MemoryKind}, utils::split_bits};
+use crate::{constants::MemoryIntent, utils::split_bits};
 
 /// I should deprecate this.. hmm
 pub fn get_memory_type_index(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice, required_flags: &[ash::vk::MemoryPropertyFlags]) -> Option<u32> {
@@ -60,29 +60,35 @@ fn test_split_flags() {
   assert!(res.contains(&ash::vk::MemoryPropertyFlags::HOST_VISIBLE));
 }
 
-pub fn get_memory_type_index_raw(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice, required_flags: u32, memory_type_bits: u32) -> Option<u32> {
-  // the actual memory type we can use is an intersection of the indexes found in memory_type_bits, which also pass flag requirements
-  let memory_type_bits_split = split_bits(memory_type_bits);
-  let memory_type_bits_reversed = memory_type_bits_split.iter().rev(); // starts at least significant bit
-  let allowed_memory_type_indexes = 
-    memory_type_bits_reversed.enumerate()
-    .map(|(i, b)| {
-      if *b == 1 { Some(i as u32) } else { None }
-    })
-    .filter_map(|x| x)
-    .collect_vec();
-
+/// required flags must all be there. each preferred flag a type has counts for it, each avoided flag against it
+pub fn get_memory_type_index_raw(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice, required_flags: u32, preferred_flags: u32, avoided_flags: u32, memory_type_bits: u32) -> Option<u32> {
   let memory_properties = unsafe { instance.get_physical_device_memory_properties(*physical_device) };
   let all_memory_types = memory_properties.memory_types_as_slice();
-  let required_flags = ash::vk::MemoryPropertyFlags::from_raw(required_flags);
+  let flags = MemoryFlags {
+    required: ash::vk::MemoryPropertyFlags::from_raw(required_flags),
+    preferred: ash::vk::MemoryPropertyFlags::from_raw(preferred_flags),
+    avoided: ash::vk::MemoryPropertyFlags::from_raw(avoided_flags),
+  };
+  get_best_memory_type_index(all_memory_types, memory_type_bits, &flags)
+}
 
-  for allowed_index in allowed_memory_type_indexes.iter() {
-    let memory_type = all_memory_types.get(*allowed_index as usize).expect("memory type index out of bounds?");
-    let flags = memory_type.property_flags;
-    let satisfies_flags = flags & required_flags == required_flags;
-    if satisfies_flags { return Some(*allowed_index); }
+pub fn get_memory_type_index_for_intent(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice, intent: MemoryIntent, memory_type_bits: u32) -> Option<u32> {
+  let flags = get_memory_flags_from_intent(intent);
+  get_memory_type_index_raw(instance, physical_device, flags.required.as_raw(), flags.preferred.as_raw(), flags.avoided.as_raw(), memory_type_bits)
+}
+
+/// the actual memory type we can use is an intersection of the indexes found in memory_type_bits, which also pass flag requirements.
+/// ties go to the lower index, drivers list the faster types first
+pub fn get_best_memory_type_index(memory_types: &[ash::vk::MemoryType], memory_type_bits: u32, flags: &MemoryFlags) -> Option<u32> {
+  let mut best: Option<(u32, i32)> = None;
+  for (i, memory_type) in memory_types.iter().enumerate().take(32) {
+    let is_allowed = memory_type_bits & (1 << i) != 0;
+    let property_flags = memory_type.property_flags;
+    if !is_allowed || !property_flags.contains(flags.required) { continue; }
+    let score = (property_flags & flags.preferred).as_raw().count_ones() as i32 - (property_flags & flags.avoided).as_raw().count_ones() as i32;
+    if best.map_or(true, |(_, best_score)| score > best_score) { best = Some((i as u32, score)); }
   }
-  return None;
+  best.map(|(i, _)| i)
 }
 
 pub fn get_heap_index(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice, index: u32) -> u32 {
@@ -103,23 +109,52 @@ pub fn print_memory_flags(instance: &ash::Instance, physical_device: &ash::vk::P
   }
 }
 
+/// only the required flags have to exist somewhere, preferred ones are a bonus
 pub fn get_if_physical_device_supports_all_memory_requirements(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice) -> bool {
-  for kind in MemoryKind::iter() {
-    let flags = get_memory_flags_from_kind(kind);
-    let memory_type_index = get_memory_type_index(&instance, &physical_device, &flags);
+  for intent in MemoryIntent::iter() {
+    let flags = get_memory_flags_from_intent(intent);
+    let memory_type_index = get_memory_type_index(&instance, &physical_device, &[flags.required]);
     if memory_type_index.is_none() { return false; }
   }
   return true;
 }
 
-pub fn get_memory_flags_from_kind(kind: MemoryKind) -> Vec<ash::vk::MemoryPropertyFlags> {
-  match kind {
-    MemoryKind::Buffer1 => vec![
-      ash::vk::MemoryPropertyFlags::HOST_VISIBLE,
-    ],
-    MemoryKind::Image1 => vec![
-      ash::vk::MemoryPropertyFlags::HOST_VISIBLE,
-    ],
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MemoryFlags {
+  pub required: ash::vk::MemoryPropertyFlags,
+  pub preferred: ash::vk::MemoryPropertyFlags,
+  pub avoided: ash::vk::MemoryPropertyFlags,
+}
+
+pub fn get_memory_flags_from_intent(intent: MemoryIntent) -> MemoryFlags {
+  use ash::vk::MemoryPropertyFlags as Flags;
+  match intent {
+    // device local is only preferred so linear images and odd memory_type_bits still find something.
+    // host visible is avoided to leave the small BAR heap to uploads
+    MemoryIntent::GpuOnly => MemoryFlags {
+      required: Flags::empty(),
+      preferred: Flags::DEVICE_LOCAL,
+      avoided: Flags::HOST_VISIBLE,
+    },
+    // device local and host visible is resizable BAR (or the 256MiB BAR window), writes go straight to vram.
+    // otherwise plain coherent system memory. cached memory doesn't help writes
+    MemoryIntent::CpuToGpu => MemoryFlags {
+      required: Flags::HOST_VISIBLE,
+      preferred: Flags::DEVICE_LOCAL | Flags::HOST_COHERENT,
+      avoided: Flags::HOST_CACHED,
+    },
+    // reads from uncached memory are painfully slow, and device local reads cross the bus
+    MemoryIntent::GpuToCpu => MemoryFlags {
+      required: Flags::HOST_VISIBLE,
+      preferred: Flags::HOST_CACHED | Flags::HOST_COHERENT,
+      avoided: Flags::DEVICE_LOCAL,
+    },
+    // lazily allocated memory only exists on tilers, everywhere else this is GpuOnly
+    MemoryIntent::Transient => MemoryFlags {
+      required: Flags::empty(),
+      preferred: Flags::DEVICE_LOCAL | Flags::LAZILY_ALLOCATED,
+      avoided: Flags::HOST_VISIBLE,
+    },
   }
 }
 
@@ -142,4 +177,46 @@ pub fn get_memory_type_flags_from_index(instance: &ash::Instance, physical_devic
   let memory_type = get_memory_type_from_index(instance, physical_device, index);
   let flags = memory_type.property_flags;
   return flags;
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_intent_prefers_rebar_and_falls_back() {
+  use ash::vk::MemoryPropertyFlags as Flags;
+  // a discrete card with resizable BAR
+  let rebar = [
+    ash::vk::MemoryType { property_flags: Flags::DEVICE_LOCAL, heap_index: 0 },
+    ash::vk::MemoryType { property_flags: Flags::HOST_VISIBLE | Flags::HOST_COHERENT, heap_index: 0 },
+    ash::vk::MemoryType { property_flags: Flags::HOST_VISIBLE | Flags::HOST_COHERENT | Flags::HOST_CACHED, heap_index: 0 },
+    ash::vk::MemoryType { property_flags: Flags::DEVICE_LOCAL | Flags::HOST_VISIBLE | Flags::HOST_COHERENT, heap_index: 0 },
+  ];
+  let all_types = 0b1111;
+  let pick = |types: &[ash::vk::MemoryType], bits: u32, intent: MemoryIntent| get_best_memory_type_index(types, bits, &get_memory_flags_from_intent(intent));
+  assert_eq!(pick(&rebar, all_types, MemoryIntent::GpuOnly), Some(0));
+  assert_eq!(pick(&rebar, all_types, MemoryIntent::CpuToGpu), Some(3));
+  assert_eq!(pick(&rebar, all_types, MemoryIntent::GpuToCpu), Some(2));
+  assert_eq!(pick(&rebar, all_types, MemoryIntent::Transient), Some(0));
+
+  // the same card without BAR, or a resource that can't live there
+  assert_eq!(pick(&rebar, 0b0111, MemoryIntent::CpuToGpu), Some(1));
+  // nothing host visible allowed at all
+  assert_eq!(pick(&rebar, 0b0001, MemoryIntent::CpuToGpu), None);
+  // only host memory allowed, GpuOnly still gets something
+  assert_eq!(pick(&rebar, 0b0010, MemoryIntent::GpuOnly), Some(1));
+}
+
+#[test]
+fn test_intent_on_unified_memory() {
+  use ash::vk::MemoryPropertyFlags as Flags;
+  // an integrated gpu, everything is device local
+  let unified = [
+    ash::vk::MemoryType { property_flags: Flags::DEVICE_LOCAL, heap_index: 0 },
+    ash::vk::MemoryType { property_flags: Flags::DEVICE_LOCAL | Flags::HOST_VISIBLE | Flags::HOST_COHERENT, heap_index: 0 },
+    ash::vk::MemoryType { property_flags: Flags::DEVICE_LOCAL | Flags::HOST_VISIBLE | Flags::HOST_COHERENT | Flags::HOST_CACHED, heap_index: 0 },
+    ash::vk::MemoryType { property_flags: Flags::DEVICE_LOCAL | Flags::LAZILY_ALLOCATED, heap_index: 0 },
+  ];
+  let pick = |intent: MemoryIntent| get_best_memory_type_index(&unified, 0b1111, &get_memory_flags_from_intent(intent));
+  assert_eq!(pick(MemoryIntent::GpuOnly), Some(0));
+  assert_eq!(pick(MemoryIntent::CpuToGpu), Some(1));
+  assert_eq!(pick(MemoryIntent::GpuToCpu), Some(2));
+  assert_eq!(pick(MemoryIntent::Transient), Some(3));
+}
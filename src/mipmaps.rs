@@ -0,0 +1,182 @@
+use crate::upload;
+
+/// a full chain, down to 1x1
+pub fn get_mip_level_count(extent: &ash::vk::Extent3D) -> u32 {
+  let largest = extent.width.max(extent.height).max(1);
+  32 - largest.leading_zeros()
+}
+
+pub fn get_mip_extent(extent: &ash::vk::Extent3D, level: u32) -> ash::vk::Extent3D {
+  ash::vk::Extent3D::default()
+    .width((extent.width >> level).max(1))
+    .height((extent.height >> level).max(1))
+    .depth((extent.depth >> level).max(1))
+}
+
+/// blitting a mip chain needs a linear filter, and blit support on both ends
+pub fn get_if_format_supports_mip_blits(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice, format: ash::vk::Format) -> bool {
+  let props = unsafe { instance.get_physical_device_format_properties(*physical_device, format) };
+  let flags = ash::vk::FormatFeatureFlags::BLIT_SRC | ash::vk::FormatFeatureFlags::BLIT_DST | ash::vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+  props.optimal_tiling_features & flags == flags
+}
+
+/// needs a queue with graphics. every level must be in TRANSFER_DST_OPTIMAL with the first written_levels written.
+/// each level after those is blitted from the one before it, and everything ends up in TRANSFER_SRC_OPTIMAL
+pub fn record_mip_chain_blits(device: &ash::Device, command_buffer: &ash::vk::CommandBuffer, image: &ash::vk::Image, extent: &ash::vk::Extent3D, written_levels: u32, mip_levels: u32) -> () {
+  let get_levels_range = |level: u32, level_count: u32| ash::vk::ImageSubresourceRange::default()
+    .aspect_mask(ash::vk::ImageAspectFlags::COLOR)
+    .base_mip_level(level)
+    .level_count(level_count)
+    .base_array_layer(0)
+    .layer_count(1);
+  let get_level_layers = |level: u32| ash::vk::ImageSubresourceLayers::default()
+    .aspect_mask(ash::vk::ImageAspectFlags::COLOR)
+    .mip_level(level)
+    .base_array_layer(0)
+    .layer_count(1);
+  let get_far_corner = |level: u32| {
+    let mip_extent = get_mip_extent(extent, level);
+    ash::vk::Offset3D::default().x(mip_extent.width as i32).y(mip_extent.height as i32).z(mip_extent.depth as i32)
+  };
+
+  // the level just written becomes the source for the next one
+  let to_transfer_src = |level: u32, level_count: u32| ash::vk::ImageMemoryBarrier::default()
+    .old_layout(ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+    .new_layout(ash::vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+    .src_access_mask(ash::vk::AccessFlags::TRANSFER_WRITE)
+    .dst_access_mask(ash::vk::AccessFlags::TRANSFER_READ)
+    .src_queue_family_index(ash::vk::QUEUE_FAMILY_IGNORED)
+    .dst_queue_family_index(ash::vk::QUEUE_FAMILY_IGNORED)
+    .image(*image)
+    .subresource_range(get_levels_range(level, level_count));
+
+  unsafe {
+    // written levels that nothing is blitted from
+    if written_levels > 1 {
+      device.cmd_pipeline_barrier(*command_buffer, ash::vk::PipelineStageFlags::TRANSFER, ash::vk::PipelineStageFlags::TRANSFER, ash::vk::DependencyFlags::empty(), &[], &[], &[to_transfer_src(0, written_levels - 1)]);
+    }
+    for level in written_levels..mip_levels {
+      device.cmd_pipeline_barrier(*command_buffer, ash::vk::PipelineStageFlags::TRANSFER, ash::vk::PipelineStageFlags::TRANSFER, ash::vk::DependencyFlags::empty(), &[], &[], &[to_transfer_src(level - 1, 1)]);
+      let region = ash::vk::ImageBlit::default()
+        .src_offsets([ash::vk::Offset3D::default(), get_far_corner(level - 1)])
+        .dst_offsets([ash::vk::Offset3D::default(), get_far_corner(level)])
+        .src_subresource(get_level_layers(level - 1))
+        .dst_subresource(get_level_layers(level))
+      ;
+      device.cmd_blit_image(*command_buffer, *image, ash::vk::ImageLayout::TRANSFER_SRC_OPTIMAL, *image, ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[region], ash::vk::Filter::LINEAR);
+    }
+    // the last level was only ever written
+    device.cmd_pipeline_barrier(*command_buffer, ash::vk::PipelineStageFlags::TRANSFER, ash::vk::PipelineStageFlags::TRANSFER, ash::vk::DependencyFlags::empty(), &[], &[], &[to_transfer_src(mip_levels - 1, 1)]);
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ChannelEncoding {
+  Unorm8,
+  Srgb8,
+  Unorm16,
+  Float32,
+}
+
+fn get_channel_encoding(format: ash::vk::Format) -> Option<ChannelEncoding> {
+  match format {
+    ash::vk::Format::R8_UNORM | ash::vk::Format::R8G8B8A8_UNORM | ash::vk::Format::B8G8R8A8_UNORM => Some(ChannelEncoding::Unorm8),
+    ash::vk::Format::R8_SRGB | ash::vk::Format::R8G8B8A8_SRGB | ash::vk::Format::B8G8R8A8_SRGB => Some(ChannelEncoding::Srgb8),
+    ash::vk::Format::R16G16B16A16_UNORM => Some(ChannelEncoding::Unorm16),
+    ash::vk::Format::R32G32B32A32_SFLOAT => Some(ChannelEncoding::Float32),
+    _ => None,
+  }
+}
+
+/// the CPU fallback for formats that can't be blitted with a linear filter. returns every level, tightly packed one after another,
+/// or None for formats it doesn't know. a 2x2 box filter, done on linear values so SRGB textures don't darken
+pub fn get_mip_chain(bytes: &[u8], extent: &ash::vk::Extent3D, format: ash::vk::Format) -> Option<Vec<u8>> {
+  let encoding = get_channel_encoding(format)?;
+  let texel_size = upload::get_format_texel_size(format)? as usize;
+  let channel_size = match encoding { ChannelEncoding::Unorm8 | ChannelEncoding::Srgb8 => 1, ChannelEncoding::Unorm16 => 2, ChannelEncoding::Float32 => 4 };
+  let channels = texel_size / channel_size;
+
+  let mut chain = bytes.to_vec();
+  let mut values = decode_channels(bytes, encoding, channels);
+  let (mut width, mut height) = (extent.width as usize, extent.height as usize);
+  for _ in 1..get_mip_level_count(extent) {
+    let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+    let mut next = vec![0f32; next_width * next_height * channels];
+    for y in 0..next_height {
+      for x in 0..next_width {
+        // odd sizes clamp, so the last row or column is counted twice rather than read out of bounds
+        let xs = [(2 * x).min(width - 1), (2 * x + 1).min(width - 1)];
+        let ys = [(2 * y).min(height - 1), (2 * y + 1).min(height - 1)];
+        for channel in 0..channels {
+          let sum: f32 = ys.iter().flat_map(|sy| xs.iter().map(move |sx| (sy, sx))).map(|(sy, sx)| values[(sy * width + sx) * channels + channel]).sum();
+          next[(y * next_width + x) * channels + channel] = sum / 4.0;
+        }
+      }
+    }
+    chain.extend(encode_channels(&next, encoding, channels));
+    (values, width, height) = (next, next_width, next_height);
+  }
+  Some(chain)
+}
+
+fn decode_channels(bytes: &[u8], encoding: ChannelEncoding, channels: usize) -> Vec<f32> {
+  match encoding {
+    ChannelEncoding::Unorm8 => bytes.iter().map(|c| *c as f32 / 255.0).collect(),
+    ChannelEncoding::Srgb8 => bytes.iter().enumerate().map(|(i, c)| {
+      let value = *c as f32 / 255.0;
+      if is_alpha_channel(i, channels) { value } else { srgb_to_linear(value) }
+    }).collect(),
+    ChannelEncoding::Unorm16 => bytes.chunks_exact(2).map(|c| u16::from_ne_bytes([c[0], c[1]]) as f32 / 65535.0).collect(),
+    ChannelEncoding::Float32 => bytes.chunks_exact(4).map(|c| f32::from_ne_bytes([c[0], c[1], c[2], c[3]])).collect(),
+  }
+}
+
+fn encode_channels(values: &[f32], encoding: ChannelEncoding, channels: usize) -> Vec<u8> {
+  let to_unorm8 = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+  match encoding {
+    ChannelEncoding::Unorm8 => values.iter().map(|v| to_unorm8(*v)).collect(),
+    ChannelEncoding::Srgb8 => values.iter().enumerate().map(|(i, v)| {
+      if is_alpha_channel(i, channels) { to_unorm8(*v) } else { to_unorm8(linear_to_srgb(*v)) }
+    }).collect(),
+    ChannelEncoding::Unorm16 => values.iter().flat_map(|v| ((v.clamp(0.0, 1.0) * 65535.0).round() as u16).to_ne_bytes()).collect(),
+    ChannelEncoding::Float32 => values.iter().flat_map(|v| v.to_ne_bytes()).collect(),
+  }
+}
+
+/// alpha is never SRGB encoded
+fn is_alpha_channel(i: usize, channels: usize) -> bool {
+  channels == 4 && i % 4 == 3
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+  if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+  if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 }
+}
+
+#[test]
+fn test_mip_level_count() {
+  let extent = |width, height| ash::vk::Extent3D::default().width(width).height(height).depth(1);
+  assert_eq!(get_mip_level_count(&extent(1, 1)), 1);
+  assert_eq!(get_mip_level_count(&extent(256, 128)), 9);
+  assert_eq!(get_mip_level_count(&extent(5, 3)), 3);
+  assert_eq!(get_mip_extent(&extent(5, 3), 2), extent(1, 1));
+}
+
+#[test]
+fn test_cpu_mip_chain() {
+  let extent = ash::vk::Extent3D::default().width(2).height(2).depth(1);
+  let black_and_white = [0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255, 0, 0, 0, 255];
+
+  let chain = get_mip_chain(&black_and_white, &extent, ash::vk::Format::R8G8B8A8_UNORM).expect("format has a cpu fallback");
+  assert_eq!(chain.len(), 16 + 4);
+  assert_eq!(&chain[16..], &[128, 128, 128, 255]);
+
+  // half way in linear light is a lot brighter than 128 once encoded again
+  let chain = get_mip_chain(&black_and_white, &extent, ash::vk::Format::R8G8B8A8_SRGB).expect("format has a cpu fallback");
+  assert_eq!(&chain[16..], &[188, 188, 188, 255]);
+
+  assert_eq!(get_mip_chain(&black_and_white, &extent, ash::vk::Format::BC1_RGB_UNORM_BLOCK), None);
+}
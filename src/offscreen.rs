@@ -0,0 +1,73 @@
+use proc_macros::{Getters};
+use crate::{commands, constants, device_context::SharedDevice, image_state::ImageUsage, error::{GfxError, GfxResult}, create_image_with_usage, get_image_layout, read_image, resources};
+
+#[derive(Getters)]
+/// host visible, linearly tiled image that rendered pixels get copied into so the CPU can read them back
+pub struct OffscreenTarget {
+  /// owns its memory, which stays mapped
+  pub image: resources::Image,
+  pub extent: ash::vk::Extent3D,
+  pub format: ash::vk::Format,
+}
+
+/// always R8G8B8A8_UNORM, so the readback can go straight into a png
+pub fn create_offscreen_target(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice, device: &SharedDevice, queue_family_index: u32, extent: &ash::vk::Extent3D) -> GfxResult<OffscreenTarget> {
+  let format = ash::vk::Format::R8G8B8A8_UNORM;
+
+  // the blit is what converts from whatever format the source is in
+  let props = unsafe { instance.get_physical_device_format_properties(*physical_device, format) };
+  let flags = ash::vk::FormatFeatureFlags::BLIT_DST;
+  if props.linear_tiling_features & flags != flags { return Err(GfxError::UnsupportedFormat(format)); }
+  // linear tiling only has to support transfers, so ask for nothing else and check the device can make one this size
+  let usage = ash::vk::ImageUsageFlags::TRANSFER_DST;
+  let image_format_properties = unsafe {
+    instance.get_physical_device_image_format_properties(*physical_device, format, ash::vk::ImageType::TYPE_2D, ash::vk::ImageTiling::LINEAR, usage, ash::vk::ImageCreateFlags::empty())
+  }.map_err(|result| match result {
+    ash::vk::Result::ERROR_FORMAT_NOT_SUPPORTED => GfxError::UnsupportedFormat(format),
+    result => result.into(),
+  })?;
+  let max_extent = image_format_properties.max_extent;
+  if extent.width > max_extent.width || extent.height > max_extent.height { return Err(GfxError::UnsupportedFormat(format)); }
+
+  let (image, format) = create_image_with_usage(device, queue_family_index, extent, &format, ash::vk::ImageTiling::LINEAR, 1, usage)?;
+  // read back by the host, so cached memory if there is any
+  let image = resources::Image::from_handle(device, image, extent, &format, ash::vk::ImageTiling::LINEAR, constants::MemoryIntent::GpuToCpu)?;
+
+  Ok(OffscreenTarget { image, extent: *extent, format })
+}
+
+/// src_image has to be the same size as the target. its tracked state decides the barriers it needs.
+/// leaves the target in GENERAL, with the writes made visible to the host
+pub fn copy_image_to_offscreen_target(device: &SharedDevice, command_pool: &ash::vk::CommandPool, queue: &ash::vk::Queue, synchronization2: Option<&ash::khr::synchronization2::Device>, src_image: &resources::Image, target: &OffscreenTarget) -> GfxResult<()> {
+  // a blit rather than a copy, so the source can be in any blittable format. same size, nothing to filter
+  commands::immediate_submit(device, command_pool, queue, synchronization2, |recorder| {
+    recorder
+      .blit_images(src_image, &target.image, ash::vk::Filter::NEAREST)
+      .prepare_image(&target.image, ImageUsage::HostRead);
+  })
+}
+
+/// tightly packed RGBA8 pixels, row padding removed
+pub fn read_offscreen_target(device: &ash::Device, target: &OffscreenTarget) -> GfxResult<Vec<u8>> {
+  let allocation = target.image.allocation();
+  let mapped_memory = allocation.get_mapped_ptr().expect("offscreen target memory is host visible");
+
+  // memory might not be HOST_COHERENT
+  allocation.invalidate()?;
+
+  let layout = get_image_layout(device, *target.image);
+  let bytes = read_image(mapped_memory, &layout, &target.extent);
+  Ok(bytes)
+}
+
+pub fn read_offscreen_target_to_rgba_image(device: &ash::Device, target: &OffscreenTarget) -> GfxResult<image::RgbaImage> {
+  let bytes = read_offscreen_target(device, target)?;
+  let image = image::RgbaImage::from_raw(target.extent.width, target.extent.height, bytes).expect("readback buffer is the wrong size for the image");
+  Ok(image)
+}
+
+pub fn save_offscreen_target_png(device: &ash::Device, target: &OffscreenTarget, path: &str) -> GfxResult<()> {
+  let image = read_offscreen_target_to_rgba_image(device, target)?;
+  image.save_with_format(path, image::ImageFormat::Png)?;
+  Ok(())
+}
@@ -0,0 +1,219 @@
+use crate::{device_context::SharedDevice, error::GfxResult, resources};
+
+/// how a colour attachment combines what the fragment shader outputs with what is already there
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+  /// replaces it
+  Opaque,
+  /// straight alpha, colour * alpha + dst * (1 - alpha)
+  Alpha,
+  /// colour + dst * (1 - alpha), for colours already multiplied by their alpha
+  PremultipliedAlpha,
+  /// colour * alpha + dst
+  Additive,
+}
+
+impl BlendMode {
+  pub fn get_attachment_state(&self) -> ash::vk::PipelineColorBlendAttachmentState {
+    use ash::vk::BlendFactor as F;
+    let state = ash::vk::PipelineColorBlendAttachmentState::default()
+      .color_write_mask(ash::vk::ColorComponentFlags::RGBA)
+      .color_blend_op(ash::vk::BlendOp::ADD)
+      .alpha_blend_op(ash::vk::BlendOp::ADD);
+    let (src_color, dst_color, src_alpha, dst_alpha) = match self {
+      BlendMode::Opaque => return state.blend_enable(false),
+      BlendMode::Alpha => (F::SRC_ALPHA, F::ONE_MINUS_SRC_ALPHA, F::ONE, F::ONE_MINUS_SRC_ALPHA),
+      BlendMode::PremultipliedAlpha => (F::ONE, F::ONE_MINUS_SRC_ALPHA, F::ONE, F::ONE_MINUS_SRC_ALPHA),
+      BlendMode::Additive => (F::SRC_ALPHA, F::ONE, F::ONE, F::ONE),
+    };
+    state
+      .blend_enable(true)
+      .src_color_blend_factor(src_color)
+      .dst_color_blend_factor(dst_color)
+      .src_alpha_blend_factor(src_alpha)
+      .dst_alpha_blend_factor(dst_alpha)
+  }
+}
+
+/// depth testing, and whether passing fragments write their depth
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DepthState {
+  pub write: bool,
+  pub compare_op: ash::vk::CompareOp,
+}
+
+/// None turns depth testing off
+pub fn get_depth_stencil_state(depth: Option<DepthState>) -> ash::vk::PipelineDepthStencilStateCreateInfo<'static> {
+  let state = ash::vk::PipelineDepthStencilStateCreateInfo::default()
+    .depth_bounds_test_enable(false)
+    .stencil_test_enable(false);
+  match depth {
+    Some(depth) => state.depth_test_enable(true).depth_write_enable(depth.write).depth_compare_op(depth.compare_op),
+    None => state.depth_test_enable(false).depth_write_enable(false).depth_compare_op(ash::vk::CompareOp::ALWAYS),
+  }
+}
+
+/// start with GraphicsPipelineBuilder::new and chain the rest. the defaults are no vertex input, a triangle list, filled,
+/// no culling with counter clockwise front faces, no attachments and no depth test.
+/// pipelines are made for dynamic rendering, so there's no render pass. the viewport and scissor are always dynamic,
+/// see set_viewport_and_scissor
+#[derive(Clone, Debug)]
+pub struct GraphicsPipelineBuilder {
+  pub vertex_shader: ash::vk::ShaderModule,
+  pub fragment_shader: ash::vk::ShaderModule,
+  pub vertex_bindings: Vec<ash::vk::VertexInputBindingDescription>,
+  pub vertex_attributes: Vec<ash::vk::VertexInputAttributeDescription>,
+  pub topology: ash::vk::PrimitiveTopology,
+  pub polygon_mode: ash::vk::PolygonMode,
+  pub cull_mode: ash::vk::CullModeFlags,
+  pub front_face: ash::vk::FrontFace,
+  pub blend: BlendMode,
+  pub color_formats: Vec<ash::vk::Format>,
+  pub depth_format: Option<ash::vk::Format>,
+  pub depth: Option<DepthState>,
+}
+
+impl GraphicsPipelineBuilder {
+  /// both shaders start at main
+  pub fn new(vertex_shader: &ash::vk::ShaderModule, fragment_shader: &ash::vk::ShaderModule) -> Self {
+    GraphicsPipelineBuilder {
+      vertex_shader: *vertex_shader,
+      fragment_shader: *fragment_shader,
+      vertex_bindings: vec![],
+      vertex_attributes: vec![],
+      topology: ash::vk::PrimitiveTopology::TRIANGLE_LIST,
+      polygon_mode: ash::vk::PolygonMode::FILL,
+      cull_mode: ash::vk::CullModeFlags::NONE,
+      front_face: ash::vk::FrontFace::COUNTER_CLOCKWISE,
+      blend: BlendMode::Opaque,
+      color_formats: vec![],
+      depth_format: None,
+      depth: None,
+    }
+  }
+
+  pub fn vertex_input(mut self, bindings: &[ash::vk::VertexInputBindingDescription], attributes: &[ash::vk::VertexInputAttributeDescription]) -> Self {
+    self.vertex_bindings = bindings.to_vec();
+    self.vertex_attributes = attributes.to_vec();
+    self
+  }
+
+  pub fn topology(mut self, topology: ash::vk::PrimitiveTopology) -> Self {
+    self.topology = topology;
+    self
+  }
+
+  /// anything but FILL needs the fillModeNonSolid feature
+  pub fn polygon_mode(mut self, polygon_mode: ash::vk::PolygonMode) -> Self {
+    self.polygon_mode = polygon_mode;
+    self
+  }
+
+  pub fn cull_mode(mut self, cull_mode: ash::vk::CullModeFlags, front_face: ash::vk::FrontFace) -> Self {
+    self.cull_mode = cull_mode;
+    self.front_face = front_face;
+    self
+  }
+
+  /// the same for every colour attachment
+  pub fn blend(mut self, blend: BlendMode) -> Self {
+    self.blend = blend;
+    self
+  }
+
+  /// the formats of the attachments it draws to, in order. has to match what begin_rendering is given
+  pub fn color_formats(mut self, color_formats: &[ash::vk::Format]) -> Self {
+    self.color_formats = color_formats.to_vec();
+    self
+  }
+
+  /// the depth attachment's format, needed for depth testing
+  pub fn depth_format(mut self, depth_format: Option<ash::vk::Format>) -> Self {
+    self.depth_format = depth_format;
+    self
+  }
+
+  pub fn depth(mut self, depth: Option<DepthState>) -> Self {
+    self.depth = depth;
+    self
+  }
+
+  pub fn build(&self, device: &SharedDevice, layout: &ash::vk::PipelineLayout) -> GfxResult<resources::Pipeline> {
+    let entry_point = c"main";
+    let stages = [
+      ash::vk::PipelineShaderStageCreateInfo::default().stage(ash::vk::ShaderStageFlags::VERTEX).module(self.vertex_shader).name(entry_point),
+      ash::vk::PipelineShaderStageCreateInfo::default().stage(ash::vk::ShaderStageFlags::FRAGMENT).module(self.fragment_shader).name(entry_point),
+    ];
+    let vertex_input = ash::vk::PipelineVertexInputStateCreateInfo::default()
+      .vertex_binding_descriptions(&self.vertex_bindings)
+      .vertex_attribute_descriptions(&self.vertex_attributes);
+    let input_assembly = ash::vk::PipelineInputAssemblyStateCreateInfo::default().topology(self.topology);
+    // the counts still have to be given when the viewport and scissor are dynamic
+    let viewport = ash::vk::PipelineViewportStateCreateInfo::default().viewport_count(1).scissor_count(1);
+    let rasterization = ash::vk::PipelineRasterizationStateCreateInfo::default()
+      .polygon_mode(self.polygon_mode)
+      .cull_mode(self.cull_mode)
+      .front_face(self.front_face)
+      .line_width(1.0);
+    let multisample = ash::vk::PipelineMultisampleStateCreateInfo::default().rasterization_samples(ash::vk::SampleCountFlags::TYPE_1);
+    let depth_stencil = get_depth_stencil_state(self.depth);
+    let attachments = vec![self.blend.get_attachment_state(); self.color_formats.len()];
+    let color_blend = ash::vk::PipelineColorBlendStateCreateInfo::default().attachments(&attachments);
+    let dynamic_states = [ash::vk::DynamicState::VIEWPORT, ash::vk::DynamicState::SCISSOR];
+    let dynamic_state = ash::vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states);
+    // takes the place of the render pass
+    let mut rendering = ash::vk::PipelineRenderingCreateInfo::default()
+      .color_attachment_formats(&self.color_formats)
+      .depth_attachment_format(self.depth_format.unwrap_or(ash::vk::Format::UNDEFINED));
+    let create_info = ash::vk::GraphicsPipelineCreateInfo::default()
+      .stages(&stages)
+      .vertex_input_state(&vertex_input)
+      .input_assembly_state(&input_assembly)
+      .viewport_state(&viewport)
+      .rasterization_state(&rasterization)
+      .multisample_state(&multisample)
+      .depth_stencil_state(&depth_stencil)
+      .color_blend_state(&color_blend)
+      .dynamic_state(&dynamic_state)
+      .layout(*layout)
+      .push_next(&mut rendering);
+    let pipelines = unsafe { device.create_graphics_pipelines(ash::vk::PipelineCache::null(), &[create_info], None).map_err(|(_, result)| result)? };
+    Ok(resources::Pipeline { pipeline: pipelines[0], device: device.clone() })
+  }
+}
+
+/// compute pipelines have nothing to configure but the shader, which starts at main
+pub fn create_compute_pipeline(device: &SharedDevice, layout: &ash::vk::PipelineLayout, shader: &ash::vk::ShaderModule) -> GfxResult<resources::Pipeline> {
+  let stage = ash::vk::PipelineShaderStageCreateInfo::default().stage(ash::vk::ShaderStageFlags::COMPUTE).module(*shader).name(c"main");
+  let create_info = ash::vk::ComputePipelineCreateInfo::default().stage(stage).layout(*layout);
+  let pipelines = unsafe { device.create_compute_pipelines(ash::vk::PipelineCache::null(), &[create_info], None).map_err(|(_, result)| result)? };
+  Ok(resources::Pipeline { pipeline: pipelines[0], device: device.clone() })
+}
+
+/// covers the whole extent, with depths from 0 to 1
+pub fn set_viewport_and_scissor(device: &ash::Device, command_buffer: &ash::vk::CommandBuffer, extent: &ash::vk::Extent2D) -> () {
+  let viewport = ash::vk::Viewport::default()
+    .width(extent.width as f32)
+    .height(extent.height as f32)
+    .min_depth(0.0)
+    .max_depth(1.0);
+  let scissor = ash::vk::Rect2D::default().extent(*extent);
+  unsafe {
+    device.cmd_set_viewport(*command_buffer, 0, &[viewport]);
+    device.cmd_set_scissor(*command_buffer, 0, &[scissor]);
+  }
+}
+
+#[test]
+fn test_fixed_function_state() {
+  let alpha = BlendMode::Alpha.get_attachment_state();
+  assert_eq!(alpha.blend_enable, ash::vk::TRUE);
+  assert_eq!((alpha.src_color_blend_factor, alpha.dst_color_blend_factor), (ash::vk::BlendFactor::SRC_ALPHA, ash::vk::BlendFactor::ONE_MINUS_SRC_ALPHA));
+  let opaque = BlendMode::Opaque.get_attachment_state();
+  assert_eq!(opaque.blend_enable, ash::vk::FALSE);
+  assert_eq!(opaque.color_write_mask, ash::vk::ColorComponentFlags::RGBA);
+
+  let depth = get_depth_stencil_state(Some(DepthState { write: false, compare_op: ash::vk::CompareOp::LESS_OR_EQUAL }));
+  assert_eq!((depth.depth_test_enable, depth.depth_write_enable), (ash::vk::TRUE, ash::vk::FALSE));
+  assert_eq!(get_depth_stencil_state(None).depth_test_enable, ash::vk::FALSE);
+}
@@ -0,0 +1,162 @@
+use proc_macros::{Getters};
+
+#[derive(Getters, Clone, Copy, Debug)]
+/// a queue and the command pool that records for it. pools can't be shared across families
+pub struct GfxQueue {
+  pub family_index: u32,
+  pub queue_index: u32,
+  pub queue: ash::vk::Queue,
+  pub command_pool: ash::vk::CommandPool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// where a queue comes from, decided before the device exists
+pub struct QueueAssignment {
+  pub family_index: u32,
+  pub queue_index: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+/// the main queue is always index 0 of the main family. the others are None if not requested or not available
+pub struct QueueAssignments {
+  pub main: QueueAssignment,
+  pub transfer: Option<QueueAssignment>,
+  pub compute: Option<QueueAssignment>,
+}
+
+impl QueueAssignments {
+  /// how many queues to ask for from each family, as (family_index, count)
+  pub fn get_queue_counts(&self) -> Vec<(u32, u32)> {
+    let mut counts: Vec<(u32, u32)> = vec![];
+    for assignment in [Some(self.main), self.transfer, self.compute].iter().flatten() {
+      match counts.iter_mut().find(|(family_index, _)| *family_index == assignment.family_index) {
+        Some((_, count)) => *count = (*count).max(assignment.queue_index + 1),
+        None => counts.push((assignment.family_index, assignment.queue_index + 1)),
+      }
+    }
+    counts
+  }
+}
+
+/// picks dedicated transfer and compute queues, preferring families the main queue doesn't use so the work
+/// can actually overlap. falls back to a second queue in an already used family, then to None
+pub fn get_queue_assignments(families: &[ash::vk::QueueFamilyProperties], main_family_index: u32, want_transfer: bool, want_compute: bool) -> QueueAssignments {
+  let mut used_counts = vec![0u32; families.len()];
+  used_counts[main_family_index as usize] = 1;
+  let main = QueueAssignment { family_index: main_family_index, queue_index: 0 };
+
+  let has = |family: &ash::vk::QueueFamilyProperties, flags: ash::vk::QueueFlags| family.queue_flags.contains(flags);
+  let lacks = |family: &ash::vk::QueueFamilyProperties, flags: ash::vk::QueueFlags| !family.queue_flags.intersects(flags);
+
+  // transfer. a transfer only family is usually the copy engine
+  let transfer = match want_transfer {
+    false => None,
+    true => take_queue(families, &mut used_counts, &[
+      &|family| has(family, ash::vk::QueueFlags::TRANSFER) && lacks(family, ash::vk::QueueFlags::GRAPHICS | ash::vk::QueueFlags::COMPUTE),
+      &|family| has(family, ash::vk::QueueFlags::TRANSFER) && lacks(family, ash::vk::QueueFlags::GRAPHICS),
+      &|family| has(family, ash::vk::QueueFlags::TRANSFER),
+    ]),
+  };
+
+  // async compute
+  let compute = match want_compute {
+    false => None,
+    true => take_queue(families, &mut used_counts, &[
+      &|family| has(family, ash::vk::QueueFlags::COMPUTE) && lacks(family, ash::vk::QueueFlags::GRAPHICS),
+      &|family| has(family, ash::vk::QueueFlags::COMPUTE),
+    ]),
+  };
+
+  QueueAssignments { main, transfer, compute }
+}
+
+/// first family passing the earliest filter, unused families before ones that already have queues taken
+fn take_queue(families: &[ash::vk::QueueFamilyProperties], used_counts: &mut [u32], filters: &[&dyn Fn(&ash::vk::QueueFamilyProperties) -> bool]) -> Option<QueueAssignment> {
+  for only_unused in [true, false] {
+    for filter in filters.iter() {
+      let found = families.iter().enumerate().position(|(i, family)| {
+        let is_free = used_counts[i] < family.queue_count;
+        let is_unused = used_counts[i] == 0;
+        filter(family) && is_free && (is_unused || !only_unused)
+      });
+      if let Some(family_index) = found {
+        let queue_index = used_counts[family_index];
+        used_counts[family_index] += 1;
+        return Some(QueueAssignment { family_index: family_index as u32, queue_index });
+      }
+    }
+  }
+  None
+}
+
+/// the release half goes on the source queue, the acquire half on the destination queue, with a semaphore between them.
+/// both must name the same layouts and families. when the families match, the acquire barrier alone is an ordinary barrier
+pub fn get_image_ownership_transfer_barriers<'a>(
+  image: ash::vk::Image,
+  subresource_range: ash::vk::ImageSubresourceRange,
+  old_layout: ash::vk::ImageLayout,
+  new_layout: ash::vk::ImageLayout,
+  src_family_index: u32,
+  dst_family_index: u32,
+  src_access_mask: ash::vk::AccessFlags,
+  dst_access_mask: ash::vk::AccessFlags,
+) -> (ash::vk::ImageMemoryBarrier<'a>, ash::vk::ImageMemoryBarrier<'a>) {
+  let (src_family_index, dst_family_index) = match src_family_index == dst_family_index {
+    true => (ash::vk::QUEUE_FAMILY_IGNORED, ash::vk::QUEUE_FAMILY_IGNORED),
+    false => (src_family_index, dst_family_index),
+  };
+  let barrier = ash::vk::ImageMemoryBarrier::default()
+    .old_layout(old_layout)
+    .new_layout(new_layout)
+    .src_queue_family_index(src_family_index)
+    .dst_queue_family_index(dst_family_index)
+    .image(image)
+    .subresource_range(subresource_range);
+  // dst access means nothing on the releasing queue, src access nothing on the acquiring one
+  let release = barrier.src_access_mask(src_access_mask).dst_access_mask(ash::vk::AccessFlags::empty());
+  let acquire = match src_family_index == dst_family_index {
+    true => barrier.src_access_mask(src_access_mask).dst_access_mask(dst_access_mask),
+    false => barrier.src_access_mask(ash::vk::AccessFlags::empty()).dst_access_mask(dst_access_mask),
+  };
+  (release, acquire)
+}
+
+pub fn destroy_queue_command_pool(device: &ash::Device, queue: &GfxQueue) -> () {
+  unsafe { device.destroy_command_pool(queue.command_pool, None); }
+}
+
+#[test]
+fn test_queue_assignments_prefer_dedicated_families() {
+  let all = ash::vk::QueueFlags::GRAPHICS | ash::vk::QueueFlags::COMPUTE | ash::vk::QueueFlags::TRANSFER;
+  // roughly what a desktop amd or nvidia card reports
+  let families = [
+    ash::vk::QueueFamilyProperties::default().queue_flags(all).queue_count(16),
+    ash::vk::QueueFamilyProperties::default().queue_flags(ash::vk::QueueFlags::COMPUTE | ash::vk::QueueFlags::TRANSFER).queue_count(8),
+    ash::vk::QueueFamilyProperties::default().queue_flags(ash::vk::QueueFlags::TRANSFER).queue_count(2),
+  ];
+  let assignments = get_queue_assignments(&families, 0, true, true);
+  assert_eq!(assignments.transfer, Some(QueueAssignment { family_index: 2, queue_index: 0 }));
+  assert_eq!(assignments.compute, Some(QueueAssignment { family_index: 1, queue_index: 0 }));
+  assert_eq!(assignments.get_queue_counts(), vec![(0, 1), (2, 1), (1, 1)]);
+
+  let assignments = get_queue_assignments(&families, 0, false, true);
+  assert_eq!(assignments.transfer, None);
+  assert_eq!(assignments.compute, Some(QueueAssignment { family_index: 1, queue_index: 0 }));
+}
+
+#[test]
+fn test_queue_assignments_fall_back_to_shared_families() {
+  let all = ash::vk::QueueFlags::GRAPHICS | ash::vk::QueueFlags::COMPUTE | ash::vk::QueueFlags::TRANSFER;
+  // one family with a few queues, like many integrated gpus
+  let families = [ash::vk::QueueFamilyProperties::default().queue_flags(all).queue_count(2)];
+  let assignments = get_queue_assignments(&families, 0, true, true);
+  assert_eq!(assignments.transfer, Some(QueueAssignment { family_index: 0, queue_index: 1 }));
+  assert_eq!(assignments.compute, None);
+  assert_eq!(assignments.get_queue_counts(), vec![(0, 2)]);
+
+  // one family with one queue, like llvmpipe
+  let families = [ash::vk::QueueFamilyProperties::default().queue_flags(all).queue_count(1)];
+  let assignments = get_queue_assignments(&families, 0, true, true);
+  assert_eq!(assignments.transfer, None);
+  assert_eq!(assignments.compute, None);
+}
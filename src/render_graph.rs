@@ -0,0 +1,665 @@
+use crate::{allocator::ResourceKind, commands::{self, BufferBarrier, ImageBarrier}, constants::MemoryIntent, device_context::SharedDevice, error::GfxResult, image_state::{ImageState, ImageUsage}, resources};
+
+// a frame is declared as passes, each saying which images and buffers it reads and writes. compiling works out an order,
+// drops passes nothing uses, gives transient images memory (shared between those that are never alive at the same time)
+// and plans the barriers between passes. recording then only has to replay that plan around each pass's commands
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImageHandle(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BufferHandle(pub usize);
+
+/// what a buffer is about to be used for, the buffer version of ImageUsage
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BufferUsage {
+  TransferSrc,
+  TransferDst,
+  Vertex,
+  Index,
+  Uniform,
+  /// read by fragment or compute shaders
+  StorageRead,
+  /// read and written by compute shaders
+  StorageReadWrite,
+  Indirect,
+  HostRead,
+}
+
+impl BufferUsage {
+  /// only stages and accesses that also exist without synchronization2
+  pub fn get_stage_access(&self) -> (ash::vk::PipelineStageFlags2, ash::vk::AccessFlags2) {
+    use ash::vk::{AccessFlags2 as A, PipelineStageFlags2 as S};
+    match self {
+      BufferUsage::TransferSrc => (S::TRANSFER, A::TRANSFER_READ),
+      BufferUsage::TransferDst => (S::TRANSFER, A::TRANSFER_WRITE),
+      BufferUsage::Vertex => (S::VERTEX_INPUT, A::VERTEX_ATTRIBUTE_READ),
+      BufferUsage::Index => (S::VERTEX_INPUT, A::INDEX_READ),
+      BufferUsage::Uniform => (S::VERTEX_SHADER | S::FRAGMENT_SHADER | S::COMPUTE_SHADER, A::UNIFORM_READ),
+      BufferUsage::StorageRead => (S::FRAGMENT_SHADER | S::COMPUTE_SHADER, A::SHADER_READ),
+      BufferUsage::StorageReadWrite => (S::COMPUTE_SHADER, A::SHADER_READ | A::SHADER_WRITE),
+      BufferUsage::Indirect => (S::DRAW_INDIRECT, A::INDIRECT_COMMAND_READ),
+      BufferUsage::HostRead => (S::HOST, A::HOST_READ),
+    }
+  }
+
+  pub fn get_if_writes(&self) -> bool {
+    matches!(self, BufferUsage::TransferDst | BufferUsage::StorageReadWrite)
+  }
+}
+
+/// what a transient image has to be created with to be used this way
+pub fn get_image_usage_flags(usage: ImageUsage) -> ash::vk::ImageUsageFlags {
+  use ash::vk::ImageUsageFlags as F;
+  match usage {
+    ImageUsage::TransferSrc => F::TRANSFER_SRC,
+    ImageUsage::TransferDst => F::TRANSFER_DST,
+    ImageUsage::ShaderRead => F::SAMPLED,
+    ImageUsage::StorageImage | ImageUsage::StorageImageWrite => F::STORAGE,
+    ImageUsage::ColorAttachment => F::COLOR_ATTACHMENT,
+    ImageUsage::DepthAttachment => F::DEPTH_STENCIL_ATTACHMENT,
+    ImageUsage::General => F::TRANSFER_SRC | F::TRANSFER_DST | F::STORAGE,
+    // neither can happen to memory only the graph knows about
+    ImageUsage::Present | ImageUsage::HostRead => F::empty(),
+  }
+}
+
+/// barriers on depth images have to name the depth aspect, and the stencil one too if the format has it
+pub fn get_format_aspect(format: ash::vk::Format) -> ash::vk::ImageAspectFlags {
+  use ash::vk::{Format as F, ImageAspectFlags as A};
+  match format {
+    F::D16_UNORM | F::X8_D24_UNORM_PACK32 | F::D32_SFLOAT => A::DEPTH,
+    F::D16_UNORM_S8_UINT | F::D24_UNORM_S8_UINT | F::D32_SFLOAT_S8_UINT => A::DEPTH | A::STENCIL,
+    F::S8_UINT => A::STENCIL,
+    _ => A::COLOR,
+  }
+}
+
+pub enum ImageSource<'a> {
+  /// its ImageState is where the graph starts from, and is updated when the graph is recorded
+  Imported(&'a resources::Image),
+  /// not tracked anywhere, e.g. a swapchain image. initial None means the contents don't matter
+  External { image: ash::vk::Image, initial: Option<ImageUsage>, final_usage: Option<ImageUsage> },
+  /// only exists while the graph does, created when it is compiled
+  Transient,
+}
+
+pub struct GraphImage<'a> {
+  pub name: String,
+  pub extent: ash::vk::Extent3D,
+  pub format: ash::vk::Format,
+  pub mip_levels: u32,
+  pub source: ImageSource<'a>,
+}
+
+impl<'a> GraphImage<'a> {
+  pub fn get_if_transient(&self) -> bool {
+    matches!(self.source, ImageSource::Transient)
+  }
+}
+
+/// buffers are always imported, initial is what the buffer was last used for before the graph
+pub struct GraphBuffer {
+  pub name: String,
+  pub buffer: ash::vk::Buffer,
+  pub initial: Option<BufferUsage>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageAccess {
+  pub image: ImageHandle,
+  pub usage: ImageUsage,
+  pub writes: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BufferAccess {
+  pub buffer: BufferHandle,
+  pub usage: BufferUsage,
+  pub writes: bool,
+}
+
+/// the resources a pass's commands use, resolved to vulkan handles
+pub struct PassContext<'g> {
+  pub device: &'g ash::Device,
+  pub synchronization2: Option<&'g ash::khr::synchronization2::Device>,
+  pub command_buffer: ash::vk::CommandBuffer,
+  pub images: &'g [ResolvedImage],
+  pub buffers: &'g [GraphBuffer],
+}
+
+impl<'g> PassContext<'g> {
+  pub fn get_image(&self, image: ImageHandle) -> &ResolvedImage {
+    &self.images[image.0]
+  }
+
+  pub fn get_buffer(&self, buffer: BufferHandle) -> ash::vk::Buffer {
+    self.buffers[buffer.0].buffer
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ResolvedImage {
+  pub image: ash::vk::Image,
+  pub extent: ash::vk::Extent3D,
+  pub format: ash::vk::Format,
+}
+
+/// records a pass's commands, borrowing whatever the graph was declared with
+pub type RecordFn<'a> = Box<dyn Fn(&PassContext) -> GfxResult<()> + 'a>;
+
+/// every image a pass uses is in the layout of the usage it declared by the time record runs
+pub struct Pass<'a> {
+  pub name: String,
+  pub image_accesses: Vec<ImageAccess>,
+  pub buffer_accesses: Vec<BufferAccess>,
+  pub record: RecordFn<'a>,
+}
+
+impl<'a> Pass<'a> {
+  pub fn read_image(&mut self, image: ImageHandle, usage: ImageUsage) -> &mut Self {
+    self.image_accesses.push(ImageAccess { image, usage, writes: false });
+    self
+  }
+
+  /// storage images are read as well, the pass still sees what was there before
+  pub fn write_image(&mut self, image: ImageHandle, usage: ImageUsage) -> &mut Self {
+    debug_assert!(usage.get_if_writes(), "pass {} writes an image as {:?}, which only reads", self.name, usage);
+    self.image_accesses.push(ImageAccess { image, usage, writes: true });
+    self
+  }
+
+  pub fn read_buffer(&mut self, buffer: BufferHandle, usage: BufferUsage) -> &mut Self {
+    self.buffer_accesses.push(BufferAccess { buffer, usage, writes: false });
+    self
+  }
+
+  pub fn write_buffer(&mut self, buffer: BufferHandle, usage: BufferUsage) -> &mut Self {
+    debug_assert!(usage.get_if_writes(), "pass {} writes a buffer as {:?}, which only reads", self.name, usage);
+    self.buffer_accesses.push(BufferAccess { buffer, usage, writes: true });
+    self
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Resource {
+  Image(usize),
+  Buffer(usize),
+}
+
+/// passes are indices into RenderGraph::passes
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+  /// the passes each pass has to come after
+  pub dependencies: Vec<Vec<usize>>,
+  pub order: Vec<usize>,
+  /// nothing outside the graph sees what these write
+  pub culled: Vec<usize>,
+}
+
+/// declared once per frame, or whenever what the frame does changes
+#[derive(Default)]
+pub struct RenderGraph<'a> {
+  pub images: Vec<GraphImage<'a>>,
+  pub buffers: Vec<GraphBuffer>,
+  pub passes: Vec<Pass<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+  pub fn new() -> Self {
+    RenderGraph { images: vec![], buffers: vec![], passes: vec![] }
+  }
+
+  pub fn import_image(&mut self, name: &str, image: &'a resources::Image) -> ImageHandle {
+    self.add_image(name, &image.extent, &image.format, image.mip_levels, ImageSource::Imported(image))
+  }
+
+  /// final_usage is what the image is left ready for after the last pass, e.g. Present for a swapchain image.
+  /// with initial None the first barrier waits at the stage the image is first used at, so a semaphore guarding it
+  /// (like the swapchain's acquire semaphore) has to be waited on at that stage or earlier
+  pub fn import_external_image(&mut self, name: &str, image: ash::vk::Image, extent: &ash::vk::Extent3D, format: &ash::vk::Format, initial: Option<ImageUsage>, final_usage: Option<ImageUsage>) -> ImageHandle {
+    self.add_image(name, extent, format, 1, ImageSource::External { image, initial, final_usage })
+  }
+
+  /// contents start undefined. the image is created with whatever usages the passes declare
+  pub fn create_transient_image(&mut self, name: &str, extent: &ash::vk::Extent3D, format: &ash::vk::Format) -> ImageHandle {
+    self.add_image(name, extent, format, 1, ImageSource::Transient)
+  }
+
+  pub fn import_buffer(&mut self, name: &str, buffer: ash::vk::Buffer, initial: Option<BufferUsage>) -> BufferHandle {
+    self.buffers.push(GraphBuffer { name: name.to_string(), buffer, initial });
+    BufferHandle(self.buffers.len() - 1)
+  }
+
+  /// declare what the pass uses on what this returns. passes that touch the same resource keep the order they were added in
+  pub fn add_pass(&mut self, name: &str, record: impl Fn(&PassContext) -> GfxResult<()> + 'a) -> &mut Pass<'a> {
+    self.passes.push(Pass { name: name.to_string(), image_accesses: vec![], buffer_accesses: vec![], record: Box::new(record) });
+    self.passes.last_mut().expect("pass was just pushed")
+  }
+
+  fn add_image(&mut self, name: &str, extent: &ash::vk::Extent3D, format: &ash::vk::Format, mip_levels: u32, source: ImageSource<'a>) -> ImageHandle {
+    self.images.push(GraphImage { name: name.to_string(), extent: *extent, format: *format, mip_levels, source });
+    ImageHandle(self.images.len() - 1)
+  }
+
+  fn get_accesses(&self, pass: usize) -> Vec<(Resource, bool)> {
+    let pass = &self.passes[pass];
+    pass.image_accesses.iter().map(|access| (Resource::Image(access.image.0), access.writes))
+      .chain(pass.buffer_accesses.iter().map(|access| (Resource::Buffer(access.buffer.0), access.writes)))
+      .collect()
+  }
+
+  /// reads come after the last write before them, writes after every earlier read and write of the resource
+  pub fn get_dependencies(&self) -> Vec<Vec<usize>> {
+    let mut last_writer = std::collections::HashMap::<Resource, usize>::new();
+    let mut readers = std::collections::HashMap::<Resource, Vec<usize>>::new();
+    let mut dependencies = vec![vec![]; self.passes.len()];
+    for (pass, pass_dependencies) in dependencies.iter_mut().enumerate() {
+      let accesses = self.get_accesses(pass);
+      for (resource, writes) in &accesses {
+        pass_dependencies.extend(last_writer.get(resource).copied());
+        if *writes { pass_dependencies.extend(readers.get(resource).into_iter().flatten().copied()); }
+      }
+      for (resource, writes) in accesses {
+        match writes {
+          true => {
+            last_writer.insert(resource, pass);
+            readers.remove(&resource);
+          },
+          false => readers.entry(resource).or_default().push(pass),
+        }
+      }
+      pass_dependencies.retain(|dependency| *dependency != pass);
+      pass_dependencies.sort();
+      pass_dependencies.dedup();
+    }
+    dependencies
+  }
+
+  /// a pass is kept if it writes something that outlives the graph, or a kept pass depends on it.
+  /// the order is the declared one, except that a pass is put off when an independent one is ready,
+  /// so work that has to wait on a barrier isn't straight after what it waits for
+  pub fn get_schedule(&self) -> Schedule {
+    let dependencies = self.get_dependencies();
+    let mut kept = vec![false; self.passes.len()];
+    let mut pending = (0..self.passes.len())
+      .filter(|pass| self.get_accesses(*pass).iter().any(|(resource, writes)| *writes && self.get_if_outlives_graph(resource)))
+      .collect::<Vec<_>>();
+    while let Some(pass) = pending.pop() {
+      if kept[pass] { continue; }
+      kept[pass] = true;
+      pending.extend(dependencies[pass].iter().copied());
+    }
+
+    let mut scheduled = vec![false; self.passes.len()];
+    let mut order: Vec<usize> = vec![];
+    let mut remaining = (0..self.passes.len()).filter(|pass| kept[*pass]).collect::<Vec<_>>();
+    while !remaining.is_empty() {
+      let ready = remaining.iter().copied().filter(|pass| dependencies[*pass].iter().all(|dependency| scheduled[*dependency])).collect::<Vec<_>>();
+      let independent = ready.iter().copied().find(|pass| order.last().is_none_or(|last| !dependencies[*pass].contains(last)));
+      let next = independent.or(ready.first().copied()).expect("dependencies only point at earlier passes, so one is always ready");
+      scheduled[next] = true;
+      order.push(next);
+      remaining.retain(|pass| *pass != next);
+    }
+
+    let culled = (0..self.passes.len()).filter(|pass| !kept[*pass]).collect();
+    Schedule { dependencies, order, culled }
+  }
+
+  fn get_if_outlives_graph(&self, resource: &Resource) -> bool {
+    match resource {
+      Resource::Image(image) => !self.images[*image].get_if_transient(),
+      Resource::Buffer(_) => true,
+    }
+  }
+
+  /// creates the transient images and plans every barrier. the initial state of imported images is read now,
+  /// so record straight after, before anything else uses them
+  pub fn compile(self, device: &SharedDevice) -> GfxResult<CompiledRenderGraph<'a>> {
+    let schedule = self.get_schedule();
+    let mut compiled = CompiledRenderGraph {
+      resolved_images: vec![],
+      slots: vec![None; self.images.len()],
+      pass_barriers: vec![],
+      final_barriers: vec![],
+      final_levels: vec![],
+      transient_images: vec![],
+      memory: vec![],
+      device: device.clone(),
+      graph: self,
+      schedule,
+    };
+    compiled.create_transient_images()?;
+    compiled.plan_barriers();
+    Ok(compiled)
+  }
+}
+
+/// which memory slot each transient goes in, given the first and last position in the pass order it is used at.
+/// transients share a slot when their lifetimes don't overlap and some memory type suits all of them
+pub fn get_aliasing_slots(lifetimes: &[(usize, usize)], memory_type_bits: &[u32]) -> Vec<usize> {
+  let mut by_start = (0..lifetimes.len()).collect::<Vec<_>>();
+  by_start.sort_by_key(|transient| lifetimes[*transient].0);
+  // the last position each slot is used at, and the memory types all of its transients allow
+  let mut slots: Vec<(usize, u32)> = vec![];
+  let mut assigned = vec![0; lifetimes.len()];
+  for transient in by_start {
+    let (start, end) = lifetimes[transient];
+    let free = slots.iter().position(|(slot_end, slot_bits)| *slot_end < start && slot_bits & memory_type_bits[transient] != 0);
+    assigned[transient] = match free {
+      Some(slot) => {
+        slots[slot] = (end, slots[slot].1 & memory_type_bits[transient]);
+        slot
+      },
+      None => {
+        slots.push((end, memory_type_bits[transient]));
+        slots.len() - 1
+      },
+    };
+  }
+  assigned
+}
+
+/// keep it until the GPU is done with what it recorded, it owns the transient images and their memory
+pub struct CompiledRenderGraph<'a> {
+  pub graph: RenderGraph<'a>,
+  pub schedule: Schedule,
+  pub resolved_images: Vec<ResolvedImage>,
+  /// the memory slot of each transient image that is used
+  pub slots: Vec<Option<usize>>,
+  /// recorded before the pass at the same position in the order
+  pub pass_barriers: Vec<(Vec<ImageBarrier>, Vec<BufferBarrier>)>,
+  /// leave external images ready for their final usage
+  pub final_barriers: Vec<ImageBarrier>,
+  /// what each image is left as. written back to imported images when recorded
+  pub final_levels: Vec<Vec<Option<ImageUsage>>>,
+  transient_images: Vec<ash::vk::Image>,
+  memory: Vec<resources::Allocation>,
+  device: SharedDevice,
+}
+
+impl<'a> CompiledRenderGraph<'a> {
+  fn create_transient_images(&mut self) -> GfxResult<()> {
+    let position = self.schedule.order.iter().enumerate().map(|(position, pass)| (*pass, position)).collect::<std::collections::HashMap<_, _>>();
+    let mut transients = vec![];
+    for (i, image) in self.graph.images.iter().enumerate() {
+      let resolved = |vk_image| ResolvedImage { image: vk_image, extent: image.extent, format: image.format };
+      match &image.source {
+        ImageSource::Imported(imported) => self.resolved_images.push(resolved(imported.image)),
+        ImageSource::External { image: external, .. } => self.resolved_images.push(resolved(*external)),
+        ImageSource::Transient => {
+          self.resolved_images.push(resolved(ash::vk::Image::null()));
+          let accesses = self.schedule.order.iter()
+            .flat_map(|pass| self.graph.passes[*pass].image_accesses.iter().filter(|access| access.image.0 == i).map(move |access| (*pass, access.usage)))
+            .collect::<Vec<_>>();
+          if accesses.is_empty() { continue; }
+          let usage_flags = accesses.iter().fold(ash::vk::ImageUsageFlags::empty(), |flags, (_, usage)| flags | get_image_usage_flags(*usage));
+          let lifetime = (position[&accesses[0].0], position[&accesses[accesses.len() - 1].0]);
+          let vk_image = create_transient_image(&self.device, image, usage_flags)?;
+          self.transient_images.push(vk_image);
+          self.resolved_images[i].image = vk_image;
+          transients.push((i, lifetime, unsafe { self.device.get_image_memory_requirements(vk_image) }));
+        },
+      }
+    }
+
+    let lifetimes = transients.iter().map(|(_, lifetime, _)| *lifetime).collect::<Vec<_>>();
+    let memory_type_bits = transients.iter().map(|(_, _, requirements)| requirements.memory_type_bits).collect::<Vec<_>>();
+    let assigned = get_aliasing_slots(&lifetimes, &memory_type_bits);
+    let slot_count = assigned.iter().map(|slot| slot + 1).max().unwrap_or(0);
+    for slot in 0..slot_count {
+      let members = transients.iter().zip(&assigned).filter(|(_, assigned)| **assigned == slot).map(|(transient, _)| transient).collect::<Vec<_>>();
+      let requirements = members.iter().fold(ash::vk::MemoryRequirements::default().memory_type_bits(u32::MAX), |combined, (_, _, requirements)| {
+        combined
+          .size(combined.size.max(requirements.size))
+          .alignment(combined.alignment.max(requirements.alignment))
+          .memory_type_bits(combined.memory_type_bits & requirements.memory_type_bits)
+      });
+      let raw = self.device.allocator().borrow_mut().allocate(&requirements, MemoryIntent::GpuOnly, ResourceKind::OptimalImage)?;
+      let allocation = resources::Allocation::new(&self.device, raw);
+      for (image, _, _) in members {
+        unsafe { self.device.bind_image_memory(self.resolved_images[*image].image, allocation.memory(), allocation.offset())?; }
+        self.slots[*image] = Some(slot);
+      }
+      self.memory.push(allocation);
+    }
+    Ok(())
+  }
+
+  fn plan_barriers(&mut self) -> () {
+    let images = &self.graph.images;
+    let states = images.iter().map(|image| match &image.source {
+      ImageSource::Imported(imported) => ImageState { levels: std::sync::Mutex::new(imported.state.get_levels()) },
+      ImageSource::External { initial, .. } => ImageState { levels: std::sync::Mutex::new(vec![*initial; image.mip_levels as usize]) },
+      ImageSource::Transient => ImageState::new(image.mip_levels),
+    }).collect::<Vec<_>>();
+    let mut first_use = vec![true; images.len()];
+    // what the last transient in each slot was used as, the next one has to wait for it before reusing the memory
+    let mut slot_usage = vec![None; self.memory.len()];
+    let mut buffer_states = self.graph.buffers.iter().map(|buffer| BufferState::new(buffer.initial)).collect::<Vec<_>>();
+
+    for pass in &self.schedule.order {
+      let pass = &self.graph.passes[*pass];
+      let mut image_barriers = vec![];
+      for access in &pass.image_accesses {
+        let i = access.image.0;
+        let image = &images[i];
+        let mut barriers = states[i].transition(self.resolved_images[i].image, access.usage, 0, image.mip_levels, &image.name);
+        if first_use[i] {
+          match (&image.source, self.slots[i]) {
+            (ImageSource::External { initial: None, .. }, _) => {
+              barriers.iter_mut().for_each(|barrier| barrier.src_stage = barrier.dst_stage);
+            },
+            (ImageSource::Transient, Some(slot)) => {
+              if let Some(previous) = slot_usage[slot] {
+                let (stage, access) = ImageUsage::get_stage_access(&previous);
+                barriers.iter_mut().for_each(|barrier| { barrier.src_stage = stage; barrier.src_access = commands::get_write_accesses(access); });
+              }
+            },
+            _ => {},
+          }
+          first_use[i] = false;
+        }
+        if let Some(slot) = self.slots[i] { slot_usage[slot] = Some(access.usage); }
+        let aspect = get_format_aspect(image.format);
+        image_barriers.extend(barriers.into_iter().map(|barrier| barrier.aspect(aspect)));
+      }
+      let buffer_barriers = pass.buffer_accesses.iter()
+        .filter_map(|access| buffer_states[access.buffer.0].use_as(access.usage).map(|(stage, access_flags)| {
+          let (dst_stage, dst_access) = access.usage.get_stage_access();
+          BufferBarrier::new(self.graph.buffers[access.buffer.0].buffer, stage, access_flags, dst_stage, dst_access)
+        }))
+        .collect();
+      self.pass_barriers.push((image_barriers, buffer_barriers));
+    }
+
+    for (i, image) in images.iter().enumerate() {
+      if let ImageSource::External { final_usage: Some(final_usage), .. } = image.source {
+        let aspect = get_format_aspect(image.format);
+        let barriers = states[i].transition(self.resolved_images[i].image, final_usage, 0, image.mip_levels, &image.name);
+        self.final_barriers.extend(barriers.into_iter().map(|barrier| barrier.aspect(aspect)));
+      }
+    }
+    self.final_levels = states.iter().map(|state| state.get_levels()).collect();
+  }
+
+  /// the passes and their barriers in order, into a command buffer that has begun recording. once per compile
+  pub fn record(&self, synchronization2: Option<&ash::khr::synchronization2::Device>, command_buffer: &ash::vk::CommandBuffer) -> GfxResult<()> {
+    let context = PassContext {
+      device: &self.device,
+      synchronization2,
+      command_buffer: *command_buffer,
+      images: &self.resolved_images,
+      buffers: &self.graph.buffers,
+    };
+    for (pass, (image_barriers, buffer_barriers)) in self.schedule.order.iter().zip(&self.pass_barriers) {
+      commands::record_barriers(&self.device, synchronization2, command_buffer, image_barriers, buffer_barriers);
+      (self.graph.passes[*pass].record)(&context)?;
+    }
+    commands::record_barriers(&self.device, synchronization2, command_buffer, &self.final_barriers, &[]);
+    for (image, levels) in self.graph.images.iter().zip(&self.final_levels) {
+      if let ImageSource::Imported(imported) = image.source {
+        *imported.state.levels.lock().expect("image state lock poisoned") = levels.clone();
+      }
+    }
+    Ok(())
+  }
+
+  /// one line per pass in the order they run, then the culled passes and the images
+  pub fn to_text(&self) -> String {
+    let mut text = String::new();
+    for (position, (pass, (image_barriers, buffer_barriers))) in self.schedule.order.iter().zip(&self.pass_barriers).enumerate() {
+      let pass = &self.graph.passes[*pass];
+      let accesses = pass.image_accesses.iter().map(|access| format!("{} {} as {:?}", get_access_verb(access.writes), self.graph.images[access.image.0].name, access.usage))
+        .chain(pass.buffer_accesses.iter().map(|access| format!("{} {} as {:?}", get_access_verb(access.writes), self.graph.buffers[access.buffer.0].name, access.usage)))
+        .collect::<Vec<_>>();
+      text += &format!("{}: {} ({} image barriers, {} buffer barriers) {}\n", position, pass.name, image_barriers.len(), buffer_barriers.len(), accesses.join(", "));
+    }
+    for pass in &self.schedule.culled {
+      text += &format!("culled: {}\n", self.graph.passes[*pass].name);
+    }
+    for (image, slot) in self.graph.images.iter().zip(&self.slots) {
+      let source = match (&image.source, slot) {
+        (ImageSource::Imported(_), _) => "imported".to_string(),
+        (ImageSource::External { final_usage, .. }, _) => format!("external, left as {:?}", final_usage),
+        (ImageSource::Transient, Some(slot)) => format!("transient in memory slot {}", slot),
+        (ImageSource::Transient, None) => "transient, unused".to_string(),
+      };
+      text += &format!("image {}: {}x{} {:?}, {}\n", image.name, image.extent.width, image.extent.height, image.format, source);
+    }
+    text
+  }
+
+  /// graphviz. passes are boxes and resources ellipses, culled passes are dashed and aliased images share a colour
+  pub fn to_dot(&self) -> String {
+    let colors = ["lightblue", "lightpink", "palegreen", "khaki", "plum", "lightsalmon"];
+    let mut dot = "digraph render_graph {\n  rankdir=LR;\n".to_string();
+    for (i, pass) in self.graph.passes.iter().enumerate() {
+      let culled = self.schedule.culled.contains(&i);
+      let label = match self.schedule.order.iter().position(|scheduled| *scheduled == i) {
+        Some(position) => format!("{}: {}", position, pass.name),
+        None => pass.name.clone(),
+      };
+      dot += &format!("  pass{} [shape=box, label=\"{}\"{}];\n", i, label, if culled { ", style=dashed" } else { "" });
+    }
+    for (i, (image, slot)) in self.graph.images.iter().zip(&self.slots).enumerate() {
+      let fill = slot.map(|slot| format!(", style=filled, fillcolor={}", colors[slot % colors.len()])).unwrap_or_default();
+      dot += &format!("  image{} [shape=ellipse, label=\"{}\"{}];\n", i, image.name, fill);
+    }
+    for (i, buffer) in self.graph.buffers.iter().enumerate() {
+      dot += &format!("  buffer{} [shape=ellipse, label=\"{}\"];\n", i, buffer.name);
+    }
+    for (i, pass) in self.graph.passes.iter().enumerate() {
+      let edges = pass.image_accesses.iter().map(|access| (format!("image{}", access.image.0), access.writes, format!("{:?}", access.usage)))
+        .chain(pass.buffer_accesses.iter().map(|access| (format!("buffer{}", access.buffer.0), access.writes, format!("{:?}", access.usage))));
+      for (resource, writes, usage) in edges {
+        dot += &match writes {
+          true => format!("  pass{} -> {} [label=\"{}\"];\n", i, resource, usage),
+          false => format!("  {} -> pass{} [label=\"{}\"];\n", resource, i, usage),
+        };
+      }
+    }
+    dot + "}\n"
+  }
+}
+
+impl<'a> Drop for CompiledRenderGraph<'a> {
+  fn drop(&mut self) {
+    // before the memory they are bound to, which drops after this
+    for image in &self.transient_images {
+      unsafe { self.device.destroy_image(*image, None); }
+    }
+  }
+}
+
+fn get_access_verb(writes: bool) -> &'static str {
+  if writes { "writes" } else { "reads" }
+}
+
+/// the last write to a buffer, and the reads since that have already waited for it
+struct BufferState {
+  write: Option<BufferUsage>,
+  reads: Vec<BufferUsage>,
+}
+
+impl BufferState {
+  fn new(initial: Option<BufferUsage>) -> Self {
+    match initial {
+      Some(usage) if usage.get_if_writes() => BufferState { write: Some(usage), reads: vec![] },
+      initial => BufferState { write: None, reads: initial.into_iter().collect() },
+    }
+  }
+
+  /// what a barrier before using the buffer this way has to wait for, if anything
+  fn use_as(&mut self, usage: BufferUsage) -> Option<(ash::vk::PipelineStageFlags2, ash::vk::AccessFlags2)> {
+    let write = self.write.map(|write| write.get_stage_access());
+    match usage.get_if_writes() {
+      true => {
+        let read_stages = self.reads.iter().fold(ash::vk::PipelineStageFlags2::NONE, |stages, read| stages | read.get_stage_access().0);
+        let (write_stage, write_access) = write.unwrap_or((ash::vk::PipelineStageFlags2::NONE, ash::vk::AccessFlags2::NONE));
+        self.write = Some(usage);
+        self.reads.clear();
+        match write_stage | read_stages {
+          stages if stages == ash::vk::PipelineStageFlags2::NONE => None,
+          stages => Some((stages, write_access)),
+        }
+      },
+      false => {
+        let already_waited = self.reads.contains(&usage);
+        self.reads.push(usage);
+        match already_waited {
+          true => None,
+          false => write,
+        }
+      },
+    }
+  }
+}
+
+fn create_transient_image(device: &ash::Device, image: &GraphImage, usage: ash::vk::ImageUsageFlags) -> GfxResult<ash::vk::Image> {
+  let create_info = ash::vk::ImageCreateInfo::default()
+    .image_type(ash::vk::ImageType::TYPE_2D)
+    .initial_layout(ash::vk::ImageLayout::UNDEFINED)
+    .format(image.format)
+    .extent(image.extent)
+    .tiling(ash::vk::ImageTiling::OPTIMAL)
+    .usage(usage)
+    .sharing_mode(ash::vk::SharingMode::EXCLUSIVE)
+    .samples(ash::vk::SampleCountFlags::TYPE_1)
+    .mip_levels(image.mip_levels)
+    .array_layers(1)
+    ;
+  Ok(unsafe { device.create_image(&create_info, None)? })
+}
+
+#[test]
+fn test_render_graph_schedule() {
+  let extent = ash::vk::Extent3D::default().width(4).height(4).depth(1);
+  let format = ash::vk::Format::R8G8B8A8_UNORM;
+  let mut graph = RenderGraph::new();
+  let output = graph.import_external_image("output", ash::vk::Image::null(), &extent, &format, None, Some(ImageUsage::Present));
+  let scene = graph.create_transient_image("scene", &extent, &format);
+  let blurred = graph.create_transient_image("blurred", &extent, &format);
+  let unused = graph.create_transient_image("unused", &extent, &format);
+  let overlay = graph.create_transient_image("overlay", &extent, &format);
+  graph.add_pass("scene", |_| Ok(())).write_image(scene, ImageUsage::ColorAttachment);
+  graph.add_pass("blur", |_| Ok(())).read_image(scene, ImageUsage::ShaderRead).write_image(blurred, ImageUsage::ColorAttachment);
+  graph.add_pass("debug", |_| Ok(())).read_image(scene, ImageUsage::ShaderRead).write_image(unused, ImageUsage::ColorAttachment);
+  graph.add_pass("overlay", |_| Ok(())).write_image(overlay, ImageUsage::ColorAttachment);
+  graph.add_pass("composite", |_| Ok(())).read_image(blurred, ImageUsage::TransferSrc).read_image(overlay, ImageUsage::TransferSrc).write_image(output, ImageUsage::TransferDst);
+
+  let schedule = graph.get_schedule();
+  assert_eq!(schedule.dependencies, vec![vec![], vec![0], vec![0], vec![], vec![1, 3]]);
+  // nothing reads what debug writes. overlay goes between scene and blur, so blur doesn't wait straight after scene
+  assert_eq!(schedule.culled, vec![2]);
+  assert_eq!(schedule.order, vec![0, 3, 1, 4]);
+}
+
+#[test]
+fn test_aliasing_slots() {
+  // 0 and 2 never overlap, 1 overlaps both. 3 could follow 0 but can't use its memory type
+  let lifetimes = [(0, 1), (1, 3), (2, 3), (4, 5)];
+  let memory_type_bits = [0b011, 0b111, 0b001, 0b100];
+  assert_eq!(get_aliasing_slots(&lifetimes, &memory_type_bits), vec![0, 1, 0, 1]);
+}
@@ -0,0 +1,469 @@
+use proc_macros::{Getters};
+use crate::{allocator::RawAllocation, constants::MemoryIntent, device_context::SharedDevice, error::GfxResult, image_state::ImageState};
+
+// owning wrappers. each holds on to the shared device so it can destroy itself, and so the device can't go first.
+// the GPU must be done with a resource before it drops, e.g. after device_wait_idle or waiting on its fence
+
+#[derive(Getters)]
+/// memory from the device's allocator, given back when dropped. the blocks it came from can't be freed before then
+pub struct Allocation {
+  pub raw: RawAllocation,
+  #[Getters_Skip]
+  pub device: SharedDevice,
+}
+
+impl Allocation {
+  pub fn new(device: &SharedDevice, raw: RawAllocation) -> Self {
+    Allocation { raw, device: device.clone() }
+  }
+
+  pub fn memory(&self) -> ash::vk::DeviceMemory {
+    self.raw.memory
+  }
+
+  pub fn offset(&self) -> u64 {
+    self.raw.offset
+  }
+
+  pub fn get_mapped_ptr(&self) -> Option<*mut std::ffi::c_void> {
+    self.raw.get_mapped_ptr()
+  }
+
+  /// makes host writes visible to the device
+  pub fn flush(&self) -> GfxResult<()> {
+    self.device.allocator().borrow_mut().flush(&self.raw)
+  }
+
+  /// copies bytes to the start of the memory and makes them visible to the device. the memory has to be host visible
+  pub fn write_bytes(&self, bytes: &[u8]) -> GfxResult<()> {
+    let mapped_memory = self.raw.mapped_ptr.expect("only host visible memory can be written to");
+    // past the end would write over whatever the allocator put next
+    assert!(bytes.len() as u64 <= self.raw.size, "{} bytes written to an allocation of {}", bytes.len(), self.raw.size);
+    unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), mapped_memory.as_ptr(), bytes.len()); }
+    // memory might not be HOST_COHERENT
+    self.flush()
+  }
+
+  /// makes device writes visible to the host
+  pub fn invalidate(&self) -> GfxResult<()> {
+    self.device.allocator().borrow_mut().invalidate(&self.raw)
+  }
+}
+
+impl Drop for Allocation {
+  fn drop(&mut self) {
+    // already borrowed means we are unwinding from a panic inside the allocator, leaking is the lesser evil
+    if let Ok(mut allocator) = self.device.allocator().try_borrow_mut() { allocator.free(&self.raw); }
+  }
+}
+
+#[derive(Getters)]
+/// an image and the memory bound to it. the image is destroyed before its memory is freed
+pub struct Image {
+  pub image: ash::vk::Image,
+  pub allocation: Allocation,
+  pub extent: ash::vk::Extent3D,
+  pub format: ash::vk::Format,
+  pub mip_levels: u32,
+  /// what each mip level was last used for. commands::CommandRecorder::prepare_image keeps it up to date
+  pub state: ImageState,
+  #[Getters_Skip]
+  pub device: SharedDevice,
+}
+
+impl Image {
+  /// takes ownership of a bare image handle and gives it memory. the handle is destroyed if that fails
+  pub fn from_handle(device: &SharedDevice, image: ash::vk::Image, extent: &ash::vk::Extent3D, format: &ash::vk::Format, tiling: ash::vk::ImageTiling, intent: MemoryIntent) -> GfxResult<Self> {
+    Image::from_handle_with_mip_levels(device, image, extent, format, tiling, intent, 1)
+  }
+
+  /// mip_levels has to match what the image was created with
+  pub fn from_handle_with_mip_levels(device: &SharedDevice, image: ash::vk::Image, extent: &ash::vk::Extent3D, format: &ash::vk::Format, tiling: ash::vk::ImageTiling, intent: MemoryIntent, mip_levels: u32) -> GfxResult<Self> {
+    let raw = device.allocator().borrow_mut().allocate_for_image(&image, intent, tiling);
+    match raw {
+      Ok(raw) => Ok(Image { image, allocation: Allocation::new(device, raw), extent: *extent, format: *format, mip_levels, state: ImageState::new(mip_levels), device: device.clone() }),
+      Err(error) => {
+        unsafe { device.destroy_image(image, None); }
+        Err(error)
+      }
+    }
+  }
+}
+
+impl std::ops::Deref for Image {
+  type Target = ash::vk::Image;
+  fn deref(&self) -> &Self::Target {
+    &self.image
+  }
+}
+
+impl Drop for Image {
+  fn drop(&mut self) {
+    unsafe { self.device.destroy_image(self.image, None); }
+  }
+}
+
+#[derive(Getters)]
+/// a buffer and the memory bound to it. the buffer is destroyed before its memory is freed
+pub struct Buffer {
+  pub buffer: ash::vk::Buffer,
+  pub allocation: Allocation,
+  pub size: u64,
+  #[Getters_Skip]
+  pub device: SharedDevice,
+}
+
+impl Buffer {
+  /// takes ownership of a bare buffer handle and gives it memory. the handle is destroyed if that fails
+  pub fn from_handle(device: &SharedDevice, buffer: ash::vk::Buffer, size: u64, intent: MemoryIntent) -> GfxResult<Self> {
+    let raw = device.allocator().borrow_mut().allocate_for_buffer(&buffer, intent);
+    match raw {
+      Ok(raw) => Ok(Buffer { buffer, allocation: Allocation::new(device, raw), size, device: device.clone() }),
+      Err(error) => {
+        unsafe { device.destroy_buffer(buffer, None); }
+        Err(error)
+      }
+    }
+  }
+}
+
+impl std::ops::Deref for Buffer {
+  type Target = ash::vk::Buffer;
+  fn deref(&self) -> &Self::Target {
+    &self.buffer
+  }
+}
+
+impl Drop for Buffer {
+  fn drop(&mut self) {
+    unsafe { self.device.destroy_buffer(self.buffer, None); }
+  }
+}
+
+#[derive(Getters)]
+pub struct Fence {
+  pub fence: ash::vk::Fence,
+  #[Getters_Skip]
+  pub device: SharedDevice,
+}
+
+impl Fence {
+  pub fn new(device: &SharedDevice, signaled: bool) -> GfxResult<Self> {
+    let flags = if signaled { ash::vk::FenceCreateFlags::SIGNALED } else { ash::vk::FenceCreateFlags::empty() };
+    let fence = unsafe { device.create_fence(&ash::vk::FenceCreateInfo::default().flags(flags), None)? };
+    Ok(Fence { fence, device: device.clone() })
+  }
+
+  pub fn wait(&self, timeout_ns: u64) -> GfxResult<()> {
+    unsafe { self.device.wait_for_fences(&[self.fence], true, timeout_ns)?; }
+    Ok(())
+  }
+
+  pub fn reset(&self) -> GfxResult<()> {
+    unsafe { self.device.reset_fences(&[self.fence])?; }
+    Ok(())
+  }
+}
+
+impl std::ops::Deref for Fence {
+  type Target = ash::vk::Fence;
+  fn deref(&self) -> &Self::Target {
+    &self.fence
+  }
+}
+
+impl Drop for Fence {
+  fn drop(&mut self) {
+    unsafe { self.device.destroy_fence(self.fence, None); }
+  }
+}
+
+#[derive(Getters)]
+pub struct Semaphore {
+  pub semaphore: ash::vk::Semaphore,
+  #[Getters_Skip]
+  pub device: SharedDevice,
+}
+
+impl Semaphore {
+  pub fn new(device: &SharedDevice) -> GfxResult<Self> {
+    let semaphore = unsafe { device.create_semaphore(&ash::vk::SemaphoreCreateInfo::default(), None)? };
+    Ok(Semaphore { semaphore, device: device.clone() })
+  }
+}
+
+impl std::ops::Deref for Semaphore {
+  type Target = ash::vk::Semaphore;
+  fn deref(&self) -> &Self::Target {
+    &self.semaphore
+  }
+}
+
+impl Drop for Semaphore {
+  fn drop(&mut self) {
+    unsafe { self.device.destroy_semaphore(self.semaphore, None); }
+  }
+}
+
+#[derive(Getters)]
+/// a primary command buffer, given back to its pool when dropped
+pub struct CommandBuffer {
+  pub command_buffer: ash::vk::CommandBuffer,
+  pub command_pool: ash::vk::CommandPool,
+  #[Getters_Skip]
+  pub device: SharedDevice,
+}
+
+impl CommandBuffer {
+  pub fn new(device: &SharedDevice, command_pool: &ash::vk::CommandPool) -> GfxResult<Self> {
+    let command_buffer_allocate_info = ash::vk::CommandBufferAllocateInfo::default()
+      .command_buffer_count(1)
+      .command_pool(*command_pool)
+      .level(ash::vk::CommandBufferLevel::PRIMARY);
+    let command_buffers = unsafe { device.allocate_command_buffers(&command_buffer_allocate_info)? };
+    let command_buffer = *command_buffers.get(0).expect("no command buffers created?");
+    Ok(CommandBuffer { command_buffer, command_pool: *command_pool, device: device.clone() })
+  }
+}
+
+impl std::ops::Deref for CommandBuffer {
+  type Target = ash::vk::CommandBuffer;
+  fn deref(&self) -> &Self::Target {
+    &self.command_buffer
+  }
+}
+
+impl Drop for CommandBuffer {
+  fn drop(&mut self) {
+    unsafe { self.device.free_command_buffers(self.command_pool, &[self.command_buffer]); }
+  }
+}
+
+#[derive(Getters)]
+/// only needed until the pipelines using it are created
+pub struct ShaderModule {
+  pub shader_module: ash::vk::ShaderModule,
+  #[Getters_Skip]
+  pub device: SharedDevice,
+}
+
+impl ShaderModule {
+  pub fn new(device: &SharedDevice, words: &[u32]) -> GfxResult<Self> {
+    let create_info = ash::vk::ShaderModuleCreateInfo::default().code(words);
+    let shader_module = unsafe { device.create_shader_module(&create_info, None)? };
+    Ok(ShaderModule { shader_module, device: device.clone() })
+  }
+}
+
+impl std::ops::Deref for ShaderModule {
+  type Target = ash::vk::ShaderModule;
+  fn deref(&self) -> &Self::Target {
+    &self.shader_module
+  }
+}
+
+impl Drop for ShaderModule {
+  fn drop(&mut self) {
+    unsafe { self.device.destroy_shader_module(self.shader_module, None); }
+  }
+}
+
+#[derive(Getters)]
+/// has to be dropped before the image it views
+pub struct ImageView {
+  pub image_view: ash::vk::ImageView,
+  #[Getters_Skip]
+  pub device: SharedDevice,
+}
+
+impl ImageView {
+  /// a 2D view of every mip level
+  pub fn new(device: &SharedDevice, image: &ash::vk::Image, format: &ash::vk::Format, aspect_mask: ash::vk::ImageAspectFlags, mip_levels: u32) -> GfxResult<Self> {
+    let subresource_range = ash::vk::ImageSubresourceRange::default()
+      .aspect_mask(aspect_mask)
+      .base_mip_level(0)
+      .level_count(mip_levels)
+      .base_array_layer(0)
+      .layer_count(1);
+    let create_info = ash::vk::ImageViewCreateInfo::default()
+      .image(*image)
+      .view_type(ash::vk::ImageViewType::TYPE_2D)
+      .format(*format)
+      .subresource_range(subresource_range);
+    let image_view = unsafe { device.create_image_view(&create_info, None)? };
+    Ok(ImageView { image_view, device: device.clone() })
+  }
+}
+
+impl std::ops::Deref for ImageView {
+  type Target = ash::vk::ImageView;
+  fn deref(&self) -> &Self::Target {
+    &self.image_view
+  }
+}
+
+impl Drop for ImageView {
+  fn drop(&mut self) {
+    unsafe { self.device.destroy_image_view(self.image_view, None); }
+  }
+}
+
+#[derive(Getters)]
+pub struct Sampler {
+  pub sampler: ash::vk::Sampler,
+  #[Getters_Skip]
+  pub device: SharedDevice,
+}
+
+impl Sampler {
+  /// trilinear, clamped to the edges, over every mip level
+  pub fn new(device: &SharedDevice) -> GfxResult<Self> {
+    let create_info = ash::vk::SamplerCreateInfo::default()
+      .mag_filter(ash::vk::Filter::LINEAR)
+      .min_filter(ash::vk::Filter::LINEAR)
+      .mipmap_mode(ash::vk::SamplerMipmapMode::LINEAR)
+      .address_mode_u(ash::vk::SamplerAddressMode::CLAMP_TO_EDGE)
+      .address_mode_v(ash::vk::SamplerAddressMode::CLAMP_TO_EDGE)
+      .address_mode_w(ash::vk::SamplerAddressMode::CLAMP_TO_EDGE)
+      .min_lod(0.0)
+      .max_lod(ash::vk::LOD_CLAMP_NONE);
+    let sampler = unsafe { device.create_sampler(&create_info, None)? };
+    Ok(Sampler { sampler, device: device.clone() })
+  }
+}
+
+impl std::ops::Deref for Sampler {
+  type Target = ash::vk::Sampler;
+  fn deref(&self) -> &Self::Target {
+    &self.sampler
+  }
+}
+
+impl Drop for Sampler {
+  fn drop(&mut self) {
+    unsafe { self.device.destroy_sampler(self.sampler, None); }
+  }
+}
+
+#[derive(Getters)]
+pub struct DescriptorSetLayout {
+  pub descriptor_set_layout: ash::vk::DescriptorSetLayout,
+  #[Getters_Skip]
+  pub device: SharedDevice,
+}
+
+impl DescriptorSetLayout {
+  /// one descriptor per binding
+  pub fn new(device: &SharedDevice, bindings: &[(u32, ash::vk::DescriptorType, ash::vk::ShaderStageFlags)]) -> GfxResult<Self> {
+    let bindings = bindings.iter().map(|(binding, descriptor_type, stages)| {
+      ash::vk::DescriptorSetLayoutBinding::default()
+        .binding(*binding)
+        .descriptor_type(*descriptor_type)
+        .descriptor_count(1)
+        .stage_flags(*stages)
+    }).collect::<Vec<_>>();
+    let create_info = ash::vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+    let descriptor_set_layout = unsafe { device.create_descriptor_set_layout(&create_info, None)? };
+    Ok(DescriptorSetLayout { descriptor_set_layout, device: device.clone() })
+  }
+}
+
+impl std::ops::Deref for DescriptorSetLayout {
+  type Target = ash::vk::DescriptorSetLayout;
+  fn deref(&self) -> &Self::Target {
+    &self.descriptor_set_layout
+  }
+}
+
+impl Drop for DescriptorSetLayout {
+  fn drop(&mut self) {
+    unsafe { self.device.destroy_descriptor_set_layout(self.descriptor_set_layout, None); }
+  }
+}
+
+#[derive(Getters)]
+/// the sets allocated from it are freed along with it
+pub struct DescriptorPool {
+  pub descriptor_pool: ash::vk::DescriptorPool,
+  #[Getters_Skip]
+  pub device: SharedDevice,
+}
+
+impl DescriptorPool {
+  /// pool_sizes is how many descriptors of each type all the sets need between them
+  pub fn new(device: &SharedDevice, max_sets: u32, pool_sizes: &[(ash::vk::DescriptorType, u32)]) -> GfxResult<Self> {
+    let pool_sizes = pool_sizes.iter().map(|(descriptor_type, count)| ash::vk::DescriptorPoolSize::default().ty(*descriptor_type).descriptor_count(*count)).collect::<Vec<_>>();
+    let create_info = ash::vk::DescriptorPoolCreateInfo::default().max_sets(max_sets).pool_sizes(&pool_sizes);
+    let descriptor_pool = unsafe { device.create_descriptor_pool(&create_info, None)? };
+    Ok(DescriptorPool { descriptor_pool, device: device.clone() })
+  }
+
+  pub fn allocate(&self, layout: &ash::vk::DescriptorSetLayout) -> GfxResult<ash::vk::DescriptorSet> {
+    let layouts = [*layout];
+    let allocate_info = ash::vk::DescriptorSetAllocateInfo::default().descriptor_pool(self.descriptor_pool).set_layouts(&layouts);
+    let descriptor_sets = unsafe { self.device.allocate_descriptor_sets(&allocate_info)? };
+    Ok(*descriptor_sets.get(0).expect("no descriptor sets allocated?"))
+  }
+}
+
+impl std::ops::Deref for DescriptorPool {
+  type Target = ash::vk::DescriptorPool;
+  fn deref(&self) -> &Self::Target {
+    &self.descriptor_pool
+  }
+}
+
+impl Drop for DescriptorPool {
+  fn drop(&mut self) {
+    unsafe { self.device.destroy_descriptor_pool(self.descriptor_pool, None); }
+  }
+}
+
+#[derive(Getters)]
+pub struct PipelineLayout {
+  pub pipeline_layout: ash::vk::PipelineLayout,
+  #[Getters_Skip]
+  pub device: SharedDevice,
+}
+
+impl PipelineLayout {
+  pub fn new(device: &SharedDevice, set_layouts: &[ash::vk::DescriptorSetLayout], push_constant_ranges: &[ash::vk::PushConstantRange]) -> GfxResult<Self> {
+    let create_info = ash::vk::PipelineLayoutCreateInfo::default().set_layouts(set_layouts).push_constant_ranges(push_constant_ranges);
+    let pipeline_layout = unsafe { device.create_pipeline_layout(&create_info, None)? };
+    Ok(PipelineLayout { pipeline_layout, device: device.clone() })
+  }
+}
+
+impl std::ops::Deref for PipelineLayout {
+  type Target = ash::vk::PipelineLayout;
+  fn deref(&self) -> &Self::Target {
+    &self.pipeline_layout
+  }
+}
+
+impl Drop for PipelineLayout {
+  fn drop(&mut self) {
+    unsafe { self.device.destroy_pipeline_layout(self.pipeline_layout, None); }
+  }
+}
+
+#[derive(Getters)]
+/// graphics or compute, see pipeline::GraphicsPipelineBuilder
+pub struct Pipeline {
+  pub pipeline: ash::vk::Pipeline,
+  #[Getters_Skip]
+  pub device: SharedDevice,
+}
+
+impl std::ops::Deref for Pipeline {
+  type Target = ash::vk::Pipeline;
+  fn deref(&self) -> &Self::Target {
+    &self.pipeline
+  }
+}
+
+impl Drop for Pipeline {
+  fn drop(&mut self) {
+    unsafe { self.device.destroy_pipeline(self.pipeline, None); }
+  }
+}
@@ -0,0 +1,31 @@
+use crate::{device_context::SharedDevice, error::{GfxError, GfxResult}, resources};
+
+// shaders are GLSL in shaders/, compiled to SPIR-V in assets/shaders/ with glslc. the command is at the top of each file
+
+pub const SPIRV_MAGIC: u32 = 0x07230203;
+
+/// the words of a SPIR-V module, which has to be whole words and start with the magic number. byte swapped modules are swapped back
+pub fn get_spirv_words(bytes: &[u8]) -> Result<Vec<u32>, String> {
+  ash::util::read_spv(&mut std::io::Cursor::new(bytes)).map_err(|error| error.to_string())
+}
+
+pub fn load_spirv(path: &str) -> GfxResult<Vec<u32>> {
+  let bytes = std::fs::read(path).map_err(|error| GfxError::InvalidShader(path.to_string(), error.to_string()))?;
+  get_spirv_words(&bytes).map_err(|reason| GfxError::InvalidShader(path.to_string(), reason))
+}
+
+pub fn create_shader_module(device: &SharedDevice, path: &str) -> GfxResult<resources::ShaderModule> {
+  resources::ShaderModule::new(device, &load_spirv(path)?)
+}
+
+#[test]
+fn test_spirv_words() {
+  let words = load_spirv("./assets/shaders/fullscreen_quad.vert.spv").expect("shader is in the repo");
+  assert_eq!(words[0], SPIRV_MAGIC);
+
+  let swapped = words.iter().flat_map(|word| word.to_be_bytes()).collect::<Vec<_>>();
+  assert_eq!(get_spirv_words(&swapped), Ok(words));
+  assert!(get_spirv_words(&[0x03, 0x02, 0x23]).is_err());
+  assert!(get_spirv_words(&[0; 8]).is_err());
+  assert!(matches!(load_spirv("./assets/shaders/missing.spv"), Err(GfxError::InvalidShader(..))));
+}
@@ -0,0 +1,148 @@
+use proc_macros::{Getters};
+use crate::{device_context::SharedDevice, error::{GfxError, GfxResult}, resources};
+
+/// how frames reach the screen. each falls back to FIFO, the only present mode every surface has
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PresentPreference {
+  /// FIFO. never tears, waits for vblank
+  Vsync,
+  /// MAILBOX. never tears, but a newer frame replaces a queued one instead of waiting
+  LowLatency,
+  /// IMMEDIATE, then MAILBOX, then FIFO_RELAXED. can tear
+  Uncapped,
+}
+
+#[derive(Getters, Clone, Copy, Debug, PartialEq)]
+/// what the swapchain is asked for. kept by GFXWindow so a recreated swapchain is negotiated the same way
+pub struct SwapchainPreferences {
+  pub present: PresentPreference,
+  /// three images instead of one more than the surface minimum
+  pub triple_buffering: bool,
+}
+
+#[derive(Getters, Clone, Copy, Debug, PartialEq)]
+/// what the surface actually allowed
+pub struct SwapchainSettings {
+  pub present_mode: ash::vk::PresentModeKHR,
+  /// the minimum asked for, the driver can make more
+  pub image_count: u32,
+  pub composite_alpha: ash::vk::CompositeAlphaFlagsKHR,
+  pub pre_transform: ash::vk::SurfaceTransformFlagsKHR,
+  pub extent: ash::vk::Extent2D,
+}
+
+/// pass the swapchain being replaced as old_swapchain, or null. the caller still has to destroy it
+pub fn create_swapchain(physical_device: &ash::vk::PhysicalDevice, swapchain_device: &ash::khr::swapchain::Device, surface: &ash::vk::SurfaceKHR, surface_instance: &ash::khr::surface::Instance, window_size: &winit::dpi::PhysicalSize<u32>, surface_format: &ash::vk::SurfaceFormatKHR, preferences: &SwapchainPreferences, old_swapchain: ash::vk::SwapchainKHR) -> GfxResult<(ash::vk::SwapchainKHR, SwapchainSettings)> {
+  let capabilities = unsafe { surface_instance.get_physical_device_surface_capabilities(*physical_device, *surface)? };
+  let present_modes = unsafe { surface_instance.get_physical_device_surface_present_modes(*physical_device, *surface)? };
+  let usage_flags = ash::vk::ImageUsageFlags::COLOR_ATTACHMENT | ash::vk::ImageUsageFlags::TRANSFER_DST;
+  if usage_flags & capabilities.supported_usage_flags != usage_flags { return Err(GfxError::UnsupportedImageUsage(usage_flags & !capabilities.supported_usage_flags)); }
+  let settings = get_swapchain_settings(&capabilities, &present_modes, window_size, preferences);
+  let create_info =
+    ash::vk::SwapchainCreateInfoKHR::default()
+    .surface(*surface)
+    .min_image_count(settings.image_count)
+    .image_color_space(surface_format.color_space)
+    .image_format(surface_format.format)
+    .image_extent(settings.extent)
+    .image_usage(usage_flags)
+    .image_sharing_mode(ash::vk::SharingMode::EXCLUSIVE)
+    .pre_transform(settings.pre_transform)
+    .composite_alpha(settings.composite_alpha)
+    .present_mode(settings.present_mode)
+    .clipped(true)
+    .image_array_layers(1)
+    .old_swapchain(old_swapchain)
+    ;
+  let swapchain = unsafe { swapchain_device.create_swapchain(&create_info, None)? };
+  Ok((swapchain, settings))
+}
+
+/// a colour view of each swapchain image, for dynamic rendering to draw into. they have to go before the swapchain does
+pub fn create_swapchain_image_views(device: &SharedDevice, swapchain_images: &[ash::vk::Image], format: &ash::vk::Format) -> GfxResult<Vec<resources::ImageView>> {
+  swapchain_images.iter()
+    .map(|image| resources::ImageView::new(device, image, format, ash::vk::ImageAspectFlags::COLOR, 1))
+    .collect()
+}
+
+pub fn get_swapchain_settings(capabilities: &ash::vk::SurfaceCapabilitiesKHR, present_modes: &[ash::vk::PresentModeKHR], window_size: &winit::dpi::PhysicalSize<u32>, preferences: &SwapchainPreferences) -> SwapchainSettings {
+  SwapchainSettings {
+    present_mode: get_present_mode(present_modes, preferences.present),
+    image_count: get_swapchain_image_count(capabilities, preferences.triple_buffering),
+    composite_alpha: get_composite_alpha(capabilities.supported_composite_alpha),
+    // anything else means rotating everything we draw ourselves
+    pre_transform: match capabilities.supported_transforms.contains(ash::vk::SurfaceTransformFlagsKHR::IDENTITY) {
+      true => ash::vk::SurfaceTransformFlagsKHR::IDENTITY,
+      false => capabilities.current_transform,
+    },
+    extent: get_swapchain_extent(capabilities, window_size),
+  }
+}
+
+/// the first supported mode in order of preference
+pub fn get_present_mode(present_modes: &[ash::vk::PresentModeKHR], preference: PresentPreference) -> ash::vk::PresentModeKHR {
+  use ash::vk::PresentModeKHR as P;
+  let preferences: &[P] = match preference {
+    PresentPreference::Vsync => &[],
+    PresentPreference::LowLatency => &[P::MAILBOX],
+    PresentPreference::Uncapped => &[P::IMMEDIATE, P::MAILBOX, P::FIFO_RELAXED],
+  };
+  preferences.iter().find(|mode| present_modes.contains(mode)).copied().unwrap_or(P::FIFO)
+}
+
+/// one more than the minimum so we don't wait on the driver to get an image back, or three for triple buffering.
+/// a max_image_count of 0 means there is no maximum
+pub fn get_swapchain_image_count(capabilities: &ash::vk::SurfaceCapabilitiesKHR, triple_buffering: bool) -> u32 {
+  let desired = if triple_buffering { 3 } else { capabilities.min_image_count + 1 };
+  let count = desired.max(capabilities.min_image_count);
+  match capabilities.max_image_count {
+    0 => count,
+    max => count.min(max),
+  }
+}
+
+/// opaque if possible. surfaces always support at least one
+pub fn get_composite_alpha(supported: ash::vk::CompositeAlphaFlagsKHR) -> ash::vk::CompositeAlphaFlagsKHR {
+  use ash::vk::CompositeAlphaFlagsKHR as A;
+  [A::OPAQUE, A::INHERIT, A::PRE_MULTIPLIED, A::POST_MULTIPLIED].into_iter().find(|alpha| supported.contains(*alpha)).unwrap_or(A::OPAQUE)
+}
+
+/// the surface decides, unless it leaves it to us with a current extent of u32::MAX. then the window size, within the allowed range.
+/// zero while the window is minimised, when no swapchain can be made
+pub fn get_swapchain_extent(capabilities: &ash::vk::SurfaceCapabilitiesKHR, window_size: &winit::dpi::PhysicalSize<u32>) -> ash::vk::Extent2D {
+  if capabilities.current_extent.width != u32::MAX { return capabilities.current_extent; }
+  ash::vk::Extent2D::default()
+    .width(window_size.width.clamp(capabilities.min_image_extent.width, capabilities.max_image_extent.width))
+    .height(window_size.height.clamp(capabilities.min_image_extent.height, capabilities.max_image_extent.height))
+}
+
+#[test]
+fn test_swapchain_extent() {
+  let window_size = winit::dpi::PhysicalSize::new(5000, 300);
+  let capabilities = ash::vk::SurfaceCapabilitiesKHR::default()
+    .current_extent(ash::vk::Extent2D::default().width(800).height(600))
+    .min_image_extent(ash::vk::Extent2D::default().width(1).height(1))
+    .max_image_extent(ash::vk::Extent2D::default().width(4096).height(4096));
+  assert_eq!(get_swapchain_extent(&capabilities, &window_size), ash::vk::Extent2D::default().width(800).height(600));
+
+  // wayland leaves it to the window
+  let capabilities = capabilities.current_extent(ash::vk::Extent2D::default().width(u32::MAX).height(u32::MAX));
+  assert_eq!(get_swapchain_extent(&capabilities, &window_size), ash::vk::Extent2D::default().width(4096).height(300));
+}
+
+#[test]
+fn test_swapchain_negotiation() {
+  use ash::vk::PresentModeKHR as P;
+  assert_eq!(get_present_mode(&[P::FIFO, P::MAILBOX], PresentPreference::Vsync), P::FIFO);
+  assert_eq!(get_present_mode(&[P::FIFO, P::MAILBOX], PresentPreference::LowLatency), P::MAILBOX);
+  assert_eq!(get_present_mode(&[P::FIFO], PresentPreference::LowLatency), P::FIFO);
+  assert_eq!(get_present_mode(&[P::FIFO, P::FIFO_RELAXED, P::MAILBOX], PresentPreference::Uncapped), P::MAILBOX);
+
+  let capabilities = ash::vk::SurfaceCapabilitiesKHR::default().min_image_count(2).max_image_count(0);
+  assert_eq!(get_swapchain_image_count(&capabilities, false), 3);
+  assert_eq!(get_swapchain_image_count(&capabilities.min_image_count(4), true), 4);
+  assert_eq!(get_swapchain_image_count(&capabilities.min_image_count(1).max_image_count(2), true), 2);
+
+  let alpha = ash::vk::CompositeAlphaFlagsKHR::PRE_MULTIPLIED | ash::vk::CompositeAlphaFlagsKHR::INHERIT;
+  assert_eq!(get_composite_alpha(alpha), ash::vk::CompositeAlphaFlagsKHR::INHERIT);
+}
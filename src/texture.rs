@@ -0,0 +1,177 @@
+use proc_macros::{Getters};
+use crate::{block_formats, error::{GfxError, GfxResult}, gfx_headless::GFXHeadless, mipmaps, resources, texture_containers, upload};
+
+/// how the texel values should be read. colour textures are usually Srgb, normal maps and other data Linear.
+/// only 8 bit formats have SRGB variants, 16 bit and float textures are always read as stored
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorSpace {
+  Srgb,
+  Linear,
+}
+
+#[derive(Getters, Debug)]
+/// decoded pixels, tightly packed in `format`, ready for an upload::UploadBatch.
+/// holds mip_levels levels one after another, KTX2 and DDS files can come with their whole chain
+pub struct TextureData {
+  pub bytes: Vec<u8>,
+  pub extent: ash::vk::Extent3D,
+  pub format: ash::vk::Format,
+  pub mip_levels: u32,
+}
+
+#[derive(Getters)]
+/// a DEVICE_LOCAL, OPTIMAL image holding a decoded file, in TRANSFER_SRC_OPTIMAL and owned by the main queue's family
+pub struct Texture {
+  pub image: resources::Image,
+  pub color_space: ColorSpace,
+}
+
+impl Texture {
+  /// KTX2 and DDS files keep their format and mip chain. anything else is decoded by the image crate, going by the extension
+  pub fn from_path(gfx_headless: &GFXHeadless, path: &str, color_space: ColorSpace, mipmaps: bool) -> GfxResult<Texture> {
+    let bytes = std::fs::read(path)?;
+    let data = match texture_containers::get_if_container(&bytes) {
+      true => texture_containers::parse_container(&bytes, color_space)?,
+      false => {
+        let image = image::ImageReader::with_format(std::io::Cursor::new(&bytes), image::ImageFormat::from_path(path)?).decode()?;
+        get_texture_data(image, color_space)
+      },
+    };
+    Texture::from_data(gfx_headless, &data, color_space, mipmaps)
+  }
+
+  /// the file type is guessed from the contents. that doesn't work for TGA, which has no magic number
+  pub fn from_bytes(gfx_headless: &GFXHeadless, bytes: &[u8], color_space: ColorSpace, mipmaps: bool) -> GfxResult<Texture> {
+    Texture::from_data(gfx_headless, &decode_texture_bytes(bytes, color_space)?, color_space, mipmaps)
+  }
+
+  /// block compressed data is decoded on the CPU when the device can't sample it.
+  /// with mipmaps a single level image gets a whole chain, by blits when the format can be linearly filtered and on the CPU otherwise.
+  /// images that came with their own levels, and compressed ones, are left as they are
+  pub fn from_data(gfx_headless: &GFXHeadless, data: &TextureData, color_space: ColorSpace, mipmaps: bool) -> GfxResult<Texture> {
+    unpack!(gfx_headless, instance, physical_device, device);
+    let decompressed = match block_formats::get_format_compression(data.format) {
+      Some(compression) if !gfx_headless.enabled_texture_compression().contains(&compression) || check_texture_format_support(instance, physical_device, data.format).is_err() => {
+        let (bytes, format) = block_formats::decompress_mip_chain(&data.bytes, &data.extent, data.format, data.mip_levels).ok_or(GfxError::UnsupportedFormat(data.format))?;
+        Some(TextureData { bytes, extent: data.extent, format, mip_levels: data.mip_levels })
+      },
+      _ => None,
+    };
+    let data = decompressed.as_ref().unwrap_or(data);
+    check_texture_format_support(instance, physical_device, data.format)?;
+
+    let can_generate_mips = data.mip_levels == 1 && block_formats::get_format_compression(data.format).is_none();
+    let mip_levels = if mipmaps && can_generate_mips { mipmaps::get_mip_level_count(&data.extent) } else { data.mip_levels };
+    let cpu_mip_chain = match mip_levels > data.mip_levels && !mipmaps::get_if_format_supports_mip_blits(instance, physical_device, data.format) {
+      true => Some(mipmaps::get_mip_chain(&data.bytes, &data.extent, data.format).ok_or(GfxError::UnsupportedFormat(data.format))?),
+      false => None,
+    };
+    let mut batch = upload::UploadBatch::new();
+    match cpu_mip_chain.as_ref() {
+      Some(chain) => batch.add_image_with_mip_levels(chain, &data.extent, &data.format, mip_levels, mip_levels),
+      None => batch.add_image_with_mip_levels(&data.bytes, &data.extent, &data.format, mip_levels, data.mip_levels),
+    };
+    let mut images = batch.submit(instance, physical_device, device, &gfx_headless.get_transfer_queue_or_main(), &gfx_headless.get_main_queue())?;
+    Ok(Texture { image: images.remove(0), color_space })
+  }
+}
+
+impl std::ops::Deref for Texture {
+  type Target = ash::vk::Image;
+  fn deref(&self) -> &Self::Target {
+    &self.image.image
+  }
+}
+
+/// KTX2 and DDS files are parsed, not decoded
+pub fn decode_texture_bytes(bytes: &[u8], color_space: ColorSpace) -> GfxResult<TextureData> {
+  if texture_containers::get_if_container(bytes) { return texture_containers::parse_container(bytes, color_space); }
+  let image = image::ImageReader::new(std::io::Cursor::new(bytes)).with_guessed_format()?.decode()?;
+  Ok(get_texture_data(image, color_space))
+}
+
+/// everything is widened to four channels, three channel formats are rarely supported for sampling
+pub fn get_texture_data(image: image::DynamicImage, color_space: ColorSpace) -> TextureData {
+  let extent = ash::vk::Extent3D::default().width(image.width()).height(image.height()).depth(1);
+  let format = get_texture_format(image.color(), color_space);
+  let bytes = match format {
+    ash::vk::Format::R16G16B16A16_UNORM => image.into_rgba16().into_raw().iter().flat_map(|c| c.to_ne_bytes()).collect(),
+    ash::vk::Format::R32G32B32A32_SFLOAT => image.into_rgba32f().into_raw().iter().flat_map(|c| c.to_ne_bytes()).collect(),
+    _ => image.into_rgba8().into_raw(),
+  };
+  TextureData { bytes, extent, format, mip_levels: 1 }
+}
+
+/// keeps the precision of the source. float images (HDR) can't be SRGB, and neither can 16 bit ones
+pub fn get_texture_format(color_type: image::ColorType, color_space: ColorSpace) -> ash::vk::Format {
+  match color_type {
+    image::ColorType::Rgb32F | image::ColorType::Rgba32F => ash::vk::Format::R32G32B32A32_SFLOAT,
+    image::ColorType::L16 | image::ColorType::La16 | image::ColorType::Rgb16 | image::ColorType::Rgba16 => ash::vk::Format::R16G16B16A16_UNORM,
+    _ => match color_space {
+      ColorSpace::Srgb => ash::vk::Format::R8G8B8A8_SRGB,
+      ColorSpace::Linear => ash::vk::Format::R8G8B8A8_UNORM,
+    },
+  }
+}
+
+/// every usage upload::UploadBatch creates the image with, so unsupported ones fail here rather than in vkCreateImage
+pub fn check_texture_format_support(instance: &ash::Instance, physical_device: &ash::vk::PhysicalDevice, format: ash::vk::Format) -> GfxResult<()> {
+  let props = unsafe { instance.get_physical_device_format_properties(*physical_device, format) };
+  let flags = get_format_features_for_usage(upload::UPLOAD_IMAGE_USAGE);
+  if props.optimal_tiling_features & flags != flags { return Err(GfxError::UnsupportedFormat(format)); }
+  Ok(())
+}
+
+/// the format features an image needs to be created with usage
+pub fn get_format_features_for_usage(usage: ash::vk::ImageUsageFlags) -> ash::vk::FormatFeatureFlags {
+  use ash::vk::{FormatFeatureFlags as F, ImageUsageFlags as U};
+  [
+    (U::TRANSFER_SRC, F::TRANSFER_SRC),
+    (U::TRANSFER_DST, F::TRANSFER_DST),
+    (U::SAMPLED, F::SAMPLED_IMAGE),
+    (U::STORAGE, F::STORAGE_IMAGE),
+    (U::COLOR_ATTACHMENT, F::COLOR_ATTACHMENT),
+    (U::DEPTH_STENCIL_ATTACHMENT, F::DEPTH_STENCIL_ATTACHMENT),
+  ].iter()
+    .filter(|(usage_flag, _)| usage.contains(*usage_flag))
+    .fold(F::empty(), |features, (_, feature)| features | *feature)
+}
+
+#[test]
+fn test_texture_formats_follow_source() {
+  let rgb8 = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(3, 2, image::Rgb([10, 20, 30])));
+  let data = get_texture_data(rgb8.clone(), ColorSpace::Srgb);
+  assert_eq!(data.format, ash::vk::Format::R8G8B8A8_SRGB);
+  assert_eq!(data.bytes.len(), 3 * 2 * 4);
+  assert_eq!(&data.bytes[0..4], &[10, 20, 30, 255]);
+  assert_eq!(get_texture_data(rgb8, ColorSpace::Linear).format, ash::vk::Format::R8G8B8A8_UNORM);
+
+  let rgb16 = image::DynamicImage::ImageRgb16(image::ImageBuffer::from_pixel(2, 2, image::Rgb([1000u16, 2000, 3000])));
+  let data = get_texture_data(rgb16, ColorSpace::Srgb);
+  assert_eq!(data.format, ash::vk::Format::R16G16B16A16_UNORM);
+  assert_eq!(data.bytes.len(), 2 * 2 * 8);
+  assert_eq!(&data.bytes[0..8], [1000u16, 2000, 3000, u16::MAX].iter().flat_map(|c| c.to_ne_bytes()).collect::<Vec<_>>().as_slice());
+
+  let rgb32f = image::DynamicImage::ImageRgb32F(image::ImageBuffer::from_pixel(1, 1, image::Rgb([4.5f32, 0.25, 0.0])));
+  let data = get_texture_data(rgb32f, ColorSpace::Srgb);
+  assert_eq!(data.format, ash::vk::Format::R32G32B32A32_SFLOAT);
+  assert_eq!(data.bytes, [4.5f32, 0.25, 0.0, 1.0].iter().flat_map(|c| c.to_ne_bytes()).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_decode_16_bit_png() {
+  let source = image::DynamicImage::ImageRgba16(image::ImageBuffer::from_pixel(2, 1, image::Rgba([1u16, 2, 3, 4])));
+  let mut png = std::io::Cursor::new(vec![]);
+  source.write_to(&mut png, image::ImageFormat::Png).expect("failed to encode png");
+  let data = decode_texture_bytes(png.get_ref(), ColorSpace::Srgb).expect("failed to decode png");
+  assert_eq!(data.format, ash::vk::Format::R16G16B16A16_UNORM);
+  assert_eq!((data.extent.width, data.extent.height), (2, 1));
+  assert_eq!(&data.bytes[0..8], [1u16, 2, 3, 4].iter().flat_map(|c| c.to_ne_bytes()).collect::<Vec<_>>().as_slice());
+}
+
+#[test]
+fn test_format_features_for_usage() {
+  let features = get_format_features_for_usage(upload::UPLOAD_IMAGE_USAGE);
+  assert_eq!(features, ash::vk::FormatFeatureFlags::TRANSFER_SRC | ash::vk::FormatFeatureFlags::TRANSFER_DST | ash::vk::FormatFeatureFlags::SAMPLED_IMAGE);
+  assert!(!get_format_features_for_usage(ash::vk::ImageUsageFlags::COLOR_ATTACHMENT).contains(ash::vk::FormatFeatureFlags::SAMPLED_IMAGE));
+}
@@ -0,0 +1,209 @@
+use crate::{block_formats, error::{GfxError, GfxResult}, mipmaps, texture::{ColorSpace, TextureData}};
+
+pub static KTX2_IDENTIFIER: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+pub static DDS_MAGIC: [u8; 4] = *b"DDS ";
+
+/// by magic number
+pub fn get_if_container(bytes: &[u8]) -> bool {
+  bytes.starts_with(&KTX2_IDENTIFIER) || bytes.starts_with(&DDS_MAGIC)
+}
+
+/// KTX2 or DDS, by magic number. color_space only matters for old DDS files, which don't say whether they are SRGB
+pub fn parse_container(bytes: &[u8], color_space: ColorSpace) -> GfxResult<TextureData> {
+  if bytes.starts_with(&KTX2_IDENTIFIER) { return parse_ktx2(bytes); }
+  if bytes.starts_with(&DDS_MAGIC) { return parse_dds(bytes, color_space); }
+  Err(invalid("not a KTX2 or DDS file"))
+}
+
+/// a single 2D image with its mip chain. basis universal and supercompressed files, arrays, cubemaps and volumes aren't supported
+pub fn parse_ktx2(bytes: &[u8]) -> GfxResult<TextureData> {
+  if !bytes.starts_with(&KTX2_IDENTIFIER) { return Err(invalid("missing the KTX2 identifier")); }
+  let format = ash::vk::Format::from_raw(read_u32(bytes, 12)? as i32);
+  let width = read_u32(bytes, 20)?;
+  let height = read_u32(bytes, 24)?.max(1);
+  let depth = read_u32(bytes, 28)?;
+  let layer_count = read_u32(bytes, 32)?;
+  let face_count = read_u32(bytes, 36)?;
+  // 0 asks the loader to generate mips, which we leave to the caller
+  let mip_levels = read_u32(bytes, 40)?.max(1);
+  let supercompression_scheme = read_u32(bytes, 44)?;
+
+  if format == ash::vk::Format::UNDEFINED { return Err(invalid("basis universal KTX2 files need transcoding")); }
+  if supercompression_scheme != 0 { return Err(invalid(&format!("supercompression scheme {} is not supported", supercompression_scheme))); }
+  if depth > 1 || layer_count > 1 || face_count != 1 { return Err(invalid("only single 2D images are supported")); }
+
+  // level index straight after the header, largest level first. each level can live anywhere in the file
+  let extent = ash::vk::Extent3D::default().width(width).height(height).depth(1);
+  check_extent_and_mip_levels(&extent, mip_levels)?;
+  let mut levels = vec![];
+  for level in 0..mip_levels {
+    let entry = 80 + level as usize * 24;
+    let offset = read_u64(bytes, entry)?;
+    let length = read_u64(bytes, entry + 8)?;
+    let expected = get_level_size(&extent, format, level)?;
+    if length != expected { return Err(invalid(&format!("level {} is {} bytes, expected {}", level, length, expected))); }
+    levels.extend_from_slice(get_range(bytes, offset, length)?);
+  }
+  Ok(TextureData { bytes: levels, extent, format, mip_levels })
+}
+
+/// the common FourCC formats (DXT1 to DXT5, ATI1 and ATI2) and DX10 headers. cubemaps, arrays and volumes aren't supported
+pub fn parse_dds(bytes: &[u8], color_space: ColorSpace) -> GfxResult<TextureData> {
+  if !bytes.starts_with(&DDS_MAGIC) { return Err(invalid("missing the DDS magic number")); }
+  if read_u32(bytes, 4)? != 124 { return Err(invalid("wrong DDS header size")); }
+  let flags = read_u32(bytes, 8)?;
+  let height = read_u32(bytes, 12)?;
+  let width = read_u32(bytes, 16)?;
+  let has_mip_count = flags & 0x20000 != 0;
+  let mip_levels = if has_mip_count { read_u32(bytes, 28)?.max(1) } else { 1 };
+  let pixel_format_flags = read_u32(bytes, 80)?;
+  let four_cc = bytes.get(84..88).ok_or_else(|| invalid("truncated DDS header"))?;
+  let caps2 = read_u32(bytes, 112)?;
+  let is_volume = flags & 0x800000 != 0;
+  let is_cubemap = caps2 & 0x200 != 0;
+  if is_volume || is_cubemap { return Err(invalid("only single 2D images are supported")); }
+  if pixel_format_flags & 0x4 == 0 { return Err(invalid("uncompressed DDS files without a DX10 header are not supported")); }
+
+  let srgb = |unorm: ash::vk::Format, srgb: ash::vk::Format| if color_space == ColorSpace::Srgb { srgb } else { unorm };
+  let (format, data_offset) = match four_cc {
+    b"DX10" => {
+      let dxgi_format = read_u32(bytes, 128)?;
+      let array_size = read_u32(bytes, 140)?;
+      if array_size > 1 { return Err(invalid("only single 2D images are supported")); }
+      (get_format_from_dxgi(dxgi_format).ok_or_else(|| invalid(&format!("DXGI format {} is not supported", dxgi_format)))?, 148)
+    },
+    b"DXT1" => (srgb(ash::vk::Format::BC1_RGBA_UNORM_BLOCK, ash::vk::Format::BC1_RGBA_SRGB_BLOCK), 128),
+    b"DXT2" | b"DXT3" => (srgb(ash::vk::Format::BC2_UNORM_BLOCK, ash::vk::Format::BC2_SRGB_BLOCK), 128),
+    b"DXT4" | b"DXT5" => (srgb(ash::vk::Format::BC3_UNORM_BLOCK, ash::vk::Format::BC3_SRGB_BLOCK), 128),
+    b"ATI1" | b"BC4U" => (ash::vk::Format::BC4_UNORM_BLOCK, 128),
+    b"BC4S" => (ash::vk::Format::BC4_SNORM_BLOCK, 128),
+    b"ATI2" | b"BC5U" => (ash::vk::Format::BC5_UNORM_BLOCK, 128),
+    b"BC5S" => (ash::vk::Format::BC5_SNORM_BLOCK, 128),
+    _ => return Err(invalid(&format!("FourCC {:?} is not supported", String::from_utf8_lossy(four_cc)))),
+  };
+
+  // levels follow the header, largest first, tightly packed
+  let extent = ash::vk::Extent3D::default().width(width).height(height).depth(1);
+  check_extent_and_mip_levels(&extent, mip_levels)?;
+  let mut size = 0u64;
+  for level in 0..mip_levels { size = size.checked_add(get_level_size(&extent, format, level)?).ok_or_else(|| invalid("levels too large"))?; }
+  let levels = get_range(bytes, data_offset, size)?;
+  Ok(TextureData { bytes: levels.to_vec(), extent, format, mip_levels })
+}
+
+/// the DXGI_FORMAT values a DX10 header can name that we know how to upload
+fn get_format_from_dxgi(dxgi_format: u32) -> Option<ash::vk::Format> {
+  use ash::vk::Format as F;
+  match dxgi_format {
+    2 => Some(F::R32G32B32A32_SFLOAT),
+    10 => Some(F::R16G16B16A16_SFLOAT),
+    11 => Some(F::R16G16B16A16_UNORM),
+    28 => Some(F::R8G8B8A8_UNORM),
+    29 => Some(F::R8G8B8A8_SRGB),
+    71 => Some(F::BC1_RGBA_UNORM_BLOCK),
+    72 => Some(F::BC1_RGBA_SRGB_BLOCK),
+    74 => Some(F::BC2_UNORM_BLOCK),
+    75 => Some(F::BC2_SRGB_BLOCK),
+    77 => Some(F::BC3_UNORM_BLOCK),
+    78 => Some(F::BC3_SRGB_BLOCK),
+    80 => Some(F::BC4_UNORM_BLOCK),
+    81 => Some(F::BC4_SNORM_BLOCK),
+    83 => Some(F::BC5_UNORM_BLOCK),
+    84 => Some(F::BC5_SNORM_BLOCK),
+    87 => Some(F::B8G8R8A8_UNORM),
+    91 => Some(F::B8G8R8A8_SRGB),
+    95 => Some(F::BC6H_UFLOAT_BLOCK),
+    96 => Some(F::BC6H_SFLOAT_BLOCK),
+    98 => Some(F::BC7_UNORM_BLOCK),
+    99 => Some(F::BC7_SRGB_BLOCK),
+    _ => None,
+  }
+}
+
+/// headers are untrusted, a bad one must not get as far as get_mip_extent
+fn check_extent_and_mip_levels(extent: &ash::vk::Extent3D, mip_levels: u32) -> GfxResult<()> {
+  if extent.width == 0 || extent.height == 0 { return Err(invalid(&format!("empty {}x{} image", extent.width, extent.height))); }
+  let max_mip_levels = mipmaps::get_mip_level_count(extent);
+  if mip_levels > max_mip_levels { return Err(invalid(&format!("{} mip levels, a {}x{} image has at most {}", mip_levels, extent.width, extent.height, max_mip_levels))); }
+  Ok(())
+}
+
+fn get_level_size(extent: &ash::vk::Extent3D, format: ash::vk::Format, level: u32) -> GfxResult<u64> {
+  if block_formats::get_format_block_info(format).is_none() { return Err(GfxError::UnsupportedFormat(format)); }
+  block_formats::get_level_size(&mipmaps::get_mip_extent(extent, level), format).ok_or_else(|| invalid(&format!("level {} is too large", level)))
+}
+
+/// length bytes from offset, both straight from the file
+fn get_range(bytes: &[u8], offset: u64, length: u64) -> GfxResult<&[u8]> {
+  let end = offset.checked_add(length).ok_or_else(|| invalid("level data past the end of the file"))?;
+  let range = usize::try_from(offset).ok().zip(usize::try_from(end).ok()).map(|(start, end)| start..end);
+  range.and_then(|range| bytes.get(range)).ok_or_else(|| invalid("level data past the end of the file"))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> GfxResult<u32> {
+  let field = bytes.get(offset..offset + 4).ok_or_else(|| invalid("truncated header"))?;
+  Ok(u32::from_le_bytes(field.try_into().expect("4 bytes")))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> GfxResult<u64> {
+  let field = bytes.get(offset..offset + 8).ok_or_else(|| invalid("truncated header"))?;
+  Ok(u64::from_le_bytes(field.try_into().expect("8 bytes")))
+}
+
+fn invalid(reason: &str) -> GfxError {
+  GfxError::InvalidTextureFile(reason.to_string())
+}
+
+#[test]
+fn test_parse_dds() {
+  // 8x8 DXT1 with three levels: 4 blocks, 1 block, 1 block
+  let data = (0..6 * 8).map(|i| i as u8).collect::<Vec<_>>();
+  let mut dds = DDS_MAGIC.to_vec();
+  // header size, flags, height, width, pitch, depth, mip count
+  for value in [124, 0x1 | 0x2 | 0x4 | 0x1000 | 0x20000, 8, 8, 0, 0, 3u32] { dds.extend(value.to_le_bytes()); }
+  dds.resize(76, 0); // reserved
+  // pixel format size and flags, then the four cc
+  for value in [32, 0x4u32] { dds.extend(value.to_le_bytes()); }
+  dds.extend(b"DXT1");
+  dds.resize(128, 0); // masks and caps
+  dds.extend(&data);
+
+  let texture = parse_dds(&dds, ColorSpace::Srgb).expect("valid dds");
+  assert_eq!(texture.format, ash::vk::Format::BC1_RGBA_SRGB_BLOCK);
+  assert_eq!(texture.mip_levels, 3);
+  assert_eq!(texture.bytes, data);
+  assert_eq!(parse_dds(&dds, ColorSpace::Linear).expect("valid dds").format, ash::vk::Format::BC1_RGBA_UNORM_BLOCK);
+
+  // one level short
+  let truncated = &dds[..dds.len() - 8];
+  assert!(matches!(parse_dds(truncated, ColorSpace::Srgb), Err(GfxError::InvalidTextureFile(_))));
+
+  // an 8x8 image has 4 levels at most, and a width of 0 none
+  let mut too_many_levels = dds.clone();
+  too_many_levels[28..32].copy_from_slice(&33u32.to_le_bytes());
+  assert!(matches!(parse_dds(&too_many_levels, ColorSpace::Srgb), Err(GfxError::InvalidTextureFile(_))));
+  let mut empty = dds.clone();
+  empty[16..20].copy_from_slice(&0u32.to_le_bytes());
+  assert!(matches!(parse_dds(&empty, ColorSpace::Srgb), Err(GfxError::InvalidTextureFile(_))));
+}
+
+#[test]
+fn test_parse_ktx2() {
+  // 4x4 BC7 with two levels, stored smallest first like most writers do
+  let (level0, level1) = (vec![0xAAu8; 16], vec![0xBBu8; 16]);
+  let mut ktx2 = KTX2_IDENTIFIER.to_vec();
+  for value in [ash::vk::Format::BC7_SRGB_BLOCK.as_raw() as u32, 1, 4, 4, 0, 0, 1, 2, 0] { ktx2.extend(value.to_le_bytes()); }
+  ktx2.resize(80, 0); // unused dfd, kvd and sgd index
+  let data_start = 80 + 2 * 24;
+  for (offset, length) in [(data_start + 16, 16), (data_start, 16)] {
+    for value in [offset as u64, length as u64, length as u64] { ktx2.extend(value.to_le_bytes()); }
+  }
+  ktx2.extend(&level1);
+  ktx2.extend(&level0);
+
+  let texture = parse_container(&ktx2, ColorSpace::Linear).expect("valid ktx2");
+  assert_eq!(texture.format, ash::vk::Format::BC7_SRGB_BLOCK);
+  assert_eq!(texture.extent, ash::vk::Extent3D::default().width(4).height(4).depth(1));
+  assert_eq!(texture.mip_levels, 2);
+  assert_eq!(texture.bytes, [level0, level1].concat());
+}
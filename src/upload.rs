@@ -0,0 +1,257 @@
+use crate::{block_formats, constants::MemoryIntent, device_context::SharedDevice, create_buffer, create_command_buffer, create_image_with_usage, error::{GfxError, GfxResult}, image_state::ImageUsage, mipmaps, queues::{self, GfxQueue}, resources, submit_with_semaphores};
+
+/// what uploaded images are created with. nothing more, compressed formats can't be attachments or storage images
+pub const UPLOAD_IMAGE_USAGE: ash::vk::ImageUsageFlags = ash::vk::ImageUsageFlags::from_raw(
+  ash::vk::ImageUsageFlags::TRANSFER_SRC.as_raw() | ash::vk::ImageUsageFlags::TRANSFER_DST.as_raw() | ash::vk::ImageUsageFlags::SAMPLED.as_raw()
+);
+
+/// one image waiting in an UploadBatch
+pub struct PendingImageUpload<'a> {
+  /// the first provided_mip_levels levels, tightly packed one after another
+  pub bytes: &'a [u8],
+  pub extent: ash::vk::Extent3D,
+  pub format: ash::vk::Format,
+  pub mip_levels: u32,
+  /// the levels after these are blitted from the one before on the main queue
+  pub provided_mip_levels: u32,
+}
+
+/// collects image uploads so they share one staging buffer and one submission.
+/// every image comes out DEVICE_LOCAL, OPTIMAL, owned by the main queue's family and in TRANSFER_SRC_OPTIMAL
+#[derive(Default)]
+pub struct UploadBatch<'a> {
+  pub images: Vec<PendingImageUpload<'a>>,
+}
+
+impl<'a> UploadBatch<'a> {
+  pub fn new() -> Self {
+    UploadBatch { images: vec![] }
+  }
+
+  /// bytes are tightly packed texels, or blocks for compressed formats. returns where the image will be in what submit returns
+  pub fn add_image(&mut self, bytes: &'a [u8], extent: &ash::vk::Extent3D, format: &ash::vk::Format) -> usize {
+    self.add_image_with_mip_levels(bytes, extent, format, 1, 1)
+  }
+
+  /// bytes hold the first provided_mip_levels levels, the rest are generated with blits.
+  /// the format must pass mipmaps::get_if_format_supports_mip_blits unless every level is provided.
+  /// submit checks the levels and bytes add up
+  pub fn add_image_with_mip_levels(&mut self, bytes: &'a [u8], extent: &ash::vk::Extent3D, format: &ash::vk::Format, mip_levels: u32, provided_mip_levels: u32) -> usize {
+    self.images.push(PendingImageUpload { bytes, extent: *extent, format: *format, mip_levels, provided_mip_levels });
+    self.images.len() - 1
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.images.is_empty()
+  }
+
+  /// copies on the transfer queue, then hands the images over to the main queue if that is a different family
+  /// and blits any missing mip levels there. blocks until done. every level is left in TRANSFER_SRC_OPTIMAL, and tracked as such.
+  /// fails with InvalidUpload before anything is created if an image's mip levels or bytes don't add up
+  pub fn submit(
+    &self,
+    instance: &ash::Instance,
+    physical_device: &ash::vk::PhysicalDevice,
+    device: &SharedDevice,
+    transfer_queue: &GfxQueue,
+    main_queue: &GfxQueue,
+  ) -> GfxResult<Vec<resources::Image>> {
+    if self.is_empty() { return Ok(vec![]); }
+
+    // one staging buffer for everything, each level placed separately so every copy starts aligned
+    let properties = unsafe { instance.get_physical_device_properties(*physical_device) };
+    let mut sizes = vec![];
+    let mut level_sources = vec![]; // (upload index, level, range in the upload's bytes)
+    for (i, upload) in self.images.iter().enumerate() {
+      let block = block_formats::get_format_block_info(upload.format).ok_or(GfxError::UnsupportedFormat(upload.format))?;
+      if upload.provided_mip_levels < 1 || upload.provided_mip_levels > upload.mip_levels || upload.mip_levels > mipmaps::get_mip_level_count(&upload.extent) {
+        return Err(GfxError::InvalidUpload(format!("{} of {} mip levels provided for a {:?} image", upload.provided_mip_levels, upload.mip_levels, upload.extent)));
+      }
+      let invalid = || GfxError::InvalidUpload(format!("{} bytes don't hold exactly {} levels of a {:?} {:?} image", upload.bytes.len(), upload.provided_mip_levels, upload.extent, upload.format));
+      let mut start = 0u64;
+      for level in 0..upload.provided_mip_levels {
+        let mip_extent = mipmaps::get_mip_extent(&upload.extent, level);
+        let size = block_formats::get_level_size(&mip_extent, upload.format).ok_or_else(invalid)?;
+        let end = start.checked_add(size).filter(|end| *end <= upload.bytes.len() as u64).ok_or_else(invalid)?;
+        sizes.push((size, block.bytes));
+        level_sources.push((i, level, start as usize..end as usize));
+        start = end;
+      }
+      if upload.bytes.len() as u64 != start { return Err(invalid()); }
+    }
+    let (offsets, buffer_size) = get_staging_offsets(&sizes, properties.limits.optimal_buffer_copy_offset_alignment);
+    let staging_buffer = resources::Buffer::from_handle(device, create_buffer(device, buffer_size)?, buffer_size, MemoryIntent::CpuToGpu)?;
+    let mut staging_bytes = vec![0u8; buffer_size as usize];
+    for ((i, _, range), offset) in level_sources.iter().zip(offsets.iter()) {
+      let offset = *offset as usize;
+      staging_bytes[offset..offset + range.len()].copy_from_slice(&self.images[*i].bytes[range.clone()]);
+    }
+    staging_buffer.allocation().write_bytes(&staging_bytes)?;
+
+    // the images, only ever written by the copies and blits
+    let mut images = vec![];
+    for upload in self.images.iter() {
+      let (image, format) = create_image_with_usage(device, main_queue.family_index, &upload.extent, &upload.format, ash::vk::ImageTiling::OPTIMAL, upload.mip_levels, UPLOAD_IMAGE_USAGE)?;
+      images.push(resources::Image::from_handle_with_mip_levels(device, image, &upload.extent, &format, ash::vk::ImageTiling::OPTIMAL, MemoryIntent::GpuOnly, upload.mip_levels)?);
+    }
+
+    let get_subresource_range = |upload: &PendingImageUpload| ash::vk::ImageSubresourceRange::default()
+      .aspect_mask(ash::vk::ImageAspectFlags::COLOR)
+      .base_mip_level(0)
+      .level_count(upload.mip_levels)
+      .base_array_layer(0)
+      .layer_count(1);
+    let needs_ownership_transfer = transfer_queue.family_index != main_queue.family_index;
+    let needs_blits = self.images.iter().any(|upload| upload.provided_mip_levels < upload.mip_levels);
+    let to_transfer_dst = self.images.iter().zip(images.iter()).map(|(upload, image)| {
+      ash::vk::ImageMemoryBarrier::default()
+        .old_layout(ash::vk::ImageLayout::UNDEFINED)
+        .new_layout(ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+        .src_access_mask(ash::vk::AccessFlags::empty())
+        .dst_access_mask(ash::vk::AccessFlags::TRANSFER_WRITE)
+        .src_queue_family_index(ash::vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(ash::vk::QUEUE_FAMILY_IGNORED)
+        .image(**image)
+        .subresource_range(get_subresource_range(upload))
+    }).collect::<Vec<_>>();
+    // images still missing levels stay in TRANSFER_DST_OPTIMAL for the blits, which leave them in TRANSFER_SRC_OPTIMAL
+    let (releases, acquires): (Vec<_>, Vec<_>) = self.images.iter().zip(images.iter()).map(|(upload, image)| {
+      let new_layout = match upload.provided_mip_levels < upload.mip_levels {
+        true => ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        false => ash::vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+      };
+      queues::get_image_ownership_transfer_barriers(
+        **image, get_subresource_range(upload), ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL, new_layout,
+        transfer_queue.family_index, main_queue.family_index, ash::vk::AccessFlags::TRANSFER_WRITE, ash::vk::AccessFlags::TRANSFER_READ | ash::vk::AccessFlags::TRANSFER_WRITE,
+      )
+    }).unzip();
+
+    // copy on the transfer queue
+    let command_buffer = create_command_buffer(device, &transfer_queue.command_pool)?;
+    let begin_create_info = ash::vk::CommandBufferBeginInfo::default()
+      .flags(ash::vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    unsafe {
+      device
+      .begin_command_buffer(*command_buffer, &begin_create_info)?;
+
+      // UNDEFINED -> TRANSFER_DST_OPTIMAL, nothing in the images yet
+      device.cmd_pipeline_barrier(*command_buffer, ash::vk::PipelineStageFlags::TOP_OF_PIPE, ash::vk::PipelineStageFlags::TRANSFER, ash::vk::DependencyFlags::empty(), &[], &[], &to_transfer_dst);
+
+      for ((i, level, _), offset) in level_sources.iter().zip(offsets.iter()) {
+        let region = ash::vk::BufferImageCopy::default()
+          .buffer_offset(*offset)
+          .buffer_row_length(0) // tightly packed
+          .buffer_image_height(0)
+          .image_subresource(ash::vk::ImageSubresourceLayers::default()
+            .aspect_mask(ash::vk::ImageAspectFlags::COLOR)
+            .mip_level(*level)
+            .base_array_layer(0)
+            .layer_count(1)
+          )
+          .image_offset(ash::vk::Offset3D::default())
+          .image_extent(mipmaps::get_mip_extent(&self.images[*i].extent, *level));
+        device.cmd_copy_buffer_to_image(*command_buffer, *staging_buffer, *images[*i], ash::vk::ImageLayout::TRANSFER_DST_OPTIMAL, &[region]);
+      }
+
+      // TRANSFER_DST_OPTIMAL -> TRANSFER_SRC_OPTIMAL. also releases the images to the main family if that is a different one
+      match needs_ownership_transfer {
+        true => device.cmd_pipeline_barrier(*command_buffer, ash::vk::PipelineStageFlags::TRANSFER, ash::vk::PipelineStageFlags::BOTTOM_OF_PIPE, ash::vk::DependencyFlags::empty(), &[], &[], &releases),
+        false => device.cmd_pipeline_barrier(*command_buffer, ash::vk::PipelineStageFlags::TRANSFER, ash::vk::PipelineStageFlags::TRANSFER, ash::vk::DependencyFlags::empty(), &[], &[], &acquires),
+      }
+
+      device
+      .end_command_buffer(*command_buffer)?;
+    };
+    // the main queue has work when it has to acquire or blit. it may be a different queue even in the same family
+    let needs_main_queue = needs_ownership_transfer || needs_blits;
+    let semaphore = match needs_main_queue {
+      true => Some(resources::Semaphore::new(device)?),
+      false => None,
+    };
+    let signal_semaphores = semaphore.iter().map(|semaphore| **semaphore).collect::<Vec<_>>();
+    let fence = submit_with_semaphores(device, &transfer_queue.queue, &command_buffer, &[], &signal_semaphores)?;
+
+    // acquire and blit on the main queue, once the copies have happened
+    let main_command_buffer_and_fence = match semaphore.as_ref() {
+      None => None,
+      Some(semaphore) => {
+        let main_command_buffer = create_command_buffer(device, &main_queue.command_pool)?;
+        unsafe {
+          device.begin_command_buffer(*main_command_buffer, &begin_create_info)?;
+          if needs_ownership_transfer {
+            device.cmd_pipeline_barrier(*main_command_buffer, ash::vk::PipelineStageFlags::TOP_OF_PIPE, ash::vk::PipelineStageFlags::TRANSFER, ash::vk::DependencyFlags::empty(), &[], &[], &acquires);
+          }
+          for (upload, image) in self.images.iter().zip(images.iter()) {
+            if upload.provided_mip_levels == upload.mip_levels { continue; }
+            mipmaps::record_mip_chain_blits(device, &main_command_buffer, image, &upload.extent, upload.provided_mip_levels, upload.mip_levels);
+          }
+          device.end_command_buffer(*main_command_buffer)?;
+        }
+        let main_fence = submit_with_semaphores(device, &main_queue.queue, &main_command_buffer, &[(**semaphore, ash::vk::PipelineStageFlags::TRANSFER)], &[])?;
+        Some((main_command_buffer, main_fence))
+      },
+    };
+
+    // await for fences. the staging buffer goes away after this
+    let timeout_ms = 9999;
+    let timeout_ns = timeout_ms * 1000 * 1000;
+    fence.wait(timeout_ns)?;
+    if let Some((main_command_buffer, main_fence)) = main_command_buffer_and_fence {
+      main_fence.wait(timeout_ns)?;
+    }
+    for image in images.iter() { image.state().set_levels(Some(ImageUsage::TransferSrc), 0, image.mip_levels()); }
+    Ok(images)
+  }
+}
+
+/// bytes per texel for the uncompressed formats we upload
+pub fn get_format_texel_size(format: ash::vk::Format) -> Option<u64> {
+  match format {
+    ash::vk::Format::R8_UNORM | ash::vk::Format::R8_SRGB => Some(1),
+    ash::vk::Format::R8G8B8A8_UNORM | ash::vk::Format::R8G8B8A8_SRGB | ash::vk::Format::B8G8R8A8_UNORM | ash::vk::Format::B8G8R8A8_SRGB => Some(4),
+    ash::vk::Format::R16G16B16A16_UNORM | ash::vk::Format::R16G16B16A16_SFLOAT => Some(8),
+    ash::vk::Format::R32G32B32A32_SFLOAT => Some(16),
+    _ => None,
+  }
+}
+
+/// where each upload goes in the staging buffer, and how big the buffer is. sizes are (bytes, texel_size), use the block size for compressed formats.
+/// offsets have to be a multiple of the texel size, and of 4 when copying on a transfer only queue
+pub fn get_staging_offsets(sizes: &[(u64, u64)], optimal_alignment: u64) -> (Vec<u64>, u64) {
+  let mut offsets = vec![];
+  let mut end = 0u64;
+  for (size, texel_size) in sizes.iter() {
+    let alignment = get_lcm(get_lcm(*texel_size, 4), optimal_alignment.max(1));
+    // not always a power of two, 3 channel formats have 3, 6 or 12 byte texels
+    let offset = end.div_ceil(alignment) * alignment;
+    offsets.push(offset);
+    end = offset + size;
+  }
+  (offsets, end)
+}
+
+fn get_lcm(a: u64, b: u64) -> u64 {
+  let gcd = |mut a: u64, mut b: u64| { while b != 0 { (a, b) = (b, a % b); } a };
+  a / gcd(a, b) * b
+}
+
+#[test]
+fn test_staging_offsets() {
+  // 4 byte texels, 12 bytes each
+  let (offsets, size) = get_staging_offsets(&[(12, 4), (12, 4), (12, 4)], 1);
+  assert_eq!(offsets, vec![0, 12, 24]);
+  assert_eq!(size, 36);
+
+  // an odd sized single channel image pushes the next one to a multiple of 4
+  let (offsets, size) = get_staging_offsets(&[(3, 1), (16, 16), (8, 8)], 1);
+  assert_eq!(offsets, vec![0, 16, 32]);
+  assert_eq!(size, 40);
+
+  // the device's preferred alignment wins when it is bigger
+  let (offsets, _) = get_staging_offsets(&[(4, 4), (4, 4)], 64);
+  assert_eq!(offsets, vec![0, 64]);
+
+  // 12 byte texels need a multiple of 12 and of 4
+  let (offsets, _) = get_staging_offsets(&[(4, 4), (12, 12)], 1);
+  assert_eq!(offsets, vec![0, 12]);
+}